@@ -0,0 +1,74 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use rand::Rng;
+
+/// How a failed reconcile should be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Retry with an exponential backoff starting at `base` and capped at `max`
+    Backoff { base: Duration, max: Duration },
+    /// Do not retry until the object changes
+    Never,
+}
+
+impl Retry {
+    /// A backoff between `base` and `max` seconds
+    pub const fn backoff(base: u64, max: u64) -> Self {
+        Self::Backoff {
+            base: Duration::from_secs(base),
+            max: Duration::from_secs(max),
+        }
+    }
+}
+
+/// Per-object exponential backoff with jitter.
+///
+/// Objects are identified by a key, usually their `ObjectRef`, and their failure count
+/// is reset once they reconcile successfully.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl Backoff {
+    /// Records a failure of `key` and returns how long to wait before retrying it
+    pub fn next_delay(&self, key: &str, base: Duration, max: Duration) -> Duration {
+        let mut failures = self.failures.lock().unwrap();
+        let attempt = failures.entry(key.to_string()).or_default();
+        let delay = base.saturating_mul(2u32.saturating_pow(*attempt)).min(max);
+        *attempt = attempt.saturating_add(1);
+
+        // equal jitter: keep half of the delay and randomize the other half
+        let half = delay / 2;
+        half + half.mul_f64(rand::rng().random::<f64>())
+    }
+
+    /// Forgets the failures of `key`
+    pub fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_delay_grows_and_resets() {
+        let backoff = Backoff::default();
+        let base = Duration::from_secs(2);
+        let max = Duration::from_secs(10);
+
+        for expected in [2, 4, 8, 10, 10] {
+            let delay = backoff.next_delay("ns/test", base, max);
+            let expected = Duration::from_secs(expected);
+            assert!(
+                delay >= expected / 2 && delay <= expected,
+                "{delay:?} not within jitter of {expected:?}"
+            );
+        }
+
+        backoff.reset("ns/test");
+        assert!(backoff.next_delay("ns/test", base, max) <= base);
+    }
+}
@@ -0,0 +1,482 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use chrono::TimeDelta;
+use clap::Parser;
+use serde::Deserialize;
+use snafu::{ResultExt, Whatever};
+use validator::{Validate, ValidationError};
+use validator_derive::Validate;
+
+use crate::{
+    leader_election::LeaderElectionConfig,
+    scope::{Namespaces, WatchScope},
+    telemetry::{LogFormat, OtlpProtocol, SamplerKind, TelemetryConfig},
+};
+
+/// Command line flags of the operator.
+///
+/// Every flag can also be set with its environment variable, and overrides the value
+/// of the configuration file.
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path of the YAML configuration file
+    #[arg(long, env = "PROBELET_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address of the health, metrics and diagnostics server
+    #[arg(long, env = "BIND_ADDRESS")]
+    pub bind_address: Option<SocketAddr>,
+    /// Maximum number of concurrent reconciles per controller, 0 for unlimited
+    #[arg(long, env = "CONTROLLER_CONCURRENCY")]
+    pub concurrency: Option<u16>,
+    /// Interval between two reconciles of an unchanged object
+    #[arg(long, env = "REQUEUE_INTERVAL_SECONDS")]
+    pub requeue_interval_seconds: Option<u64>,
+    /// Maximum delay before retrying a failed reconcile
+    #[arg(long, env = "MAX_BACKOFF_SECONDS")]
+    pub max_backoff_seconds: Option<u64>,
+    /// How long reconciles can stop before the operator is considered wedged
+    #[arg(long, env = "LIVENESS_THRESHOLD_SECONDS")]
+    pub liveness_threshold_seconds: Option<u64>,
+    /// Grace period of the worker pods to stop, set as their `terminationGracePeriodSeconds`
+    /// and used when deleting them
+    #[arg(long, env = "DELETION_GRACE_PERIOD_SECONDS")]
+    pub deletion_grace_period_seconds: Option<u32>,
+    /// Image of the workers of the `WorkerGroup`s that do not set one
+    #[arg(long, env = "WORKER_IMAGE")]
+    pub worker_image: Option<String>,
+    /// `ClusterRole` bound to the service account of the workers
+    #[arg(long, env = "WORKER_CLUSTER_ROLE")]
+    pub worker_cluster_role: Option<String>,
+    /// Comma separated namespaces to watch, all namespaces when unset
+    #[arg(long, env = "WATCH_NAMESPACES", value_delimiter = ',')]
+    pub watch_namespaces: Vec<String>,
+    /// Label selector of the namespaces to watch
+    #[arg(long, env = "WATCH_NAMESPACE_SELECTOR")]
+    pub watch_namespace_selector: Option<String>,
+    /// Label selector of the `WorkerGroup`s to reconcile
+    #[arg(long, env = "WATCH_WORKER_GROUP_SELECTOR")]
+    pub watch_worker_group_selector: Option<String>,
+    /// Format of the logs
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// OTLP endpoint the traces are exported to, tracing is disabled when unset
+    #[arg(long, env = "OPENTELEMETRY_ENDPOINT_URL")]
+    pub telemetry_endpoint: Option<String>,
+    /// Transport of the OTLP exporter, `OTEL_EXPORTER_OTLP_PROTOCOL` by default
+    #[arg(long, value_enum)]
+    pub otlp_protocol: Option<OtlpProtocol>,
+    /// Sampler of the traces, `OTEL_TRACES_SAMPLER` by default
+    #[arg(long, value_enum)]
+    pub trace_sampler: Option<SamplerKind>,
+    /// Ratio of the sampled traces, `OTEL_TRACES_SAMPLER_ARG` by default
+    #[arg(long)]
+    pub trace_sampler_ratio: Option<f64>,
+    /// Run the controllers only on the replica holding the leader lease
+    #[arg(long, env = "LEADER_ELECTION_ENABLED")]
+    pub leader_election: Option<bool>,
+    /// Name of the leader `Lease`
+    #[arg(long, env = "LEADER_ELECTION_LEASE_NAME")]
+    pub lease_name: Option<String>,
+    /// Namespace of the leader `Lease`
+    #[arg(long, env = "POD_NAMESPACE")]
+    pub lease_namespace: Option<String>,
+    /// Identity of this replica in the leader `Lease`
+    #[arg(long, env = "POD_NAME")]
+    pub identity: Option<String>,
+    #[arg(long, env = "LEADER_ELECTION_LEASE_DURATION_SECONDS")]
+    pub lease_duration_seconds: Option<u64>,
+    #[arg(long, env = "LEADER_ELECTION_RENEW_DEADLINE_SECONDS")]
+    pub renew_deadline_seconds: Option<u64>,
+    #[arg(long, env = "LEADER_ELECTION_RETRY_PERIOD_SECONDS")]
+    pub retry_period_seconds: Option<u64>,
+}
+
+/// Configuration of the operator
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct OperatorConfig {
+    #[validate(nested)]
+    pub server: ServerConfig,
+    #[validate(nested)]
+    pub controller: ControllerConfig,
+    #[validate(nested)]
+    pub watch: WatchConfig,
+    #[validate(nested)]
+    pub telemetry: TelemetryConfig,
+    #[validate(nested)]
+    pub leader_election: LeaderElectionConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address of the health, metrics and diagnostics server
+    pub bind_address: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 8080)),
+        }
+    }
+}
+
+/// How much longer than the wait between two reconciles of an object the default liveness
+/// threshold is
+const LIVENESS_MARGIN_SECONDS: u64 = 5 * 60;
+
+/// The longest requeue interval and backoff, so deadlines stay far from overflowing
+const MAX_DELAY_SECONDS: u64 = 24 * 60 * 60;
+
+/// The longest liveness threshold, a week
+const MAX_LIVENESS_THRESHOLD_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// Tuning of the controllers
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+#[validate(schema(function = "validate_controller"))]
+pub struct ControllerConfig {
+    /// Maximum number of concurrent reconciles per controller, 0 for unlimited
+    pub concurrency: u16,
+    /// Interval between two reconciles of an unchanged object, at most a day
+    #[validate(range(min = 1, max = MAX_DELAY_SECONDS))]
+    pub requeue_interval_seconds: u64,
+    /// Maximum delay before retrying a failed reconcile, caps the backoff of every error,
+    /// at most a day
+    #[validate(range(min = 1, max = MAX_DELAY_SECONDS))]
+    pub max_backoff_seconds: u64,
+    /// How long reconciles can stop, or a watcher fail, before `/livez` fails. Defaults to
+    /// the longest of `requeueIntervalSeconds` and `maxBackoffSeconds`, plus 5 minutes, as
+    /// an object waits that long between two reconciles. When set, it must be longer than
+    /// both.
+    #[validate(range(min = 1, max = MAX_LIVENESS_THRESHOLD_SECONDS))]
+    pub liveness_threshold_seconds: Option<u64>,
+    /// Grace period of the worker pods to stop, set as their `terminationGracePeriodSeconds`
+    /// and used when deleting them
+    pub deletion_grace_period_seconds: u32,
+    /// Image of the workers of the `WorkerGroup`s that do not set one
+    #[validate(length(min = 1))]
+    pub worker_image: String,
+    /// `ClusterRole` granting the workers read access to their probes, bound to the
+    /// service account created for each `WorkerGroup`
+    #[validate(length(min = 1))]
+    pub worker_cluster_role: String,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 0,
+            requeue_interval_seconds: 5 * 60,
+            max_backoff_seconds: 30 * 60,
+            liveness_threshold_seconds: None,
+            deletion_grace_period_seconds: 30,
+            worker_image: concat!(
+                "ghcr.io/mmoreiradj/probelet/operator:",
+                env!("CARGO_PKG_VERSION")
+            )
+            .to_string(),
+            worker_cluster_role: "probelet-worker".to_string(),
+        }
+    }
+}
+
+fn validate_controller(config: &ControllerConfig) -> Result<(), ValidationError> {
+    if let Some(threshold) = config.liveness_threshold_seconds
+        && threshold
+            <= config
+                .requeue_interval_seconds
+                .max(config.max_backoff_seconds)
+    {
+        return Err(ValidationError::new(
+            "livenessThresholdSeconds must be longer than requeueIntervalSeconds and \
+             maxBackoffSeconds",
+        ));
+    }
+    Ok(())
+}
+
+impl ControllerConfig {
+    pub fn requeue_interval(&self) -> Duration {
+        Duration::from_secs(self.requeue_interval_seconds)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_secs(self.max_backoff_seconds)
+    }
+
+    /// How long reconciles can stop before the operator is considered wedged
+    pub fn liveness_threshold(&self) -> TimeDelta {
+        let seconds = self.liveness_threshold_seconds.unwrap_or_else(|| {
+            self.requeue_interval_seconds.max(self.max_backoff_seconds) + LIVENESS_MARGIN_SECONDS
+        });
+        TimeDelta::try_seconds(i64::try_from(seconds).unwrap_or(i64::MAX)).unwrap_or(TimeDelta::MAX)
+    }
+}
+
+/// Which namespaces and objects the controllers watch
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+#[validate(schema(function = "validate_watch"))]
+pub struct WatchConfig {
+    /// Namespaces to watch, all namespaces when empty
+    pub namespaces: Vec<String>,
+    /// Label selector of the namespaces to watch
+    pub namespace_selector: Option<String>,
+    /// Label selector of the `WorkerGroup`s to reconcile
+    pub worker_group_selector: Option<String>,
+}
+
+fn validate_watch(config: &WatchConfig) -> Result<(), ValidationError> {
+    if !config.namespaces.is_empty() && config.namespace_selector.is_some() {
+        return Err(ValidationError::new(
+            "namespaces and namespaceSelector are mutually exclusive",
+        ));
+    }
+    Ok(())
+}
+
+impl WatchConfig {
+    pub fn scope(&self) -> WatchScope {
+        let namespaces = match (&self.namespaces[..], &self.namespace_selector) {
+            ([], Some(selector)) => Namespaces::Selector(selector.clone()),
+            ([], None) => Namespaces::All,
+            (namespaces, _) => Namespaces::List(namespaces.to_vec()),
+        };
+        WatchScope {
+            namespaces,
+            worker_group_selector: self.worker_group_selector.clone(),
+        }
+    }
+}
+
+impl OperatorConfig {
+    /// Loads the configuration file given on the command line, then applies the
+    /// `OTEL_*` environment variables and the flags
+    pub fn load(cli: Cli) -> Result<Self, Whatever> {
+        let mut config = match &cli.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).with_whatever_context(|_| {
+                    format!("Failed to read config file {}", path.display())
+                })?;
+                Self::from_yaml(&content)?
+            }
+            None => Self::default(),
+        };
+        config.telemetry.apply_env()?;
+        let config = config.with_overrides(cli);
+        config.validate().whatever_context("Invalid config")?;
+        Ok(config)
+    }
+
+    fn from_yaml(content: &str) -> Result<Self, Whatever> {
+        serde_yaml::from_str(content).whatever_context("Invalid config file")
+    }
+
+    fn with_overrides(mut self, cli: Cli) -> Self {
+        fn set<T>(value: &mut T, flag: Option<T>) {
+            if let Some(flag) = flag {
+                *value = flag;
+            }
+        }
+
+        set(&mut self.server.bind_address, cli.bind_address);
+
+        let controller = &mut self.controller;
+        set(&mut controller.concurrency, cli.concurrency);
+        set(
+            &mut controller.requeue_interval_seconds,
+            cli.requeue_interval_seconds,
+        );
+        set(&mut controller.max_backoff_seconds, cli.max_backoff_seconds);
+        if cli.liveness_threshold_seconds.is_some() {
+            controller.liveness_threshold_seconds = cli.liveness_threshold_seconds;
+        }
+        set(
+            &mut controller.deletion_grace_period_seconds,
+            cli.deletion_grace_period_seconds,
+        );
+        set(&mut controller.worker_image, cli.worker_image);
+        set(&mut controller.worker_cluster_role, cli.worker_cluster_role);
+
+        if !cli.watch_namespaces.is_empty() {
+            self.watch.namespaces = cli.watch_namespaces;
+            self.watch.namespace_selector = None;
+        }
+        if cli.watch_namespace_selector.is_some() {
+            self.watch.namespace_selector = cli.watch_namespace_selector;
+            self.watch.namespaces.clear();
+        }
+        if cli.watch_worker_group_selector.is_some() {
+            self.watch.worker_group_selector = cli.watch_worker_group_selector;
+        }
+
+        let telemetry = &mut self.telemetry;
+        set(&mut telemetry.log_format, cli.log_format);
+        if cli.telemetry_endpoint.is_some() {
+            telemetry.endpoint = cli.telemetry_endpoint;
+            telemetry.enabled = true;
+        }
+        set(&mut telemetry.protocol, cli.otlp_protocol);
+        set(&mut telemetry.sampler, cli.trace_sampler);
+        set(&mut telemetry.sampler_ratio, cli.trace_sampler_ratio);
+
+        let leader_election = &mut self.leader_election;
+        set(&mut leader_election.enabled, cli.leader_election);
+        set(&mut leader_election.lease_name, cli.lease_name);
+        set(&mut leader_election.lease_namespace, cli.lease_namespace);
+        set(&mut leader_election.identity, cli.identity);
+        set(
+            &mut leader_election.lease_duration_seconds,
+            cli.lease_duration_seconds,
+        );
+        set(
+            &mut leader_election.renew_deadline_seconds,
+            cli.renew_deadline_seconds,
+        );
+        set(
+            &mut leader_election.retry_period_seconds,
+            cli.retry_period_seconds,
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::health::Health;
+
+    const CONFIG: &str = r#"
+server:
+  bindAddress: 127.0.0.1:9090
+controller:
+  concurrency: 4
+  requeueIntervalSeconds: 60
+watch:
+  namespaces: [team-a, team-b]
+telemetry:
+  logFormat: json
+leaderElection:
+  enabled: true
+  leaseName: probelet
+"#;
+
+    #[test]
+    fn test_config_file() {
+        let config = OperatorConfig::from_yaml(CONFIG).unwrap();
+        assert_eq!(config.server.bind_address.port(), 9090);
+        assert_eq!(config.controller.concurrency, 4);
+        assert_eq!(
+            config.controller.requeue_interval(),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            config.controller.deletion_grace_period_seconds, 30,
+            "default"
+        );
+        assert_eq!(
+            config.watch.scope().namespaces,
+            Namespaces::List(vec!["team-a".to_string(), "team-b".to_string()])
+        );
+        assert_eq!(config.telemetry.log_format, LogFormat::Json);
+        assert!(config.leader_election.enabled);
+        assert_eq!(config.leader_election.lease_name, "probelet");
+        assert!(config.validate().is_ok());
+
+        assert!(OperatorConfig::from_yaml("server:\n  port: 80").is_err());
+    }
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let cli = Cli::try_parse_from([
+            "operator",
+            "--bind-address",
+            "0.0.0.0:8081",
+            "--watch-namespace-selector",
+            "probelet.dev/enabled=true",
+            "--telemetry-endpoint",
+            "http://localhost:4317",
+        ])
+        .unwrap();
+        let config = OperatorConfig::from_yaml(CONFIG)
+            .unwrap()
+            .with_overrides(cli);
+
+        assert_eq!(config.server.bind_address.port(), 8081);
+        assert_eq!(config.controller.concurrency, 4, "kept from the file");
+        assert_eq!(
+            config.watch.scope().namespaces,
+            Namespaces::Selector("probelet.dev/enabled=true".to_string())
+        );
+        assert!(config.telemetry.enabled);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let mut config = OperatorConfig::default();
+        config.watch.namespaces = vec!["team-a".to_string()];
+        config.watch.namespace_selector = Some("probelet.dev/enabled=true".to_string());
+        assert!(config.validate().is_err(), "namespaces and selector");
+
+        let mut config = OperatorConfig::default();
+        config.leader_election.renew_deadline_seconds = 20;
+        assert!(
+            config.validate().is_err(),
+            "renew deadline after lease duration"
+        );
+
+        let mut config = OperatorConfig::default();
+        config.controller.requeue_interval_seconds = 0;
+        assert!(config.validate().is_err(), "no requeue interval");
+
+        let mut config = OperatorConfig::default();
+        config.controller.max_backoff_seconds = u64::MAX;
+        assert!(config.validate().is_err(), "backoff overflowing deadlines");
+
+        let mut config = OperatorConfig::default();
+        config.controller.liveness_threshold_seconds = Some(20 * 60);
+        assert!(
+            config.validate().is_err(),
+            "liveness threshold shorter than the backoff"
+        );
+        config.controller.liveness_threshold_seconds = Some(40 * 60);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_default_liveness_threshold() {
+        let health = Health::default();
+        health.record_watch("workergroup", true, 1);
+        let now = Utc::now();
+
+        // an object failing repeatedly, or left unchanged, is only reconciled again after
+        // the maximum backoff or the requeue interval
+        let config = ControllerConfig::default();
+        for seconds in [config.max_backoff_seconds, config.requeue_interval_seconds] {
+            let last_event = now - TimeDelta::seconds(seconds as i64);
+            assert!(
+                health
+                    .liveness(last_event, now, config.liveness_threshold())
+                    .is_ok(),
+                "{seconds} seconds"
+            );
+        }
+
+        let config = ControllerConfig {
+            requeue_interval_seconds: 3600,
+            ..Default::default()
+        };
+        assert_eq!(config.liveness_threshold(), TimeDelta::minutes(65));
+        let config = ControllerConfig {
+            liveness_threshold_seconds: Some(7200),
+            ..Default::default()
+        };
+        assert_eq!(config.liveness_threshold(), TimeDelta::hours(2));
+    }
+}
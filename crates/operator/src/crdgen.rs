@@ -1,11 +1,36 @@
-use kube::CustomResourceExt;
-use operator::worker_group::WorkerGroup;
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
 
-fn main() {
-    let worker_crd = serde_yaml::to_string(&WorkerGroup::crd()).unwrap();
+use clap::Parser;
+use operator::crds;
 
-    io::stdout().write_all(b"---\n").unwrap();
-    io::stdout().write_all(worker_crd.as_bytes()).unwrap();
-    io::stdout().flush().unwrap();
+/// Generates the CRDs owned by the operator
+#[derive(Debug, Parser)]
+struct Args {
+    /// Writes one file per CRD in this directory instead of printing them all to stdout
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+
+    match args.output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            for crd in crds::crds() {
+                std::fs::write(dir.join(crds::file_name(&crd)), crds::to_yaml(&crd))?;
+            }
+        }
+        None => {
+            let mut stdout = io::stdout();
+            for crd in crds::crds() {
+                stdout.write_all(crds::to_yaml(&crd).as_bytes())?;
+            }
+            stdout.flush()?;
+        }
+    }
+    Ok(())
 }
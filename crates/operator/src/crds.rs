@@ -0,0 +1,59 @@
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{CustomResourceExt, ResourceExt};
+
+use crate::{probe::Probe, worker_group::WorkerGroup};
+
+/// The CRDs owned by the operator
+pub fn crds() -> Vec<CustomResourceDefinition> {
+    vec![Probe::crd(), WorkerGroup::crd()]
+}
+
+/// The file a CRD is written to, named after the CRD, e.g. `probes.probelet.dev.yaml`
+pub fn file_name(crd: &CustomResourceDefinition) -> String {
+    format!("{}.yaml", crd.name_any())
+}
+
+/// Serializes a CRD as a YAML document
+pub fn to_yaml(crd: &CustomResourceDefinition) -> String {
+    format!("---\n{}", serde_yaml::to_string(crd).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, path::Path};
+
+    use super::*;
+
+    const CHART_CRDS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../charts/operator/templates/crds"
+    );
+
+    /// Fails when the CRDs of the chart do not match the Rust types, run `just generate-crd`
+    /// to update them
+    #[test]
+    fn test_chart_crds_are_up_to_date() {
+        let dir = Path::new(CHART_CRDS);
+        for crd in crds() {
+            let path = dir.join(file_name(&crd));
+            let chart = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("{} is missing: {e}", path.display()));
+            assert_eq!(
+                chart,
+                to_yaml(&crd),
+                "{} drifted from the Rust types, run `just generate-crd`",
+                path.display()
+            );
+        }
+
+        let expected = crds().iter().map(file_name).collect::<BTreeSet<_>>();
+        let files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            files, expected,
+            "CRDs in the chart the operator does not own"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::{fmt, sync::Arc};
+
+use kube::{
+    Resource, ResourceExt,
+    runtime::{controller::Action, finalizer, reflector::ObjectRef},
+};
+use snafu::{ErrorCompat, IntoError};
+use tracing::warn;
+
+use crate::{Context, backoff::Retry, metrics::MetricLabel};
+
+/// How a Kubernetes client error can be retried, sorted by its status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KubeErrorKind {
+    /// The object was modified concurrently, the next attempt will likely succeed
+    Conflict,
+    /// The API server could not be reached or was overloaded
+    Transient,
+    /// The API server rejected the object as invalid
+    Invalid,
+    /// A resource quota of the namespace rejected the object
+    QuotaExceeded,
+    /// Any other Kubernetes error
+    Api,
+}
+
+impl KubeErrorKind {
+    fn of(error: &kube::Error) -> Self {
+        match error {
+            kube::Error::Api(response) => match response.code {
+                409 => KubeErrorKind::Conflict,
+                429 | 500.. => KubeErrorKind::Transient,
+                400 | 422 => KubeErrorKind::Invalid,
+                403 if response.message.contains("exceeded quota") => KubeErrorKind::QuotaExceeded,
+                _ => KubeErrorKind::Api,
+            },
+            kube::Error::HyperError(_) | kube::Error::Service(_) | kube::Error::ReadEvents(_) => {
+                KubeErrorKind::Transient
+            }
+            _ => KubeErrorKind::Api,
+        }
+    }
+}
+
+impl fmt::Display for KubeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KubeErrorKind::Conflict => "conflict",
+            KubeErrorKind::Transient => "transient error",
+            KubeErrorKind::Invalid => "validation error",
+            KubeErrorKind::QuotaExceeded => "quota exceeded",
+            KubeErrorKind::Api => "error",
+        })
+    }
+}
+
+/// A Kubernetes client error of a controller
+#[derive(Debug)]
+pub(crate) struct KubeError {
+    pub kind: KubeErrorKind,
+    message: String,
+    source: Box<kube::Error>,
+}
+
+impl fmt::Display for KubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Kubernetes {}: {}: {}",
+            self.kind, self.message, self.source
+        )
+    }
+}
+
+impl std::error::Error for KubeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl ErrorCompat for KubeError {}
+
+/// Context selector for Kubernetes client errors, sorted by how they can be retried
+pub(crate) struct KubeSnafu<M> {
+    pub message: M,
+}
+
+impl<M: Into<String>> IntoError<KubeError> for KubeSnafu<M> {
+    type Source = kube::Error;
+
+    fn into_error(self, source: kube::Error) -> KubeError {
+        KubeError::new(self.message, source)
+    }
+}
+
+impl KubeError {
+    pub fn new(message: impl Into<String>, source: kube::Error) -> Self {
+        Self {
+            kind: KubeErrorKind::of(&source),
+            message: message.into(),
+            source: Box::new(source),
+        }
+    }
+
+    /// How the reconcile should be retried after this error
+    pub fn retry(&self) -> Retry {
+        match self.kind {
+            KubeErrorKind::Conflict => Retry::backoff(1, 30),
+            KubeErrorKind::Transient => Retry::backoff(5, 5 * 60),
+            KubeErrorKind::QuotaExceeded => Retry::backoff(30, 15 * 60),
+            KubeErrorKind::Invalid | KubeErrorKind::Api => Retry::backoff(60, 30 * 60),
+        }
+    }
+}
+
+impl MetricLabel for KubeError {
+    fn metric_label(&self) -> String {
+        match self.kind {
+            KubeErrorKind::Conflict => "conflict",
+            KubeErrorKind::Transient => "transient",
+            KubeErrorKind::Invalid => "invalid",
+            KubeErrorKind::QuotaExceeded => "quota_exceeded",
+            KubeErrorKind::Api => "api",
+        }
+        .to_string()
+    }
+}
+
+/// The error of a reconcile, which knows how it should be retried
+pub(crate) trait ReconcileError: snafu::Error + MetricLabel + Sized {
+    fn retry(&self) -> Retry;
+}
+
+/// How to retry after the failures of the finalizer, or of the reconcile it ran
+pub(crate) fn finalizer_retry<E: ReconcileError>(error: &finalizer::Error<E>) -> Retry {
+    match error {
+        finalizer::Error::ApplyFailed(e) | finalizer::Error::CleanupFailed(e) => e.retry(),
+        finalizer::Error::AddFinalizer(_) | finalizer::Error::RemoveFinalizer(_) => {
+            Retry::backoff(1, 60)
+        }
+        finalizer::Error::UnnamedObject | finalizer::Error::InvalidFinalizer => Retry::Never,
+    }
+}
+
+/// The metric label of the failures of the finalizer, or of the reconcile it ran
+pub(crate) fn finalizer_metric_label<E: ReconcileError>(error: &finalizer::Error<E>) -> String {
+    match error {
+        finalizer::Error::ApplyFailed(e) | finalizer::Error::CleanupFailed(e) => e.metric_label(),
+        finalizer::Error::AddFinalizer(_) => "add_finalizer".to_string(),
+        finalizer::Error::RemoveFinalizer(_) => "remove_finalizer".to_string(),
+        finalizer::Error::UnnamedObject => "unnamed_object".to_string(),
+        finalizer::Error::InvalidFinalizer => "invalid_finalizer".to_string(),
+    }
+}
+
+/// The error policy of the controllers: counts the failure and requeues the object with
+/// its backoff, or waits for it to change when retrying cannot help
+pub(crate) fn error_policy<K, E>(
+    controller: &str,
+    object: Arc<K>,
+    error: &E,
+    context: Arc<Context>,
+) -> Action
+where
+    K: Resource<DynamicType = ()> + MetricLabel,
+    E: ReconcileError,
+{
+    warn!(
+        "reconcile failed for {} \"{}\" in ns \"{}\": {error:?}",
+        K::kind(&()),
+        object.name_any(),
+        object.namespace().unwrap_or_default()
+    );
+    context
+        .metrics
+        .reconcile
+        .set_failure(controller, &*object, error);
+    match error.retry() {
+        Retry::Backoff { base, max } => {
+            let key = ObjectRef::from_obj(&*object).to_string();
+            let max = max.min(context.config.max_backoff());
+            Action::requeue(context.backoff.next_delay(&key, base.min(max), max))
+        }
+        Retry::Never => Action::await_change(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ErrorResponse;
+
+    use super::*;
+
+    fn kind(code: u16, message: &str) -> KubeErrorKind {
+        KubeErrorKind::of(&kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: message.to_string(),
+            reason: String::new(),
+            code,
+        }))
+    }
+
+    #[test]
+    fn test_kube_errors_are_classified() {
+        for (code, expected) in [
+            (409, KubeErrorKind::Conflict),
+            (429, KubeErrorKind::Transient),
+            (503, KubeErrorKind::Transient),
+            (422, KubeErrorKind::Invalid),
+            (404, KubeErrorKind::Api),
+        ] {
+            assert_eq!(kind(code, ""), expected, "code {code}");
+        }
+
+        let quota = kind(403, "pods \"test-0\" is forbidden: exceeded quota: pods");
+        assert_eq!(quota, KubeErrorKind::QuotaExceeded);
+        assert_eq!(kind(403, "forbidden"), KubeErrorKind::Api);
+
+        let error = KubeSnafu { message: "test" }.into_error(kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: String::new(),
+            reason: String::new(),
+            code: 409,
+        }));
+        assert_eq!(error.metric_label(), "conflict");
+        assert!(error.to_string().starts_with("Kubernetes conflict: test: "));
+    }
+}
@@ -0,0 +1,579 @@
+pub mod dns;
+mod grpc;
+pub mod http;
+mod icmp;
+mod metrics;
+mod net;
+mod tcp;
+mod tls;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use kube::{
+    Api, Client, ResourceExt,
+    runtime::{
+        WatchStreamExt, reflector,
+        watcher::{self, Event, watcher},
+    },
+};
+pub use metrics::{ExecutorMetrics, ProbeLabels, ProbePhaseLabels};
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+use validator::Validate;
+
+use crate::{
+    probe::{Probe, ProbeKind, ProbeSpec},
+    worker_group::{DRAINING_ANNOTATION, SHARD_ANNOTATION},
+};
+
+/// How often the annotations of the worker pod are read to notice it is draining, or that
+/// its shard changed
+const ANNOTATIONS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Who the worker is, read from the labels of its pod through the downward API
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerIdentity {
+    pub name: String,
+    pub worker_group: String,
+    pub namespace: String,
+}
+
+/// A phase of a run of a probe, timed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolving the host name
+    Dns,
+    /// Opening the connection
+    Connect,
+    /// The TLS handshake
+    Tls,
+    /// From sending the request to receiving the first byte of the response
+    FirstByte,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [Phase::Dns, Phase::Connect, Phase::Tls, Phase::FirstByte];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Dns => "dns",
+            Phase::Connect => "connect",
+            Phase::Tls => "tls",
+            Phase::FirstByte => "first_byte",
+        }
+    }
+}
+
+/// The outcome of a run of a probe
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeOutcome {
+    pub success: bool,
+    /// The total duration of the run
+    pub duration: Duration,
+    /// Why the probe failed, or a summary of the response when it succeeded
+    pub message: String,
+    /// The duration of the phases the run went through, in order
+    pub phases: Vec<(Phase, Duration)>,
+    /// When the certificate of the server expires, for the probes checking it
+    pub certificate_expiry: Option<DateTime<Utc>>,
+}
+
+impl ProbeOutcome {
+    pub fn success(duration: Duration, message: impl Into<String>) -> Self {
+        Self {
+            success: true,
+            duration,
+            message: message.into(),
+            phases: Vec::new(),
+            certificate_expiry: None,
+        }
+    }
+
+    pub fn failure(duration: Duration, message: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            duration,
+            message: message.into(),
+            phases: Vec::new(),
+            certificate_expiry: None,
+        }
+    }
+
+    pub fn with_phases(mut self, phases: Vec<(Phase, Duration)>) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    pub fn with_certificate_expiry(mut self, expiry: DateTime<Utc>) -> Self {
+        self.certificate_expiry = Some(expiry);
+        self
+    }
+
+    /// Times a run, which records the duration of the phases it goes through and returns
+    /// the message of the outcome
+    async fn measure(
+        run: impl AsyncFnOnce(&mut Vec<(Phase, Duration)>) -> Result<String, String>,
+    ) -> Self {
+        let start = Instant::now();
+        let mut phases = Vec::new();
+        match run(&mut phases).await {
+            Ok(message) => Self::success(start.elapsed(), message),
+            Err(message) => Self::failure(start.elapsed(), message),
+        }
+        .with_phases(phases)
+    }
+
+    /// The phases the run went through, without their durations
+    #[cfg(test)]
+    fn phase_list(&self) -> Vec<Phase> {
+        self.phases.iter().map(|(phase, _)| *phase).collect()
+    }
+
+    /// The phase durations for the logs, like `dns=1ms connect=2ms`
+    fn phase_summary(&self) -> String {
+        self.phases
+            .iter()
+            .map(|(phase, duration)| format!("{}={}ms", phase.as_str(), duration.as_millis()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// What the probes need from the worker to run, beyond their spec
+#[derive(Clone)]
+pub struct ProbeContext {
+    pub client: Client,
+    /// The namespace of the probes, where the resources they refer to are read
+    pub namespace: String,
+    /// The CA bundles of the `Tls` probes, read from their `ConfigMap`s
+    ca_bundles: Arc<tls::CaBundles>,
+}
+
+/// Runs a probe once
+pub async fn execute(kind: &ProbeKind, context: &ProbeContext) -> ProbeOutcome {
+    match kind {
+        ProbeKind::Http(probe) => http::execute(probe).await,
+        ProbeKind::Tcp(probe) => tcp::execute(probe).await,
+        ProbeKind::Dns(probe) => dns::execute(probe).await,
+        ProbeKind::Tls(probe) => tls::execute(probe, context).await,
+        ProbeKind::Grpc(probe) => grpc::execute(probe).await,
+        ProbeKind::Icmp(probe) => icmp::execute(probe).await,
+    }
+}
+
+/// Runs a probe once, failing it when it takes longer than its timeout
+async fn execute_with_timeout(spec: &ProbeSpec, context: &ProbeContext) -> ProbeOutcome {
+    let timeout = Duration::from_secs(spec.timeout_seconds.into());
+    match tokio::time::timeout(timeout, execute(&spec.kind, context)).await {
+        Ok(outcome) => outcome,
+        Err(_) => ProbeOutcome::failure(timeout, format!("timed out after {timeout:?}")),
+    }
+}
+
+/// State shared between the executor and the health and metrics server of the worker
+#[derive(Clone, Default)]
+pub struct WorkerState {
+    pub metrics: Arc<ExecutorMetrics>,
+    synced: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+}
+
+impl WorkerState {
+    /// Ready once the probes are listed, and until the worker is drained
+    pub fn readiness(&self) -> Result<(), String> {
+        if self.draining.load(Ordering::Relaxed) {
+            return Err("draining".to_string());
+        }
+        if !self.synced.load(Ordering::Relaxed) {
+            return Err("probes are not listed yet".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The part of the probes of its group a worker runs, written `<index>/<count>`.
+///
+/// The operator numbers the ready workers of a group, each probe runs on the worker whose
+/// index is the hash of its name modulo their count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// Whether the probe named `name` runs on the worker of this shard
+    pub fn owns(&self, name: &str) -> bool {
+        // FNV-1a, stable across builds so that every worker agrees on the assignments
+        let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        hash % u64::from(self.count) == u64::from(self.index)
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid shard {value}, expected <index>/<count>");
+        let (index, count) = value.split_once('/').ok_or_else(invalid)?;
+        let shard = Shard {
+            index: index.parse().map_err(|_| invalid())?,
+            count: count.parse().map_err(|_| invalid())?,
+        };
+        if shard.index >= shard.count {
+            return Err(invalid());
+        }
+        Ok(shard)
+    }
+}
+
+/// The valid probes of the namespace run by the `WorkerGroup` of the worker and owned by
+/// its shard, by name. A worker without a shard runs no probe.
+pub fn assigned_probes(
+    probes: &[Arc<Probe>],
+    identity: &WorkerIdentity,
+    shard: Option<Shard>,
+) -> BTreeMap<String, ProbeSpec> {
+    probes
+        .iter()
+        .filter(|probe| {
+            probe.namespace().as_deref() == Some(identity.namespace.as_str())
+                && probe.spec.worker_group == identity.worker_group
+                && probe.metadata.deletion_timestamp.is_none()
+                && probe.spec.validate().is_ok()
+                && shard.is_some_and(|shard| shard.owns(&probe.name_any()))
+        })
+        .map(|probe| (probe.name_any(), probe.spec.clone()))
+        .collect()
+}
+
+/// The value of `key` in the annotations file written by the downward API, one
+/// `key="value"` per line
+fn annotation<'a>(annotations: &'a str, key: &str) -> Option<&'a str> {
+    annotations.lines().find_map(|line| {
+        let (name, value) = line.split_once('=')?;
+        (name == key).then(|| value.trim_matches('"'))
+    })
+}
+
+/// Whether the `probelet.dev/draining` annotation is set in the annotations file
+pub fn is_draining(annotations: &str) -> bool {
+    annotation(annotations, DRAINING_ANNOTATION) == Some("true")
+}
+
+/// The shard in the `probelet.dev/shard` annotation of the annotations file
+pub fn shard(annotations: &str) -> Option<Shard> {
+    annotation(annotations, SHARD_ANNOTATION)?.parse().ok()
+}
+
+struct RunningProbe {
+    spec: ProbeSpec,
+    task: JoinHandle<()>,
+}
+
+/// Runs each assigned probe on its interval, restarting it when its spec changes
+pub struct Scheduler {
+    identity: WorkerIdentity,
+    metrics: Arc<ExecutorMetrics>,
+    context: ProbeContext,
+    /// The part of the probes of the group the worker runs, none until the operator sets it
+    shard: Option<Shard>,
+    running: HashMap<String, RunningProbe>,
+}
+
+impl Scheduler {
+    pub fn new(identity: WorkerIdentity, metrics: Arc<ExecutorMetrics>, client: Client) -> Self {
+        let context = ProbeContext {
+            client,
+            namespace: identity.namespace.clone(),
+            ca_bundles: Default::default(),
+        };
+        Self {
+            identity,
+            metrics,
+            context,
+            shard: None,
+            running: HashMap::new(),
+        }
+    }
+
+    /// Changes the shard of the worker, applied by the next `sync`
+    pub fn set_shard(&mut self, shard: Option<Shard>) {
+        if shard != self.shard {
+            info!(
+                "worker shard changed to {}",
+                shard.map_or("none".to_string(), |shard| shard.to_string())
+            );
+            self.shard = shard;
+        }
+    }
+
+    /// Starts the probes newly assigned to the worker and stops the ones that are not
+    pub fn sync(&mut self, probes: &[Arc<Probe>]) {
+        let mut assigned = assigned_probes(probes, &self.identity, self.shard);
+
+        self.running.retain(|name, running| {
+            if assigned.get(name) == Some(&running.spec) {
+                assigned.remove(name);
+                return true;
+            }
+            info!("stopping probe \"{name}\"");
+            running.task.abort();
+            self.metrics.remove(&ProbeLabels {
+                namespace: self.identity.namespace.clone(),
+                probe: name.clone(),
+            });
+            false
+        });
+
+        for (name, spec) in assigned {
+            info!("starting probe \"{name}\"");
+            let labels = ProbeLabels {
+                namespace: self.identity.namespace.clone(),
+                probe: name.clone(),
+            };
+            let task = tokio::spawn(run_probe(
+                labels,
+                spec.clone(),
+                self.metrics.clone(),
+                self.context.clone(),
+            ));
+            self.running.insert(name, RunningProbe { spec, task });
+        }
+        self.metrics.assigned.set(self.running.len() as i64);
+    }
+
+    /// Names of the probes currently running
+    pub fn running(&self) -> Vec<String> {
+        let mut names = self.running.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        for running in self.running.values() {
+            running.task.abort();
+        }
+    }
+}
+
+/// Runs a probe on its interval until the task is aborted
+async fn run_probe(
+    labels: ProbeLabels,
+    spec: ProbeSpec,
+    metrics: Arc<ExecutorMetrics>,
+    context: ProbeContext,
+) {
+    let period = Duration::from_secs(spec.interval_seconds.max(1).into());
+    // spread the first runs so probes created together do not run in lockstep
+    let offset = period.mul_f64(rand::random::<f64>());
+    let mut interval = tokio::time::interval_at(Instant::now() + offset, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let outcome = execute_with_timeout(&spec, &context).await;
+        if outcome.success {
+            debug!(
+                probe = labels.probe,
+                duration = ?outcome.duration,
+                phases = outcome.phase_summary(),
+                "probe succeeded: {}",
+                outcome.message
+            );
+        } else {
+            warn!(
+                probe = labels.probe,
+                duration = ?outcome.duration,
+                phases = outcome.phase_summary(),
+                "probe failed: {}",
+                outcome.message
+            );
+        }
+        metrics.record(&labels, &outcome);
+    }
+}
+
+/// Runs the probes assigned to the worker until the probe watch ends.
+///
+/// The worker learns its shard, and that the operator marks it as draining, through
+/// `annotations_file`. The probes are stopped once it is draining.
+pub async fn run(
+    client: Client,
+    identity: WorkerIdentity,
+    annotations_file: PathBuf,
+    state: WorkerState,
+) {
+    let api = Api::<Probe>::namespaced(client.clone(), &identity.namespace);
+    let (reader, writer) = reflector::store();
+    let mut events = reflector(writer, watcher(api, watcher::Config::default()))
+        .default_backoff()
+        .boxed();
+    let mut scheduler = Scheduler::new(identity, state.metrics.clone(), client);
+    let mut annotations_check = tokio::time::interval(ANNOTATIONS_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(Event::Init | Event::InitApply(_))) => continue,
+                Some(Ok(_)) => state.synced.store(true, Ordering::Relaxed),
+                Some(Err(e)) => {
+                    warn!("probe watcher failed: {e}");
+                    continue;
+                }
+                None => break,
+            },
+            _ = annotations_check.tick() => {
+                let annotations = tokio::fs::read_to_string(&annotations_file)
+                    .await
+                    .unwrap_or_default();
+                if is_draining(&annotations) && !state.draining.swap(true, Ordering::Relaxed) {
+                    info!("worker is draining, stopping its probes");
+                }
+                scheduler.set_shard(shard(&annotations));
+            }
+        }
+
+        if state.draining.load(Ordering::Relaxed) {
+            scheduler.sync(&[]);
+        } else if state.synced.load(Ordering::Relaxed) {
+            scheduler.sync(&reader.state());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::http::{Request, Response};
+    use kube::{Resource, client::Body};
+
+    use super::*;
+    use crate::probe::HttpProbe;
+
+    fn identity() -> WorkerIdentity {
+        WorkerIdentity {
+            name: "workers-0".to_string(),
+            worker_group: "workers".to_string(),
+            namespace: "default".to_string(),
+        }
+    }
+
+    fn probe(name: &str, worker_group: &str, url: &str) -> Arc<Probe> {
+        let mut probe = Probe::new(
+            name,
+            ProbeSpec {
+                worker_group: worker_group.to_string(),
+                interval_seconds: 60,
+                timeout_seconds: 10,
+                kind: ProbeKind::Http(HttpProbe {
+                    url: url.to_string(),
+                    ..Default::default()
+                }),
+            },
+        );
+        probe.meta_mut().namespace = Some("default".to_string());
+        Arc::new(probe)
+    }
+
+    #[test]
+    fn test_assigned_probes() {
+        let probes = [
+            probe("a", "workers", "https://example.com"),
+            probe("b", "others", "https://example.com"),
+            probe("c", "workers", "not a url"),
+        ];
+        let assigned = assigned_probes(&probes, &identity(), Some(Shard { index: 0, count: 1 }));
+        assert_eq!(assigned.keys().collect::<Vec<_>>(), ["a"]);
+        assert!(assigned_probes(&probes, &identity(), None).is_empty());
+    }
+
+    #[test]
+    fn test_shards_split_the_probes() {
+        let probes = (0..100)
+            .map(|i| probe(&format!("probe-{i}"), "workers", "https://example.com"))
+            .collect::<Vec<_>>();
+        let mut owners = BTreeMap::<String, u32>::new();
+        for index in 0..3 {
+            let shard = Shard { index, count: 3 };
+            let assigned = assigned_probes(&probes, &identity(), Some(shard));
+            assert!(assigned.len() > 10, "{shard}: {} probes", assigned.len());
+            for name in assigned.into_keys() {
+                assert_eq!(owners.insert(name, index), None, "run once");
+            }
+        }
+        assert_eq!(owners.len(), probes.len(), "every probe runs");
+    }
+
+    #[test]
+    fn test_shard_annotation() {
+        let annotations = "probelet.dev/operatorVersion=\"0.1.0\"\nprobelet.dev/shard=\"1/3\"\n";
+        assert_eq!(shard(annotations), Some(Shard { index: 1, count: 3 }));
+        assert_eq!(Shard { index: 1, count: 3 }.to_string(), "1/3");
+        assert_eq!(shard("probelet.dev/shard=\"3/3\"\n"), None);
+        assert_eq!(shard("probelet.dev/shard=\"1\"\n"), None);
+        assert_eq!(shard(""), None);
+    }
+
+    #[test]
+    fn test_is_draining() {
+        let annotations =
+            "probelet.dev/operatorVersion=\"0.1.0\"\nprobelet.dev/draining=\"true\"\n";
+        assert!(is_draining(annotations));
+        assert!(!is_draining("probelet.dev/operatorVersion=\"0.1.0\"\n"));
+        assert!(!is_draining("probelet.dev/draining=\"false\"\n"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_scheduler_sync() {
+        let metrics = Arc::new(ExecutorMetrics::default());
+        let (service, _handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let client = Client::new(service, "default");
+        let mut scheduler = Scheduler::new(identity(), metrics.clone(), client);
+        scheduler.set_shard(Some(Shard { index: 0, count: 1 }));
+
+        scheduler.sync(&[
+            probe("a", "workers", "https://example.com"),
+            probe("b", "workers", "https://example.org"),
+            probe("c", "others", "https://example.com"),
+        ]);
+        assert_eq!(scheduler.running(), ["a", "b"]);
+        assert_eq!(metrics.assigned.get(), 2);
+
+        // a changed spec restarts the probe
+        let first = scheduler.running["a"].task.id();
+        scheduler.sync(&[
+            probe("a", "workers", "https://example.net"),
+            probe("b", "workers", "https://example.org"),
+        ]);
+        assert_ne!(scheduler.running["a"].task.id(), first);
+
+        scheduler.sync(&[probe("b", "workers", "https://example.org")]);
+        assert_eq!(scheduler.running(), ["b"]);
+        assert_eq!(metrics.assigned.get(), 1);
+    }
+}
@@ -0,0 +1,554 @@
+mod message;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    time::Instant,
+};
+
+use crate::{
+    executor::{Phase, ProbeOutcome, net},
+    probe::{DnsAssertions, DnsProbe, DnsProtocol, DnsRecordType, DnsResponseCode},
+};
+
+const DNS_PORT: u16 = 53;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Parses the address of a nameserver, an IP address with an optional port
+pub fn parse_nameserver(value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            value
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        })
+        .map_err(|_| format!("{value} is not an IP address with an optional port"))
+}
+
+/// The first nameserver of a `resolv.conf` file
+fn resolv_conf_nameserver(contents: &str) -> Option<SocketAddr> {
+    contents.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("nameserver") {
+            return None;
+        }
+        fields.next().and_then(|ip| parse_nameserver(ip).ok())
+    })
+}
+
+/// Runs a DNS probe once
+pub async fn execute(probe: &DnsProbe) -> ProbeOutcome {
+    ProbeOutcome::measure(async |phases| run(probe, phases).await).await
+}
+
+async fn run(probe: &DnsProbe, phases: &mut Vec<(Phase, Duration)>) -> Result<String, String> {
+    let nameserver = match &probe.nameserver {
+        Some(nameserver) => parse_nameserver(nameserver)?,
+        None => tokio::fs::read_to_string(RESOLV_CONF)
+            .await
+            .ok()
+            .and_then(|contents| resolv_conf_nameserver(&contents))
+            .ok_or_else(|| format!("no nameserver is set and {RESOLV_CONF} has none"))?,
+    };
+    let id = rand::random();
+    let query = message::encode_query(id, &probe.name, probe.record_type)?;
+
+    let started = Instant::now();
+    let mut response = match probe.protocol {
+        DnsProtocol::Udp => query_udp(nameserver, &query, id).await?,
+        DnsProtocol::Tcp => query_tcp(nameserver, &query, id, phases).await?,
+    };
+    if response.truncated && probe.protocol == DnsProtocol::Udp {
+        response = query_tcp(nameserver, &query, id, phases).await?;
+    }
+    // the TCP connections are timed in their own phase
+    let connect_time = phases
+        .iter()
+        .find(|(phase, _)| *phase == Phase::Connect)
+        .map_or(Duration::ZERO, |(_, duration)| *duration);
+    let response_time = started.elapsed().saturating_sub(connect_time);
+    net::record_phase(phases, Phase::Dns, response_time);
+
+    let failures = check(
+        &probe.assertions,
+        probe.record_type,
+        &response,
+        response_time,
+    );
+    if !failures.is_empty() {
+        return Err(failures.join("; "));
+    }
+    let count = answers(&response, probe.record_type).len();
+    Ok(format!(
+        "{} with {count} {} records from {nameserver}",
+        message::rcode_name(response.rcode),
+        message::type_name(probe.record_type)
+    ))
+}
+
+async fn query_udp(
+    nameserver: SocketAddr,
+    query: &[u8],
+    id: u16,
+) -> Result<message::Response, String> {
+    let local = match nameserver {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|e| format!("failed to open a UDP socket: {e}"))?;
+    socket
+        .connect(nameserver)
+        .await
+        .map_err(|e| format!("failed to query {nameserver}: {e}"))?;
+    socket
+        .send(query)
+        .await
+        .map_err(|e| format!("failed to query {nameserver}: {e}"))?;
+
+    let mut buffer = vec![0; message::MAX_UDP_SIZE.into()];
+    loop {
+        let size = socket
+            .recv(&mut buffer)
+            .await
+            .map_err(|e| format!("failed to receive the answer of {nameserver}: {e}"))?;
+        let response = message::parse_response(&buffer[..size])
+            .map_err(|e| format!("invalid answer from {nameserver}: {e}"))?;
+        // answers to earlier queries that timed out may still arrive
+        if response.id == id {
+            return Ok(response);
+        }
+    }
+}
+
+async fn query_tcp(
+    nameserver: SocketAddr,
+    query: &[u8],
+    id: u16,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<message::Response, String> {
+    let mut stream = net::connect(&[nameserver], phases).await?;
+    let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(query);
+    stream
+        .write_all(&framed)
+        .await
+        .map_err(|e| format!("failed to query {nameserver}: {e}"))?;
+
+    let length = stream
+        .read_u16()
+        .await
+        .map_err(|e| format!("failed to receive the answer of {nameserver}: {e}"))?;
+    let mut buffer = vec![0; length.into()];
+    stream
+        .read_exact(&mut buffer)
+        .await
+        .map_err(|e| format!("failed to receive the answer of {nameserver}: {e}"))?;
+    let response = message::parse_response(&buffer)
+        .map_err(|e| format!("invalid answer from {nameserver}: {e}"))?;
+    if response.id != id {
+        return Err(format!("{nameserver} answered another query"));
+    }
+    Ok(response)
+}
+
+/// The records of the answer of the queried type, the answer to an `A` query can also
+/// have the `CNAME` records leading to them
+fn answers(response: &message::Response, record_type: DnsRecordType) -> Vec<&message::Record> {
+    let type_code = message::type_code(record_type);
+    response
+        .answers
+        .iter()
+        .filter(|record| record.type_code == type_code)
+        .collect()
+}
+
+/// Checks the response against the assertions, returning why each failing one failed
+fn check(
+    assertions: &DnsAssertions,
+    record_type: DnsRecordType,
+    response: &message::Response,
+    response_time: Duration,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    let expected_rcode = message::rcode(assertions.rcode);
+    if response.rcode != expected_rcode {
+        failures.push(format!(
+            "rcode is {}, expected {}",
+            message::rcode_name(response.rcode),
+            message::rcode_name(expected_rcode)
+        ));
+    }
+
+    let records = answers(response, record_type);
+    let type_name = message::type_name(record_type);
+    let min_answers = assertions
+        .min_answers
+        .unwrap_or(u32::from(assertions.rcode == DnsResponseCode::NoError));
+    if records.len() < min_answers as usize {
+        failures.push(format!(
+            "the answer has {} {type_name} records, expected at least {min_answers}",
+            records.len()
+        ));
+    }
+
+    let mut values = records
+        .iter()
+        .map(|record| record.value.clone())
+        .collect::<Vec<_>>();
+    values.sort();
+    for expected in &assertions.contains {
+        let expected = normalize(record_type, expected);
+        if !values.contains(&expected) {
+            failures.push(format!(
+                "the answer does not contain {expected}, it has {}",
+                describe(&values)
+            ));
+        }
+    }
+    if let Some(expected) = &assertions.equals {
+        let mut expected = expected
+            .iter()
+            .map(|value| normalize(record_type, value))
+            .collect::<Vec<_>>();
+        expected.sort();
+        if expected != values {
+            failures.push(format!(
+                "the answer is {}, expected {}",
+                describe(&values),
+                describe(&expected)
+            ));
+        }
+    }
+
+    for record in &records {
+        if let Some(min) = assertions.min_ttl
+            && record.ttl < min
+        {
+            failures.push(format!(
+                "the TTL of {} is {}s, below the minimum of {min}s",
+                record.value, record.ttl
+            ));
+        }
+        if let Some(max) = assertions.max_ttl
+            && record.ttl > max
+        {
+            failures.push(format!(
+                "the TTL of {} is {}s, above the maximum of {max}s",
+                record.value, record.ttl
+            ));
+        }
+    }
+
+    if let Some(max) = assertions.max_response_time_ms
+        && response_time > Duration::from_millis(max)
+    {
+        failures.push(format!(
+            "the nameserver answered in {}ms, more than the max of {max}ms",
+            response_time.as_millis()
+        ));
+    }
+
+    failures
+}
+
+fn describe(values: &[String]) -> String {
+    if values.is_empty() {
+        return "nothing".to_string();
+    }
+    values.join(", ")
+}
+
+/// Writes an expected value the way answers are formatted, so `2001:DB8::1` matches
+/// `2001:db8::1` and `mail.example.com` matches `mail.example.com.`
+fn normalize(record_type: DnsRecordType, value: &str) -> String {
+    let value = value.trim();
+    match record_type {
+        DnsRecordType::A | DnsRecordType::Aaaa => value
+            .parse::<IpAddr>()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|_| value.to_string()),
+        DnsRecordType::Cname => fully_qualified(value),
+        DnsRecordType::Mx | DnsRecordType::Srv => {
+            let mut fields = value.split_whitespace().collect::<Vec<_>>();
+            let target = fields.pop().map(fully_qualified).unwrap_or_default();
+            fields.push(&target);
+            fields.join(" ")
+        }
+        DnsRecordType::Txt | DnsRecordType::Caa => value.to_string(),
+    }
+}
+
+fn fully_qualified(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    if name.ends_with('.') {
+        return name;
+    }
+    format!("{name}.")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Answer = (DnsRecordType, u32, Vec<u8>);
+
+    /// Compression pointer to the name of the question
+    const QUESTION_NAME: [u8; 2] = [0xc0, 12];
+
+    fn wire_name(name: &str) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        message::encode_name(name, &mut buffer).unwrap();
+        buffer
+    }
+
+    /// The records of the stub zone, `None` for names that do not exist
+    fn zone(name: &str, type_code: u16) -> Option<Vec<Answer>> {
+        let answers = match name {
+            "example.test" => vec![
+                (DnsRecordType::A, 300, vec![192, 0, 2, 1]),
+                (DnsRecordType::A, 30, vec![192, 0, 2, 2]),
+                (
+                    DnsRecordType::Aaaa,
+                    300,
+                    "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+                ),
+                // mail + the question name, compressed
+                (
+                    DnsRecordType::Mx,
+                    300,
+                    [&[0, 10, 4][..], b"mail", &QUESTION_NAME].concat(),
+                ),
+                (
+                    DnsRecordType::Txt,
+                    300,
+                    [&[7][..], b"v=spf1 ", &[4], b"-all"].concat(),
+                ),
+                (
+                    DnsRecordType::Caa,
+                    300,
+                    [&[0, 5][..], b"issue", b"letsencrypt.org"].concat(),
+                ),
+            ],
+            "www.example.test" => vec![
+                (DnsRecordType::Cname, 300, wire_name("example.test")),
+                (DnsRecordType::A, 300, vec![192, 0, 2, 1]),
+            ],
+            "_http._tcp.example.test" => vec![(
+                DnsRecordType::Srv,
+                300,
+                [&[0, 0, 0, 5, 0, 80][..], &wire_name("web.example.test")].concat(),
+            )],
+            "big.example.test" => (0..200)
+                .map(|i| (DnsRecordType::A, 300, vec![10, 0, 0, i as u8]))
+                .collect(),
+            _ => return None,
+        };
+        let answers = answers
+            .into_iter()
+            .filter(|(record_type, _, _)| {
+                let code = message::type_code(*record_type);
+                code == type_code || code == 5
+            })
+            .collect();
+        Some(answers)
+    }
+
+    /// Answers a query from the stub zone, truncating answers over 512 bytes when `udp`
+    fn answer(query: &[u8], udp: bool) -> Vec<u8> {
+        let mut position = 12;
+        let mut labels = Vec::new();
+        while query[position] != 0 {
+            let length = query[position] as usize;
+            labels.push(String::from_utf8_lossy(
+                &query[position + 1..position + 1 + length],
+            ));
+            position += 1 + length;
+        }
+        let question_end = position + 5;
+        let type_code = u16::from_be_bytes([query[position + 1], query[position + 2]]);
+
+        let (rcode, answers) = match zone(&labels.join("."), type_code) {
+            Some(answers) => (0, answers),
+            None => (3, Vec::new()),
+        };
+        let mut records = Vec::new();
+        for (record_type, ttl, data) in &answers {
+            records.extend_from_slice(&QUESTION_NAME);
+            records.extend_from_slice(&message::type_code(*record_type).to_be_bytes());
+            records.extend_from_slice(&[0, 1]);
+            records.extend_from_slice(&ttl.to_be_bytes());
+            records.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            records.extend_from_slice(data);
+        }
+        let truncated = udp && records.len() > 512;
+
+        let flags: u16 = 0x8180 | rcode | if truncated { 0x0200 } else { 0 };
+        let count = if truncated { 0 } else { answers.len() as u16 };
+        let mut response = [&query[..2], &flags.to_be_bytes()[..], &[0, 1]].concat();
+        response.extend_from_slice(&count.to_be_bytes());
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        response.extend_from_slice(&query[12..question_end]);
+        if !truncated {
+            response.extend_from_slice(&records);
+        }
+        response
+    }
+
+    /// Serves the stub zone over UDP and TCP on the same port
+    async fn stub_nameserver() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let listener = TcpListener::bind(address).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            loop {
+                let (size, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let response = answer(&buffer[..size], true);
+                socket.send_to(&response, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let length = stream.read_u16().await.unwrap();
+                let mut query = vec![0; length.into()];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = answer(&query, false);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        address
+    }
+
+    fn dns_probe(nameserver: SocketAddr, name: &str, record_type: DnsRecordType) -> DnsProbe {
+        DnsProbe {
+            name: name.to_string(),
+            record_type,
+            nameserver: Some(nameserver.to_string()),
+            protocol: DnsProtocol::Udp,
+            assertions: DnsAssertions::default(),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_probe_records() {
+        let nameserver = stub_nameserver().await;
+        for (name, record_type, expected) in [
+            (
+                "example.test",
+                DnsRecordType::A,
+                &["192.0.2.2", "192.0.2.1"][..],
+            ),
+            ("example.test", DnsRecordType::Aaaa, &["2001:DB8::1"]),
+            ("www.example.test", DnsRecordType::A, &["192.0.2.1"]),
+            ("www.example.test", DnsRecordType::Cname, &["Example.test"]),
+            ("example.test", DnsRecordType::Mx, &["10 mail.example.test"]),
+            ("example.test", DnsRecordType::Txt, &["v=spf1 -all"]),
+            (
+                "_http._tcp.example.test",
+                DnsRecordType::Srv,
+                &["0 5 80 web.example.test."],
+            ),
+            (
+                "example.test",
+                DnsRecordType::Caa,
+                &["0 issue \"letsencrypt.org\""],
+            ),
+        ] {
+            let mut probe = dns_probe(nameserver, name, record_type);
+            probe.assertions.equals =
+                Some(expected.iter().map(|value| value.to_string()).collect());
+            let outcome = execute(&probe).await;
+            assert!(
+                outcome.success,
+                "{name} {record_type:?}: {}",
+                outcome.message
+            );
+        }
+
+        let outcome = execute(&dns_probe(nameserver, "example.test", DnsRecordType::A)).await;
+        assert_eq!(
+            outcome.message,
+            format!("NOERROR with 2 A records from {nameserver}")
+        );
+        assert_eq!(outcome.phases.len(), 1);
+        assert_eq!(outcome.phases[0].0, Phase::Dns);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_probe_assertions() {
+        let nameserver = stub_nameserver().await;
+
+        let outcome = execute(&dns_probe(nameserver, "missing.test", DnsRecordType::A)).await;
+        assert_eq!(
+            outcome.message,
+            "rcode is NXDOMAIN, expected NOERROR; the answer has 0 A records, expected at least 1"
+        );
+
+        let mut probe = dns_probe(nameserver, "missing.test", DnsRecordType::A);
+        probe.assertions.rcode = DnsResponseCode::NxDomain;
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+
+        let mut probe = dns_probe(nameserver, "example.test", DnsRecordType::A);
+        probe.assertions = DnsAssertions {
+            contains: vec!["192.0.2.3".to_string()],
+            equals: Some(vec!["192.0.2.1".to_string()]),
+            min_ttl: Some(60),
+            max_ttl: Some(120),
+            ..Default::default()
+        };
+        let outcome = execute(&probe).await;
+        insta::assert_snapshot!(outcome.message.replace("; ", "\n"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_probe_tcp() {
+        let nameserver = stub_nameserver().await;
+
+        let mut probe = dns_probe(nameserver, "example.test", DnsRecordType::A);
+        probe.protocol = DnsProtocol::Tcp;
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+        let phases = outcome.phase_list();
+        assert_eq!(phases, [Phase::Connect, Phase::Dns]);
+        let timed = outcome.phases.iter().map(|(_, duration)| *duration).sum();
+        assert!(outcome.duration >= timed, "the connection is timed once");
+
+        // the answer does not fit in a UDP datagram, the query is retried over TCP
+        let mut probe = dns_probe(nameserver, "big.example.test", DnsRecordType::A);
+        probe.assertions.min_answers = Some(200);
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+    }
+
+    #[test]
+    fn test_resolv_conf_nameserver() {
+        let contents = "search default.svc.cluster.local svc.cluster.local\n\
+                        nameserver 10.96.0.10\n\
+                        nameserver 10.96.0.11\n\
+                        options ndots:5\n";
+        assert_eq!(
+            resolv_conf_nameserver(contents),
+            Some("10.96.0.10:53".parse().unwrap())
+        );
+        assert_eq!(resolv_conf_nameserver("options ndots:5\n"), None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::probe::{DnsRecordType, DnsResponseCode};
+
+/// The largest answer over UDP the worker accepts, advertised through EDNS
+pub(super) const MAX_UDP_SIZE: u16 = 4096;
+
+const HEADER_SIZE: usize = 12;
+/// The message is a response
+const FLAG_QR: u16 = 0x8000;
+/// The message was truncated to fit in a UDP datagram
+const FLAG_TC: u16 = 0x0200;
+/// Recursion desired
+const FLAG_RD: u16 = 0x0100;
+const CLASS_IN: u16 = 1;
+const TYPE_OPT: u16 = 41;
+/// How many compression pointers a name can follow, to stop on loops
+const MAX_POINTERS: usize = 32;
+
+pub(super) fn type_code(record_type: DnsRecordType) -> u16 {
+    match record_type {
+        DnsRecordType::A => 1,
+        DnsRecordType::Cname => 5,
+        DnsRecordType::Mx => 15,
+        DnsRecordType::Txt => 16,
+        DnsRecordType::Aaaa => 28,
+        DnsRecordType::Srv => 33,
+        DnsRecordType::Caa => 257,
+    }
+}
+
+pub(super) fn type_name(record_type: DnsRecordType) -> &'static str {
+    match record_type {
+        DnsRecordType::A => "A",
+        DnsRecordType::Aaaa => "AAAA",
+        DnsRecordType::Cname => "CNAME",
+        DnsRecordType::Mx => "MX",
+        DnsRecordType::Txt => "TXT",
+        DnsRecordType::Srv => "SRV",
+        DnsRecordType::Caa => "CAA",
+    }
+}
+
+pub(super) fn rcode(code: DnsResponseCode) -> u8 {
+    match code {
+        DnsResponseCode::NoError => 0,
+        DnsResponseCode::FormErr => 1,
+        DnsResponseCode::ServFail => 2,
+        DnsResponseCode::NxDomain => 3,
+        DnsResponseCode::NotImp => 4,
+        DnsResponseCode::Refused => 5,
+    }
+}
+
+pub(super) fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        other => format!("RCODE{other}"),
+    }
+}
+
+/// A record of the answer section, with its data in the zone file format
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Record {
+    pub type_code: u16,
+    pub ttl: u32,
+    pub value: String,
+}
+
+/// The parts of a response the probe checks
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Response {
+    pub id: u16,
+    pub rcode: u8,
+    pub truncated: bool,
+    pub answers: Vec<Record>,
+}
+
+/// Appends `name` in the wire format, without compression
+pub(super) fn encode_name(name: &str, buffer: &mut Vec<u8>) -> Result<(), String> {
+    let labels = name.strip_suffix('.').unwrap_or(name);
+    if labels.len() > 253 {
+        return Err(format!("{name} is longer than 253 characters"));
+    }
+    for label in labels.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!(
+                "{name} has a label that is empty or longer than 63 characters"
+            ));
+        }
+        buffer.push(label.len() as u8);
+        buffer.extend_from_slice(label.as_bytes());
+    }
+    buffer.push(0);
+    Ok(())
+}
+
+/// A recursive query for the records of `name`, advertising `MAX_UDP_SIZE` through EDNS
+pub(super) fn encode_query(
+    id: u16,
+    name: &str,
+    record_type: DnsRecordType,
+) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(HEADER_SIZE + name.len() + 16);
+    for field in [id, FLAG_RD, 1, 0, 0, 1] {
+        query.extend_from_slice(&field.to_be_bytes());
+    }
+    encode_name(name, &mut query)?;
+    query.extend_from_slice(&type_code(record_type).to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // the OPT pseudo-record: root name, type, UDP size, extended rcode and flags, no data
+    query.push(0);
+    query.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    query.extend_from_slice(&MAX_UDP_SIZE.to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+    Ok(query)
+}
+
+/// Reads a response, skipping its question and keeping its answer section
+pub(super) fn parse_response(bytes: &[u8]) -> Result<Response, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    if flags & FLAG_QR == 0 {
+        return Err("the message is not a response".to_string());
+    }
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    reader.take(4)?;
+
+    for _ in 0..questions {
+        reader.name()?;
+        reader.take(4)?;
+    }
+    let answers = (0..answers)
+        .map(|_| reader.record())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Response {
+        id,
+        rcode: (flags & 0x000f) as u8,
+        truncated: flags & FLAG_TC != 0,
+        answers,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| "the response is truncated".to_string())?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name, in lowercase with a trailing dot
+    fn name(&mut self) -> Result<String, String> {
+        let mut labels = Vec::new();
+        let mut position = self.position;
+        let mut end = None;
+        let mut pointers = 0;
+        loop {
+            let length = *self
+                .bytes
+                .get(position)
+                .ok_or_else(|| "the response is truncated".to_string())?
+                as usize;
+            match length & 0xc0 {
+                0x00 if length == 0 => {
+                    position += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self
+                        .bytes
+                        .get(position + 1..position + 1 + length)
+                        .ok_or_else(|| "the response is truncated".to_string())?;
+                    labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                    position += 1 + length;
+                }
+                0xc0 => {
+                    let low = *self
+                        .bytes
+                        .get(position + 1)
+                        .ok_or_else(|| "the response is truncated".to_string())?;
+                    end.get_or_insert(position + 2);
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err("the response has a compression loop".to_string());
+                    }
+                    position = ((length & 0x3f) << 8) | low as usize;
+                }
+                _ => return Err("the response has an unsupported label type".to_string()),
+            }
+        }
+        self.position = end.unwrap_or(position);
+
+        if labels.is_empty() {
+            return Ok(".".to_string());
+        }
+        Ok(format!("{}.", labels.join(".")))
+    }
+
+    fn record(&mut self) -> Result<Record, String> {
+        self.name()?;
+        let type_code = self.u16()?;
+        self.take(2)?;
+        let ttl = self.u32()?;
+        let length = self.u16()? as usize;
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err("the response is truncated".to_string());
+        }
+
+        let value = match type_code {
+            1 if length == 4 => {
+                let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+                Ipv4Addr::from(bytes).to_string()
+            }
+            28 if length == 16 => {
+                let bytes: [u8; 16] = self.take(16)?.try_into().unwrap();
+                Ipv6Addr::from(bytes).to_string()
+            }
+            5 => self.name()?,
+            15 => {
+                let preference = self.u16()?;
+                format!("{preference} {}", self.name()?)
+            }
+            16 => {
+                let mut text = String::new();
+                while self.position < end {
+                    let length = self.u8()? as usize;
+                    text.push_str(&String::from_utf8_lossy(self.take(length)?));
+                }
+                text
+            }
+            33 => {
+                let priority = self.u16()?;
+                let weight = self.u16()?;
+                let port = self.u16()?;
+                format!("{priority} {weight} {port} {}", self.name()?)
+            }
+            257 if length >= 2 => {
+                let flags = self.u8()?;
+                let tag_length = self.u8()? as usize;
+                let tag = String::from_utf8_lossy(self.take(tag_length)?).into_owned();
+                let value = self.take(end.saturating_sub(self.position))?;
+                format!("{flags} {tag} \"{}\"", String::from_utf8_lossy(value))
+            }
+            _ => format!("<{length} bytes>"),
+        };
+        if self.position > end {
+            return Err(format!(
+                "the data of a record of type {type_code} is malformed"
+            ));
+        }
+        self.position = end;
+
+        Ok(Record {
+            type_code,
+            ttl,
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "Example.com.", DnsRecordType::Mx).unwrap();
+        assert_eq!(
+            query,
+            [
+                &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1][..],
+                b"\x07Example\x03com\x00",
+                &[0, 15, 0, 1],
+                &[0, 0, 41, 0x10, 0x00, 0, 0, 0, 0, 0, 0],
+            ]
+            .concat()
+        );
+
+        assert!(encode_query(1, "example..com", DnsRecordType::A).is_err());
+        let long = format!("{}.com", "a".repeat(64));
+        assert!(encode_query(1, &long, DnsRecordType::A).is_err());
+    }
+
+    #[test]
+    fn test_parse_malformed_responses() {
+        let header = [0x12, 0x34, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+
+        // a query instead of a response
+        let mut query = header;
+        query[2] = 0x01;
+        assert!(parse_response(&query).is_err());
+
+        // an answer pointing to itself
+        let looping = [&header[..], &[0xc0, 12]].concat();
+        assert_eq!(
+            parse_response(&looping).unwrap_err(),
+            "the response has a compression loop"
+        );
+
+        // an answer cut in the middle of its data
+        let cut = [&header[..], &[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0]].concat();
+        assert_eq!(
+            parse_response(&cut).unwrap_err(),
+            "the response is truncated"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    future::Future,
+    net::Ipv6Addr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http::{Request, Response, Uri, uri::PathAndQuery};
+use hyper::{body::Incoming, client::conn::http2::SendRequest};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Instant,
+};
+use tokio_rustls::TlsConnector;
+use tonic::{
+    body::Body,
+    client::Grpc,
+    codec::ProstCodec,
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+};
+
+use crate::{
+    executor::{Phase, ProbeOutcome, net, tls},
+    probe::GrpcProbe,
+};
+
+const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// `grpc.health.v1.HealthCheckRequest`
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckRequest {
+    #[prost(string, tag = "1")]
+    service: String,
+}
+
+/// `grpc.health.v1.HealthCheckResponse`
+#[derive(Clone, PartialEq, prost::Message)]
+struct HealthCheckResponse {
+    #[prost(enumeration = "ServingStatus", tag = "1")]
+    status: i32,
+}
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    ServiceUnknown = 3,
+}
+
+impl ServingStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ServingStatus::Unknown => "UNKNOWN",
+            ServingStatus::Serving => "SERVING",
+            ServingStatus::NotServing => "NOT_SERVING",
+            ServingStatus::ServiceUnknown => "SERVICE_UNKNOWN",
+        }
+    }
+}
+
+/// Runs a gRPC health probe once
+pub async fn execute(probe: &GrpcProbe) -> ProbeOutcome {
+    ProbeOutcome::measure(async |phases| run(probe, phases).await).await
+}
+
+async fn run(probe: &GrpcProbe, phases: &mut Vec<(Phase, Duration)>) -> Result<String, String> {
+    let addresses = net::resolve(&probe.host, probe.port, phases).await?;
+    let stream = net::connect(&addresses, phases).await?;
+
+    let host = probe.host.trim_start_matches('[').trim_end_matches(']');
+    let authority = match host.parse::<Ipv6Addr>() {
+        Ok(ip) => format!("[{ip}]:{}", probe.port),
+        Err(_) => format!("{host}:{}", probe.port),
+    };
+    if !probe.tls {
+        return check(stream, format!("http://{authority}"), probe, phases).await;
+    }
+
+    let server_name = ServerName::try_from(host.to_string())
+        .map_err(|e| format!("invalid server name {host}: {e}"))?;
+    let started = Instant::now();
+    let stream = TlsConnector::from(tls::client_config(&[b"h2"]))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| format!("TLS handshake failed: {e}"))?;
+    net::record_phase(phases, Phase::Tls, started.elapsed());
+    check(stream, format!("https://{authority}"), probe, phases).await
+}
+
+/// Calls `Health/Check` over an established connection
+async fn check<T>(
+    stream: T,
+    origin: String,
+    probe: &GrpcProbe,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<String, String>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let origin = origin
+        .parse::<Uri>()
+        .map_err(|e| format!("invalid origin {origin}: {e}"))?;
+    let (sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(|e| format!("HTTP/2 handshake failed: {e}"))?;
+    tokio::spawn(connection);
+    let mut client = Grpc::with_origin(Connection(sender), origin);
+
+    let service = probe.service.clone().unwrap_or_default();
+    let mut request = tonic::Request::new(HealthCheckRequest {
+        service: service.clone(),
+    });
+    for (key, value) in &probe.metadata {
+        let key = AsciiMetadataKey::from_bytes(key.as_bytes())
+            .map_err(|e| format!("invalid metadata key {key}: {e}"))?;
+        let value = AsciiMetadataValue::try_from(value.as_str())
+            .map_err(|e| format!("invalid value of metadata {key}: {e}"))?;
+        request.metadata_mut().insert(key, value);
+    }
+
+    let started = Instant::now();
+    client
+        .ready()
+        .await
+        .map_err(|e| format!("the connection is not ready: {e}"))?;
+    let response = client
+        .unary::<_, HealthCheckResponse, _>(
+            request,
+            PathAndQuery::from_static(CHECK_PATH),
+            ProstCodec::default(),
+        )
+        .await;
+    net::record_phase(phases, Phase::FirstByte, started.elapsed());
+
+    let target = match service.as_str() {
+        "" => "the server".to_string(),
+        service => format!("service \"{service}\""),
+    };
+    let status = response
+        .map_err(|status| {
+            format!(
+                "health check failed with status {:?}: {}",
+                status.code(),
+                status.message()
+            )
+        })?
+        .into_inner()
+        .status();
+    match status {
+        ServingStatus::Serving => Ok(format!("{target} is SERVING")),
+        status => Err(format!("{target} is {}", status.as_str())),
+    }
+}
+
+/// A HTTP/2 connection as the service `Grpc` sends its requests to
+struct Connection(SendRequest<Body>);
+
+impl tower_service::Service<Request<Body>> for Connection {
+    type Response = Response<Incoming>;
+    type Error = hyper::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, context: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(context)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        Box::pin(self.0.send_request(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, future::Ready, net::SocketAddr};
+
+    use axum::{Router, extract::Request as AxumRequest, routing::post};
+    use tonic::{Status, server::UnaryService};
+
+    use super::*;
+
+    /// Answers health checks from the status of each service, requiring a token
+    #[derive(Clone)]
+    struct Health(BTreeMap<&'static str, ServingStatus>);
+
+    impl UnaryService<HealthCheckRequest> for Health {
+        type Response = HealthCheckResponse;
+        type Future = Ready<Result<tonic::Response<HealthCheckResponse>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<HealthCheckRequest>) -> Self::Future {
+            let authorized = request
+                .metadata()
+                .get("authorization")
+                .is_some_and(|token| token == "Bearer stand-in");
+            let response = match self.0.get(request.get_ref().service.as_str()) {
+                _ if !authorized => Err(Status::unauthenticated("missing token")),
+                Some(status) => Ok(tonic::Response::new(HealthCheckResponse {
+                    status: *status as i32,
+                })),
+                None => Err(Status::not_found("unknown service")),
+            };
+            std::future::ready(response)
+        }
+    }
+
+    /// Serves the health service over HTTP/2 with prior knowledge
+    async fn stand_in() -> SocketAddr {
+        let health = Health(BTreeMap::from([
+            ("", ServingStatus::Serving),
+            ("payments", ServingStatus::Serving),
+            ("ledger", ServingStatus::NotServing),
+        ]));
+        let app = Router::new().route(
+            CHECK_PATH,
+            post(|request: AxumRequest| async move {
+                tonic::server::Grpc::new(ProstCodec::default())
+                    .unary(health, request)
+                    .await
+            }),
+        );
+        net::serve_http2(app).await
+    }
+
+    fn grpc_probe(address: SocketAddr, service: Option<&str>) -> GrpcProbe {
+        GrpcProbe {
+            host: address.ip().to_string(),
+            port: address.port(),
+            service: service.map(str::to_string),
+            tls: false,
+            metadata: BTreeMap::from([(
+                "authorization".to_string(),
+                "Bearer stand-in".to_string(),
+            )]),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_grpc_probe() {
+        let address = stand_in().await;
+
+        let outcome = execute(&grpc_probe(address, None)).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert_eq!(outcome.message, "the server is SERVING");
+        let phases = outcome.phase_list();
+        assert_eq!(phases, [Phase::Connect, Phase::FirstByte]);
+
+        for (service, success, message) in [
+            (Some("payments"), true, "service \"payments\" is SERVING"),
+            (Some("ledger"), false, "service \"ledger\" is NOT_SERVING"),
+            (
+                Some("unknown"),
+                false,
+                "health check failed with status NotFound: unknown service",
+            ),
+        ] {
+            let outcome = execute(&grpc_probe(address, service)).await;
+            assert_eq!(outcome.success, success);
+            assert_eq!(outcome.message, message);
+        }
+
+        let mut probe = grpc_probe(address, None);
+        probe.metadata.clear();
+        let outcome = execute(&probe).await;
+        assert_eq!(
+            outcome.message,
+            "health check failed with status Unauthenticated: missing token"
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_grpc_probe_tls_to_plaintext() {
+        let address = stand_in().await;
+        let mut probe = grpc_probe(address, None);
+        probe.tls = true;
+        let outcome = execute(&probe).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.starts_with("TLS handshake failed"),
+            "{}",
+            outcome.message
+        );
+    }
+}
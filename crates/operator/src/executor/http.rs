@@ -0,0 +1,532 @@
+mod assertions;
+
+use std::{net::IpAddr, time::Duration};
+
+pub use assertions::StatusRange;
+use bytes::{Bytes, BytesMut};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Version, header,
+};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Instant,
+};
+use tokio_rustls::TlsConnector;
+use url::{Host, Url};
+
+use crate::{
+    executor::{Phase, ProbeOutcome, net, tls},
+    probe::{HttpProbe, HttpVersion},
+};
+
+/// Only the beginning of larger bodies is checked by the assertions
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const USER_AGENT: &str = concat!("probelet/", env!("CARGO_PKG_VERSION"));
+
+/// The headers dropped when a redirect leaves the origin of the probe, like curl does
+const CREDENTIAL_HEADERS: [HeaderName; 3] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+];
+
+/// The response of the last request of a probe
+pub(super) struct HttpResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Runs a HTTP probe once, following redirects if it allows it.
+///
+/// The durations of the phases are summed over the redirects. The credentials among the
+/// headers of the probe are not sent anymore once a redirect changes the scheme, host or port.
+pub async fn execute(probe: &HttpProbe) -> ProbeOutcome {
+    let start = Instant::now();
+    let mut phases = Vec::new();
+    let mut method = match Method::from_bytes(probe.method.as_bytes()) {
+        Ok(method) => method,
+        Err(e) => return ProbeOutcome::failure(Duration::ZERO, format!("invalid method: {e}")),
+    };
+    let mut url = match Url::parse(&probe.url) {
+        Ok(url) => url,
+        Err(e) => return ProbeOutcome::failure(Duration::ZERO, format!("invalid url: {e}")),
+    };
+    let mut headers = match request_headers(probe) {
+        Ok(headers) => headers,
+        Err(message) => return ProbeOutcome::failure(Duration::ZERO, message),
+    };
+    let mut body = probe.body.clone();
+    let mut redirects = 0;
+
+    loop {
+        let response =
+            match send(&method, &url, &headers, body.as_deref(), probe, &mut phases).await {
+                Ok(response) => response,
+                Err(message) => {
+                    return ProbeOutcome::failure(start.elapsed(), message).with_phases(phases);
+                }
+            };
+
+        let location = response
+            .headers
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok());
+        if probe.follow_redirects
+            && is_redirect(response.status)
+            && let Some(location) = location
+        {
+            if redirects >= probe.max_redirects {
+                let message = format!("stopped after {redirects} redirects");
+                return ProbeOutcome::failure(start.elapsed(), message).with_phases(phases);
+            }
+            let next = match url.join(location) {
+                Ok(next) => next,
+                Err(e) => {
+                    let message = format!("invalid redirect to {location}: {e}");
+                    return ProbeOutcome::failure(start.elapsed(), message).with_phases(phases);
+                }
+            };
+            if next.origin() != url.origin() {
+                for name in CREDENTIAL_HEADERS {
+                    headers.remove(name);
+                }
+            }
+            url = next;
+            redirects += 1;
+            let see_other = response.status == StatusCode::SEE_OTHER && method != Method::HEAD;
+            let moved = matches!(response.status.as_u16(), 301 | 302) && method == Method::POST;
+            if see_other || moved {
+                method = Method::GET;
+                body = None;
+            }
+            continue;
+        }
+
+        let duration = start.elapsed();
+        let failures = assertions::check(&probe.assertions, &response, duration);
+        let outcome = if failures.is_empty() {
+            ProbeOutcome::success(duration, format!("{} from {url}", response.status))
+        } else {
+            ProbeOutcome::failure(duration, failures.join("; "))
+        };
+        return outcome.with_phases(phases);
+    }
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+}
+
+/// The user agent and the headers of the probe, which can override it
+fn request_headers(probe: &HttpProbe) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
+    for (name, value) in &probe.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("invalid header name {name}: {e}"))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| format!("invalid value of header {name}: {e}"))?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// Sends a request on a new connection, recording the duration of each phase
+async fn send(
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Option<&str>,
+    probe: &HttpProbe,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<HttpResponse, String> {
+    let host = url.host().ok_or_else(|| format!("{url} has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| format!("{url} has no port"))?;
+    let addresses = net::resolve(&host.to_string(), port, phases).await?;
+    let stream = net::connect(&addresses, phases).await?;
+
+    if url.scheme() == "https" {
+        let server_name = match host {
+            Host::Domain(domain) => ServerName::try_from(domain.to_string())
+                .map_err(|e| format!("invalid server name {domain}: {e}"))?,
+            Host::Ipv4(ip) => ServerName::IpAddress(IpAddr::V4(ip).into()),
+            Host::Ipv6(ip) => ServerName::IpAddress(IpAddr::V6(ip).into()),
+        };
+        let started = Instant::now();
+        let stream = TlsConnector::from(tls::client_config(alpn_protocols(probe.http_version)))
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| format!("TLS handshake failed: {e}"))?;
+        net::record_phase(phases, Phase::Tls, started.elapsed());
+
+        let http2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+        if probe.http_version == HttpVersion::Http2 && !http2 {
+            return Err("the server does not support HTTP/2".to_string());
+        }
+        exchange(stream, http2, method, url, headers, body, phases).await
+    } else {
+        let http2 = probe.http_version == HttpVersion::Http2;
+        exchange(stream, http2, method, url, headers, body, phases).await
+    }
+}
+
+/// The protocols negotiated through ALPN for `version`
+fn alpn_protocols(version: HttpVersion) -> &'static [&'static [u8]] {
+    match version {
+        HttpVersion::Auto => &[b"h2", b"http/1.1"],
+        HttpVersion::Http1 => &[b"http/1.1"],
+        HttpVersion::Http2 => &[b"h2"],
+    }
+}
+
+/// Sends the request over an established connection and reads the response
+async fn exchange<T>(
+    stream: T,
+    http2: bool,
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Option<&str>,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<HttpResponse, String>
+where
+    T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let request = request(http2, method, url, headers, body)?;
+    let io = TokioIo::new(stream);
+
+    let started = Instant::now();
+    let response = if http2 {
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), io)
+                .await
+                .map_err(|e| format!("HTTP/2 handshake failed: {e}"))?;
+        tokio::spawn(connection);
+        sender.send_request(request).await
+    } else {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(io)
+            .await
+            .map_err(|e| format!("HTTP/1.1 handshake failed: {e}"))?;
+        tokio::spawn(connection);
+        sender.send_request(request).await
+    }
+    .map_err(|e| format!("request failed: {e}"))?;
+    net::record_phase(phases, Phase::FirstByte, started.elapsed());
+
+    read_response(response).await
+}
+
+/// Builds the request, with an absolute URI for HTTP/2 and a `Host` header for HTTP/1.1
+fn request(
+    http2: bool,
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Option<&str>,
+) -> Result<Request<Full<Bytes>>, String> {
+    let authority = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+
+    let mut builder = Request::builder().method(method.clone());
+    builder = if http2 {
+        builder
+            .version(Version::HTTP_2)
+            .uri(format!("{}://{authority}{path}", url.scheme()))
+    } else {
+        builder
+            .version(Version::HTTP_11)
+            .uri(path)
+            .header(header::HOST, authority)
+    };
+
+    builder
+        .headers_mut()
+        .ok_or_else(|| format!("invalid request to {url}"))?
+        .extend(headers.clone());
+
+    let body = body.map(|body| Bytes::from(body.to_string()));
+    builder
+        .body(Full::new(body.unwrap_or_default()))
+        .map_err(|e| format!("invalid request to {url}: {e}"))
+}
+
+/// Reads the status, headers and the first `MAX_BODY_SIZE` bytes of the body
+async fn read_response(response: Response<Incoming>) -> Result<HttpResponse, String> {
+    let (parts, mut incoming) = response.into_parts();
+    let mut body = BytesMut::new();
+    while body.len() < MAX_BODY_SIZE {
+        let Some(frame) = incoming.frame().await else {
+            break;
+        };
+        let frame = frame.map_err(|e| format!("failed to read the body: {e}"))?;
+        if let Ok(data) = frame.into_data() {
+            let remaining = MAX_BODY_SIZE - body.len();
+            body.extend_from_slice(&data[..data.len().min(remaining)]);
+        }
+    }
+
+    Ok(HttpResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: body.freeze(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, net::SocketAddr};
+
+    use axum::{
+        Json, Router,
+        extract::Query,
+        http::{HeaderMap, StatusCode, Version},
+        response::{IntoResponse, Redirect},
+        routing::{get, post},
+    };
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::probe::{HeaderAssertion, HttpAssertions, JsonPathAssertion};
+
+    async fn stand_in() -> SocketAddr {
+        let app = Router::new()
+            .route(
+                "/health",
+                get(|| async {
+                    (
+                        [("x-probe", "stand-in")],
+                        Json(json!({"status": "ok", "checks": [{"name": "db", "up": true}]})),
+                    )
+                }),
+            )
+            .route("/moved", get(|| async { Redirect::temporary("/health") }))
+            .route("/loop", get(|| async { Redirect::temporary("/loop") }))
+            .route(
+                "/redirect",
+                get(|Query(query): Query<BTreeMap<String, String>>| async move {
+                    Redirect::temporary(&query["to"])
+                }),
+            )
+            .route(
+                "/credentials",
+                get(|headers: HeaderMap| async move {
+                    ["authorization", "cookie", "proxy-authorization", "x-token"]
+                        .into_iter()
+                        .filter(|name| headers.contains_key(*name))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }),
+            )
+            .route(
+                "/echo",
+                post(|headers: HeaderMap, body: String| async move {
+                    let token = headers.get("x-token").cloned().unwrap();
+                    ([("x-token", token)], body)
+                }),
+            )
+            .route(
+                "/version",
+                get(|version: Version| async move { format!("{version:?}") }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    StatusCode::NO_CONTENT.into_response()
+                }),
+            );
+        net::serve(app).await
+    }
+
+    fn http_probe(url: String) -> HttpProbe {
+        HttpProbe {
+            url,
+            ..Default::default()
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_assertions() {
+        let address = stand_in().await;
+        let mut probe = http_probe(format!("http://localhost:{}/health", address.port()));
+        probe.assertions = HttpAssertions {
+            status_codes: vec!["200-204".to_string()],
+            headers: vec![HeaderAssertion {
+                name: "X-Probe".to_string(),
+                equals: Some("stand-in".to_string()),
+                matches: Some("^stand".to_string()),
+            }],
+            body_contains: Some("\"ok\"".to_string()),
+            body_matches: Some(r#""status":\s*"ok""#.to_string()),
+            json_path: vec![
+                JsonPathAssertion {
+                    path: "$.status".to_string(),
+                    equals: "ok".to_string(),
+                },
+                JsonPathAssertion {
+                    path: "$.checks[?(@.name == 'db')].up".to_string(),
+                    equals: "true".to_string(),
+                },
+            ],
+            max_latency_ms: Some(5000),
+        };
+
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+        let phases = outcome.phase_list();
+        assert_eq!(phases, [Phase::Dns, Phase::Connect, Phase::FirstByte]);
+
+        probe.assertions = HttpAssertions {
+            status_codes: vec!["3xx".to_string(), "404".to_string()],
+            headers: vec![
+                HeaderAssertion {
+                    name: "x-probe".to_string(),
+                    equals: Some("other".to_string()),
+                    matches: Some("^other".to_string()),
+                },
+                HeaderAssertion {
+                    name: "x-missing".to_string(),
+                    equals: None,
+                    matches: None,
+                },
+            ],
+            body_contains: Some("down".to_string()),
+            body_matches: Some("^<html>".to_string()),
+            json_path: vec![
+                JsonPathAssertion {
+                    path: "$.status".to_string(),
+                    equals: "down".to_string(),
+                },
+                JsonPathAssertion {
+                    path: "$.missing".to_string(),
+                    equals: "1".to_string(),
+                },
+            ],
+            max_latency_ms: None,
+        };
+        let outcome = execute(&probe).await;
+        assert!(!outcome.success);
+        insta::assert_snapshot!(outcome.message.replace("; ", "\n"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_redirects() {
+        let address = stand_in().await;
+        let mut probe = http_probe(format!("http://{address}/moved"));
+
+        let outcome = execute(&probe).await;
+        assert_eq!(outcome.message, "status 307 is not one of 2xx");
+
+        probe.follow_redirects = true;
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert!(outcome.message.ends_with("/health"), "{}", outcome.message);
+
+        probe.url = format!("http://{address}/loop");
+        let outcome = execute(&probe).await;
+        assert_eq!(outcome.message, "stopped after 5 redirects");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_redirect_credentials() {
+        let address = stand_in().await;
+        let other = stand_in().await;
+        let mut probe = http_probe(String::new());
+        probe.follow_redirects = true;
+        probe.headers = ["authorization", "cookie", "proxy-authorization", "x-token"]
+            .into_iter()
+            .map(|name| (name.to_string(), "secret".to_string()))
+            .collect();
+
+        for (to, sent) in [
+            (
+                "/credentials".to_string(),
+                "authorization cookie proxy-authorization x-token",
+            ),
+            (format!("http://{other}/credentials"), "x-token"),
+        ] {
+            probe.url = format!("http://{address}/redirect?to={to}");
+            probe.assertions.body_matches = Some(format!("^{sent}$"));
+            let outcome = execute(&probe).await;
+            assert!(outcome.success, "{to}: {}", outcome.message);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_request() {
+        let address = stand_in().await;
+        let mut probe = http_probe(format!("http://{address}/echo"));
+        probe.method = "POST".to_string();
+        probe.body = Some("hello".to_string());
+        probe.headers = BTreeMap::from([("x-token".to_string(), "secret".to_string())]);
+        probe.assertions.body_contains = Some("hello".to_string());
+        probe.assertions.headers = vec![HeaderAssertion {
+            name: "x-token".to_string(),
+            equals: Some("secret".to_string()),
+            matches: None,
+        }];
+
+        let outcome = execute(&probe).await;
+        assert!(outcome.success, "{}", outcome.message);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_version() {
+        let address = stand_in().await;
+        for (version, expected) in [
+            (HttpVersion::Auto, "HTTP/1.1"),
+            (HttpVersion::Http1, "HTTP/1.1"),
+            (HttpVersion::Http2, "HTTP/2.0"),
+        ] {
+            let mut probe = http_probe(format!("http://{address}/version"));
+            probe.http_version = version;
+            probe.assertions.body_contains = Some(expected.to_string());
+            let outcome = execute(&probe).await;
+            assert!(outcome.success, "{version:?}: {}", outcome.message);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_probe_failures() {
+        let address = stand_in().await;
+        let mut probe = http_probe(format!("http://{address}/slow"));
+        probe.assertions.max_latency_ms = Some(50);
+        let outcome = execute(&probe).await;
+        assert!(
+            outcome
+                .message
+                .contains("more than the max latency of 50ms"),
+            "{}",
+            outcome.message
+        );
+
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let outcome = execute(&http_probe(format!("http://{closed}/"))).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.starts_with("failed to connect"),
+            "{}",
+            outcome.message
+        );
+        assert_eq!(outcome.phases, []);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{str::FromStr, time::Duration};
+
+use jsonpath_rust::{JsonPath, JsonPathValue};
+use regex::Regex;
+use serde_json::Value;
+
+use super::HttpResponse;
+use crate::probe::HttpAssertions;
+
+/// Accepted status codes, parsed from a code (`200`), a range (`200-299`) or a class (`2xx`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusRange {
+    start: u16,
+    end: u16,
+}
+
+impl StatusRange {
+    pub fn contains(&self, status: u16) -> bool {
+        (self.start..=self.end).contains(&status)
+    }
+}
+
+impl FromStr for StatusRange {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{pattern} is not a status code, range or class like 2xx");
+        let code = |value: &str| {
+            value
+                .parse::<u16>()
+                .ok()
+                .filter(|code| (100..=599).contains(code))
+                .ok_or_else(invalid)
+        };
+
+        if let Some(class) = pattern.to_ascii_lowercase().strip_suffix("xx") {
+            let start = code(&format!("{class}00"))?;
+            return Ok(Self {
+                start,
+                end: start + 99,
+            });
+        }
+        let (start, end) = match pattern.split_once('-') {
+            Some((start, end)) => (code(start.trim())?, code(end.trim())?),
+            None => (code(pattern)?, code(pattern)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// Checks the response against the assertions, returning why each failing one failed
+pub(super) fn check(
+    assertions: &HttpAssertions,
+    response: &HttpResponse,
+    latency: Duration,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    let status = response.status.as_u16();
+    let accepted = assertions.status_codes.iter().any(|pattern| {
+        pattern
+            .parse::<StatusRange>()
+            .is_ok_and(|range| range.contains(status))
+    });
+    if !accepted {
+        failures.push(format!(
+            "status {status} is not one of {}",
+            assertions.status_codes.join(", ")
+        ));
+    }
+
+    for header in &assertions.headers {
+        let values = response
+            .headers
+            .get_all(header.name.as_str())
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            failures.push(format!("header {} is missing", header.name));
+            continue;
+        }
+        if let Some(expected) = &header.equals
+            && !values.contains(expected)
+        {
+            failures.push(format!(
+                "header {} is \"{}\", expected \"{expected}\"",
+                header.name,
+                values.join(", ")
+            ));
+        }
+        if let Some(pattern) = &header.matches {
+            match Regex::new(pattern) {
+                Ok(regex) if values.iter().any(|value| regex.is_match(value)) => {}
+                Ok(_) => failures.push(format!(
+                    "header {} is \"{}\", which does not match /{pattern}/",
+                    header.name,
+                    values.join(", ")
+                )),
+                Err(e) => failures.push(format!("invalid regex /{pattern}/: {e}")),
+            }
+        }
+    }
+
+    let body = String::from_utf8_lossy(&response.body);
+    if let Some(expected) = &assertions.body_contains
+        && !body.contains(expected.as_str())
+    {
+        failures.push(format!("body does not contain \"{expected}\""));
+    }
+    if let Some(pattern) = &assertions.body_matches {
+        match Regex::new(pattern) {
+            Ok(regex) if regex.is_match(&body) => {}
+            Ok(_) => failures.push(format!("body does not match /{pattern}/")),
+            Err(e) => failures.push(format!("invalid regex /{pattern}/: {e}")),
+        }
+    }
+
+    if !assertions.json_path.is_empty() {
+        match serde_json::from_slice::<Value>(&response.body) {
+            Ok(json) => {
+                for assertion in &assertions.json_path {
+                    if let Err(failure) = check_json_path(&json, &assertion.path, &assertion.equals)
+                    {
+                        failures.push(failure);
+                    }
+                }
+            }
+            Err(e) => failures.push(format!("body is not JSON: {e}")),
+        }
+    }
+
+    if let Some(max) = assertions.max_latency_ms
+        && latency > Duration::from_millis(max)
+    {
+        failures.push(format!(
+            "took {}ms, more than the max latency of {max}ms",
+            latency.as_millis()
+        ));
+    }
+
+    failures
+}
+
+/// Whether a value selected by `path` equals `expected`, strings being compared without
+/// their quotes
+fn check_json_path(json: &Value, path: &str, expected: &str) -> Result<(), String> {
+    let path_expr =
+        JsonPath::<Value>::from_str(path).map_err(|e| format!("invalid JSONPath {path}: {e}"))?;
+    let values = path_expr
+        .find_slice(json)
+        .into_iter()
+        .filter(|value| !matches!(value, JsonPathValue::NoValue))
+        .map(|value| match value.to_data() {
+            Value::String(text) => text,
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if values.is_empty() {
+        return Err(format!("{path} selects nothing"));
+    }
+    if !values.iter().any(|value| value == expected) {
+        return Err(format!(
+            "{path} is {}, expected {expected}",
+            values.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_range() {
+        for (pattern, inside, outside) in [
+            ("200", 200, 201),
+            ("2xx", 299, 300),
+            ("4XX", 404, 399),
+            ("200-204", 204, 205),
+        ] {
+            let range = pattern.parse::<StatusRange>().unwrap();
+            assert!(range.contains(inside), "{pattern} contains {inside}");
+            assert!(!range.contains(outside), "{pattern} excludes {outside}");
+        }
+
+        for pattern in ["", "20", "600", "6xx", "x", "299-200", "ok"] {
+            assert!(pattern.parse::<StatusRange>().is_err(), "{pattern}");
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, time::Instant};
+
+use crate::{
+    executor::{Phase, ProbeOutcome, net},
+    probe::{IcmpAssertions, IcmpProbe},
+};
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// The payload of the echo requests, the 56 bytes `ping` sends by default
+const PAYLOAD: &[u8; 56] = b"probelet echo request payload, padded to 56 bytes.......";
+
+/// How the echo requests are sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SocketKind {
+    /// An unprivileged ICMP socket, allowed by `net.ipv4.ping_group_range`. The kernel sets
+    /// the identifier of the requests and only passes the replies to them.
+    Datagram,
+    /// A raw socket, which needs `CAP_NET_RAW` and so a worker running as root. It receives
+    /// every ICMP message of the host, with the IP header for IPv4.
+    Raw,
+}
+
+/// The round-trip times of the replies of a run, from which its statistics are computed
+#[derive(Debug, Clone, PartialEq)]
+struct Replies {
+    /// The round-trip time of each echo request, `None` when it got no reply
+    rtts: Vec<Option<Duration>>,
+}
+
+impl Replies {
+    fn received(&self) -> Vec<Duration> {
+        self.rtts.iter().flatten().copied().collect()
+    }
+
+    fn loss_percent(&self) -> f64 {
+        let lost = self.rtts.iter().filter(|rtt| rtt.is_none()).count();
+        lost as f64 * 100. / self.rtts.len() as f64
+    }
+
+    /// The mean absolute difference between the round-trip times of consecutive replies
+    fn jitter(&self) -> Duration {
+        let received = self.received();
+        if received.len() < 2 {
+            return Duration::ZERO;
+        }
+        let total = received
+            .windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]))
+            .sum::<Duration>();
+        total / (received.len() - 1) as u32
+    }
+
+    /// Summarizes the replies like `ping`: `5/5 replies, 0% loss, rtt min/avg/max
+    /// 0.041/0.052/0.070 ms, jitter 0.012 ms`
+    fn summary(&self) -> String {
+        let received = self.received();
+        let mut summary = format!(
+            "{}/{} replies, {}% loss",
+            received.len(),
+            self.rtts.len(),
+            self.loss_percent().round()
+        );
+        if let (Some(min), Some(max)) = (received.iter().min(), received.iter().max()) {
+            let avg = received.iter().sum::<Duration>() / received.len() as u32;
+            summary.push_str(&format!(
+                ", rtt min/avg/max {}/{}/{} ms, jitter {} ms",
+                millis(*min),
+                millis(avg),
+                millis(*max),
+                millis(self.jitter())
+            ));
+        }
+        summary
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.)
+}
+
+/// Runs an ICMP probe once
+pub async fn execute(probe: &IcmpProbe) -> ProbeOutcome {
+    let start = Instant::now();
+    let mut phases = Vec::new();
+    let outcome = match ping(probe, &mut phases).await {
+        Ok((address, replies)) => {
+            let failures = check(&replies, &probe.assertions);
+            let message = format!("{} from {address}", replies.summary());
+            if failures.is_empty() {
+                ProbeOutcome::success(start.elapsed(), message)
+            } else {
+                let message = format!("{}; {message}", failures.join("; "));
+                ProbeOutcome::failure(start.elapsed(), message)
+            }
+        }
+        Err(message) => ProbeOutcome::failure(start.elapsed(), message),
+    };
+    outcome.with_phases(phases)
+}
+
+/// The assertions the replies do not satisfy
+fn check(replies: &Replies, assertions: &IcmpAssertions) -> Vec<String> {
+    let received = replies.received();
+    if received.is_empty() {
+        return vec!["no reply received".to_string()];
+    }
+
+    let mut failures = Vec::new();
+    let loss = replies.loss_percent();
+    if let Some(max) = assertions.max_loss_percent
+        && loss > max
+    {
+        failures.push(format!("loss of {}% exceeds {max}%", loss.round()));
+    }
+    let avg = received.iter().sum::<Duration>() / received.len() as u32;
+    if let Some(max) = assertions.max_avg_rtt_ms
+        && avg > Duration::from_millis(max)
+    {
+        failures.push(format!(
+            "average rtt of {} ms exceeds {max} ms",
+            millis(avg)
+        ));
+    }
+    let slowest = received.iter().max().copied().unwrap_or_default();
+    if let Some(max) = assertions.max_rtt_ms
+        && slowest > Duration::from_millis(max)
+    {
+        failures.push(format!("rtt of {} ms exceeds {max} ms", millis(slowest)));
+    }
+    let jitter = replies.jitter();
+    if let Some(max) = assertions.max_jitter_ms
+        && jitter > Duration::from_millis(max)
+    {
+        failures.push(format!("jitter of {} ms exceeds {max} ms", millis(jitter)));
+    }
+    failures
+}
+
+/// Sends the echo requests `intervalMs` apart, collecting the replies until the last one
+/// arrives or `replyTimeoutMs` after the last request
+async fn ping(
+    probe: &IcmpProbe,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<(IpAddr, Replies), String> {
+    let address = net::resolve(&probe.host, 0, phases)
+        .await?
+        .first()
+        .map(SocketAddr::ip)
+        .ok_or_else(|| format!("{} has no address", probe.host))?;
+    let (socket, kind) = open(address)?;
+    let identifier = rand::random::<u16>();
+
+    let count = usize::from(probe.count);
+    let mut sent = Vec::with_capacity(count);
+    let mut rtts = vec![None; count];
+    let mut interval = tokio::time::interval(Duration::from_millis(probe.interval_ms.into()));
+    let reply_timeout = Duration::from_millis(probe.reply_timeout_ms.into());
+    let mut deadline = None;
+    let mut buffer = [0; 1500];
+    loop {
+        let timeout = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = interval.tick(), if sent.len() < count => {
+                let request = echo_request(address, identifier, sent.len() as u16);
+                socket
+                    .send(&request)
+                    .await
+                    .map_err(|e| format!("failed to send an echo request to {address}: {e}"))?;
+                sent.push(Instant::now());
+                if sent.len() == count {
+                    deadline = Some(Instant::now() + reply_timeout);
+                }
+            }
+            received = socket.recv(&mut buffer) => {
+                let length = received
+                    .map_err(|e| format!("failed to receive the replies of {address}: {e}"))?;
+                let reply = echo_reply(&buffer[..length], address, kind, identifier)
+                    .map(usize::from)
+                    .filter(|sequence| *sequence < sent.len());
+                if let Some(sequence) = reply
+                    && rtts[sequence].is_none()
+                {
+                    rtts[sequence] = Some(sent[sequence].elapsed());
+                    if rtts.iter().all(Option::is_some) {
+                        break;
+                    }
+                }
+            }
+            _ = timeout => break,
+        }
+    }
+    Ok((address, Replies { rtts }))
+}
+
+/// Opens an unprivileged ICMP socket, or a raw one when they are not allowed, connected
+/// to `address` so only its messages are received
+fn open(address: IpAddr) -> Result<(UdpSocket, SocketKind), String> {
+    let (domain, protocol) = match address {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+    };
+    let (socket, kind) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(socket) => (socket, SocketKind::Datagram),
+        Err(e) if matches!(e.kind(), ErrorKind::PermissionDenied) => {
+            let socket = Socket::new(domain, Type::RAW, Some(protocol)).map_err(|e| {
+                format!(
+                    "failed to open an ICMP socket, the group of the worker is not in \
+                     net.ipv4.ping_group_range and raw sockets need a root worker with the \
+                     NET_RAW capability: {e}"
+                )
+            })?;
+            (socket, SocketKind::Raw)
+        }
+        Err(e) => return Err(format!("failed to open an ICMP socket: {e}")),
+    };
+    socket
+        .set_nonblocking(true)
+        .map_err(|e| format!("failed to configure the ICMP socket: {e}"))?;
+    socket
+        .connect(&SocketAddr::new(address, 0).into())
+        .map_err(|e| format!("failed to connect the ICMP socket to {address}: {e}"))?;
+    let socket = UdpSocket::from_std(std::net::UdpSocket::from(socket))
+        .map_err(|e| format!("failed to register the ICMP socket: {e}"))?;
+    Ok((socket, kind))
+}
+
+/// An echo request. The kernel computes the checksum of ICMPv6 messages, and replaces the
+/// identifier on unprivileged sockets.
+fn echo_request(address: IpAddr, identifier: u16, sequence: u16) -> Vec<u8> {
+    let kind = match address {
+        IpAddr::V4(_) => ECHO_REQUEST_V4,
+        IpAddr::V6(_) => ECHO_REQUEST_V6,
+    };
+    let mut request = vec![kind, 0, 0, 0];
+    request.extend_from_slice(&identifier.to_be_bytes());
+    request.extend_from_slice(&sequence.to_be_bytes());
+    request.extend_from_slice(PAYLOAD);
+    if address.is_ipv4() {
+        let checksum = checksum(&request);
+        request[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    request
+}
+
+/// The sequence number of an echo reply to one of the requests of the run
+fn echo_reply(message: &[u8], address: IpAddr, kind: SocketKind, identifier: u16) -> Option<u16> {
+    let message = match (address, kind) {
+        // raw IPv4 sockets receive the IP header, whose length is in its first byte
+        (IpAddr::V4(_), SocketKind::Raw) => {
+            let header_length = usize::from(message.first()? & 0x0f) * 4;
+            message.get(header_length..)?
+        }
+        _ => message,
+    };
+    let reply = match address {
+        IpAddr::V4(_) => ECHO_REPLY_V4,
+        IpAddr::V6(_) => ECHO_REPLY_V6,
+    };
+    if message.len() < 8 || message[0] != reply {
+        return None;
+    }
+    let reply_identifier = u16::from_be_bytes([message[4], message[5]]);
+    if kind == SocketKind::Raw && reply_identifier != identifier {
+        return None;
+    }
+    Some(u16::from_be_bytes([message[6], message[7]]))
+}
+
+/// The internet checksum of RFC 1071
+fn checksum(message: &[u8]) -> u16 {
+    let mut sum = message
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icmp_probe(host: &str, assertions: IcmpAssertions) -> IcmpProbe {
+        IcmpProbe {
+            host: host.to_string(),
+            count: 3,
+            interval_ms: 10,
+            reply_timeout_ms: 1000,
+            assertions,
+        }
+    }
+
+    #[test]
+    fn test_replies() {
+        let millis = Duration::from_millis;
+        let replies = Replies {
+            rtts: vec![Some(millis(10)), None, Some(millis(14)), Some(millis(12))],
+        };
+        assert_eq!(replies.loss_percent(), 25.);
+        assert_eq!(replies.jitter(), millis(3));
+        assert_eq!(
+            replies.summary(),
+            "3/4 replies, 25% loss, rtt min/avg/max 10.000/12.000/14.000 ms, jitter 3.000 ms"
+        );
+
+        let assertions = IcmpAssertions {
+            max_loss_percent: Some(20.),
+            max_avg_rtt_ms: Some(12),
+            max_rtt_ms: Some(13),
+            max_jitter_ms: Some(2),
+        };
+        assert_eq!(
+            check(&replies, &assertions),
+            [
+                "loss of 25% exceeds 20%",
+                "rtt of 14.000 ms exceeds 13 ms",
+                "jitter of 3.000 ms exceeds 2 ms",
+            ]
+        );
+
+        let lost = Replies {
+            rtts: vec![None, None],
+        };
+        assert_eq!(lost.summary(), "0/2 replies, 100% loss");
+        assert_eq!(
+            check(&lost, &IcmpAssertions::default()),
+            ["no reply received"]
+        );
+    }
+
+    #[test]
+    fn test_echo_messages() {
+        let address = IpAddr::from([127, 0, 0, 1]);
+        let request = echo_request(address, 0x1234, 7);
+        assert_eq!(&request[..8], [8, 0, 0xe3, 0x9f, 0x12, 0x34, 0, 7]);
+        assert_eq!(checksum(&request), 0);
+
+        // a raw socket receives the reply behind a 20 bytes IP header
+        let mut reply = request.clone();
+        reply[0] = ECHO_REPLY_V4;
+        let raw = [&[0x45][..], &[0; 19], &reply].concat();
+        assert_eq!(echo_reply(&raw, address, SocketKind::Raw, 0x1234), Some(7));
+        assert_eq!(echo_reply(&raw, address, SocketKind::Raw, 0x4321), None);
+        assert_eq!(
+            echo_reply(&reply, address, SocketKind::Datagram, 0x4321),
+            Some(7)
+        );
+        // its own request, looped back to a raw socket
+        let looped = [&[0x45][..], &[0; 19], &request].concat();
+        assert_eq!(echo_reply(&looped, address, SocketKind::Raw, 0x1234), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_icmp_probe_loopback() {
+        for host in ["127.0.0.1", "::1", "localhost"] {
+            let outcome = execute(&icmp_probe(host, IcmpAssertions::default())).await;
+            assert!(outcome.success, "{host}: {}", outcome.message);
+            assert!(
+                outcome
+                    .message
+                    .starts_with("3/3 replies, 0% loss, rtt min/avg/max "),
+                "{}",
+                outcome.message
+            );
+        }
+
+        let assertions = IcmpAssertions {
+            max_rtt_ms: Some(0),
+            ..Default::default()
+        };
+        let outcome = execute(&icmp_probe("127.0.0.1", assertions)).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.starts_with("rtt of "),
+            "{}",
+            outcome.message
+        );
+    }
+}
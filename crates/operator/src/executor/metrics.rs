@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family, gauge::Gauge, histogram::Histogram},
+    registry::{Registry, Unit},
+};
+
+use crate::executor::{Phase, ProbeOutcome};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ProbeLabels {
+    pub namespace: String,
+    pub probe: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ProbeRunLabels {
+    pub namespace: String,
+    pub probe: String,
+    /// `success` or `failure`
+    pub result: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ProbePhaseLabels {
+    pub namespace: String,
+    pub probe: String,
+    /// `dns`, `connect`, `tls` or `first_byte`
+    pub phase: String,
+}
+
+fn duration_histogram() -> Histogram {
+    Histogram::new([0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.])
+}
+
+fn phase_labels(labels: &ProbeLabels, phase: Phase) -> ProbePhaseLabels {
+    ProbePhaseLabels {
+        namespace: labels.namespace.clone(),
+        probe: labels.probe.clone(),
+        phase: phase.as_str().to_string(),
+    }
+}
+
+/// Metrics of the probes run by a worker
+#[derive(Clone)]
+pub struct ExecutorMetrics {
+    pub runs: Family<ProbeRunLabels, Counter>,
+    /// 1 if the last run of the probe succeeded
+    pub success: Family<ProbeLabels, Gauge>,
+    pub duration: Family<ProbeLabels, Histogram, fn() -> Histogram>,
+    pub phase_duration: Family<ProbePhaseLabels, Histogram, fn() -> Histogram>,
+    /// When the certificate checked by the probe expires, as a Unix timestamp
+    pub certificate_expiry: Family<ProbeLabels, Gauge>,
+    /// Number of probes assigned to the worker
+    pub assigned: Gauge,
+    pub registry: Arc<Registry>,
+}
+
+impl Default for ExecutorMetrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("probelet_worker");
+        let runs = Family::<ProbeRunLabels, Counter>::default();
+        let success = Family::<ProbeLabels, Gauge>::default();
+        let duration = Family::<ProbeLabels, Histogram, fn() -> Histogram>::new_with_constructor(
+            duration_histogram,
+        );
+        let phase_duration =
+            Family::<ProbePhaseLabels, Histogram, fn() -> Histogram>::new_with_constructor(
+                duration_histogram,
+            );
+        let certificate_expiry = Family::<ProbeLabels, Gauge>::default();
+        let assigned = Gauge::default();
+
+        let probe = registry.sub_registry_with_prefix("probe");
+        probe.register("runs", "probe runs", runs.clone());
+        probe.register(
+            "success",
+            "whether the last run of the probe succeeded",
+            success.clone(),
+        );
+        probe.register_with_unit(
+            "duration",
+            "probe run duration",
+            Unit::Seconds,
+            duration.clone(),
+        );
+        probe.register_with_unit(
+            "phase_duration",
+            "duration of a phase of a probe run",
+            Unit::Seconds,
+            phase_duration.clone(),
+        );
+        probe.register_with_unit(
+            "certificate_expiry_timestamp",
+            "when the certificate checked by the probe expires",
+            Unit::Seconds,
+            certificate_expiry.clone(),
+        );
+        registry.register(
+            "assigned_probes",
+            "probes assigned to this worker",
+            assigned.clone(),
+        );
+
+        Self {
+            runs,
+            success,
+            duration,
+            phase_duration,
+            certificate_expiry,
+            assigned,
+            registry: Arc::new(registry),
+        }
+    }
+}
+
+impl ExecutorMetrics {
+    /// Records the outcome of a run of a probe
+    pub fn record(&self, labels: &ProbeLabels, outcome: &ProbeOutcome) {
+        let result = if outcome.success {
+            "success"
+        } else {
+            "failure"
+        };
+        self.runs
+            .get_or_create(&ProbeRunLabels {
+                namespace: labels.namespace.clone(),
+                probe: labels.probe.clone(),
+                result: result.to_string(),
+            })
+            .inc();
+        self.success
+            .get_or_create(labels)
+            .set(i64::from(outcome.success));
+        self.duration
+            .get_or_create(labels)
+            .observe(outcome.duration.as_secs_f64());
+        for (phase, duration) in &outcome.phases {
+            self.phase_duration
+                .get_or_create(&phase_labels(labels, *phase))
+                .observe(duration.as_secs_f64());
+        }
+        if let Some(expiry) = outcome.certificate_expiry {
+            self.certificate_expiry
+                .get_or_create(labels)
+                .set(expiry.timestamp());
+        }
+    }
+
+    /// Removes the series of a probe that is no longer assigned to the worker
+    pub fn remove(&self, labels: &ProbeLabels) {
+        for result in ["success", "failure"] {
+            self.runs.remove(&ProbeRunLabels {
+                namespace: labels.namespace.clone(),
+                probe: labels.probe.clone(),
+                result: result.to_string(),
+            });
+        }
+        self.success.remove(labels);
+        self.duration.remove(labels);
+        for phase in Phase::ALL {
+            self.phase_duration.remove(&phase_labels(labels, phase));
+        }
+        self.certificate_expiry.remove(labels);
+    }
+
+    /// Encodes the metrics in the OpenMetrics text format
+    pub fn encode(&self) -> String {
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &self.registry).unwrap();
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::DateTime;
+
+    use super::*;
+
+    #[test]
+    fn test_record_and_remove() {
+        let metrics = ExecutorMetrics::default();
+        let labels = ProbeLabels {
+            namespace: "default".to_string(),
+            probe: "test".to_string(),
+        };
+        metrics.record(
+            &labels,
+            &ProbeOutcome::failure(Duration::from_millis(20), "refused")
+                .with_phases(vec![(Phase::Dns, Duration::from_millis(1))])
+                .with_certificate_expiry(DateTime::from_timestamp(1_800_000_000, 0).unwrap()),
+        );
+
+        let output = metrics.encode();
+        for expected in [
+            r#"probelet_worker_probe_runs_total{namespace="default",probe="test",result="failure"} 1"#,
+            r#"probelet_worker_probe_success{namespace="default",probe="test"} 0"#,
+            r#"probelet_worker_probe_duration_seconds_count{namespace="default",probe="test"} 1"#,
+            r#"probelet_worker_probe_phase_duration_seconds_count{namespace="default",probe="test",phase="dns"} 1"#,
+            r#"probelet_worker_probe_certificate_expiry_timestamp_seconds{namespace="default",probe="test"} 1800000000"#,
+        ] {
+            assert!(output.contains(expected), "{expected} in {output}");
+        }
+
+        metrics.remove(&labels);
+        assert!(!metrics.encode().contains(r#"probe="test""#), "removed");
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{net::TcpStream, time::Instant};
+
+use crate::executor::Phase;
+
+#[cfg(test)]
+pub(super) use tests::{serve, serve_http2};
+
+/// Adds the duration of a phase, summing it with the earlier connections of the run
+pub(super) fn record_phase(phases: &mut Vec<(Phase, Duration)>, phase: Phase, duration: Duration) {
+    match phases.iter_mut().find(|(recorded, _)| *recorded == phase) {
+        Some((_, total)) => *total += duration,
+        None => phases.push((phase, duration)),
+    }
+}
+
+/// Resolves a host name or an IP address, IPv6 ones possibly in brackets, timing the
+/// DNS phase for host names
+pub(super) async fn resolve(
+    host: &str,
+    port: u16,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<Vec<SocketAddr>, String> {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let started = Instant::now();
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("failed to resolve {host}: {e}"))?
+        .collect::<Vec<_>>();
+    record_phase(phases, Phase::Dns, started.elapsed());
+    Ok(addresses)
+}
+
+/// Connects to the first address accepting the connection, timing the connect phase
+pub(super) async fn connect(
+    addresses: &[SocketAddr],
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<TcpStream, String> {
+    let started = Instant::now();
+    let mut error = "no address to connect to".to_string();
+    for address in addresses {
+        match TcpStream::connect(address).await {
+            Ok(stream) => {
+                record_phase(phases, Phase::Connect, started.elapsed());
+                return Ok(stream);
+            }
+            Err(e) => error = format!("failed to connect to {address}: {e}"),
+        }
+    }
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves `app` over HTTP/1.1 and HTTP/2 with prior knowledge
+    pub(in crate::executor) async fn serve(app: Router) -> SocketAddr {
+        serve_with(app, auto::Builder::new(TokioExecutor::new())).await
+    }
+
+    /// Serves `app` over HTTP/2 with prior knowledge only
+    pub(in crate::executor) async fn serve_http2(app: Router) -> SocketAddr {
+        serve_with(app, auto::Builder::new(TokioExecutor::new()).http2_only()).await
+    }
+
+    async fn serve_with(app: Router, builder: auto::Builder<TokioExecutor>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (service, builder) = (TowerToHyperService::new(app.clone()), builder.clone());
+                tokio::spawn(async move {
+                    let _ = builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        address
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use regex::Regex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::Instant,
+};
+
+use crate::{
+    executor::{Phase, ProbeOutcome, net},
+    probe::TcpProbe,
+};
+
+/// Stop reading a response that does not match once it is that long
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// How long to wait for more of a response that does not match yet, once some of it is read
+const RESPONSE_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How much of a response that does not match is shown in the failure
+const EXCERPT_LENGTH: usize = 100;
+
+/// Runs a TCP probe once
+pub async fn execute(probe: &TcpProbe) -> ProbeOutcome {
+    ProbeOutcome::measure(async |phases| run(probe, phases).await).await
+}
+
+async fn run(probe: &TcpProbe, phases: &mut Vec<(Phase, Duration)>) -> Result<String, String> {
+    let addresses = net::resolve(&probe.host, probe.port, phases).await?;
+    let mut stream = net::connect(&addresses, phases).await?;
+    let address = stream
+        .peer_addr()
+        .map_err(|e| format!("failed to get the peer address: {e}"))?;
+
+    if let Some(payload) = &probe.payload {
+        stream
+            .write_all(payload.as_bytes())
+            .await
+            .map_err(|e| format!("failed to send the payload: {e}"))?;
+    }
+
+    let Some(pattern) = &probe.expect else {
+        return Ok(format!("connected to {address}"));
+    };
+    let regex = Regex::new(pattern).map_err(|e| format!("invalid regex /{pattern}/: {e}"))?;
+
+    let started = Instant::now();
+    let mut received = Vec::new();
+    let mut buffer = [0; 4096];
+    while received.len() < MAX_RESPONSE_SIZE {
+        let read = if received.is_empty() {
+            stream.read(&mut buffer).await
+        } else {
+            match tokio::time::timeout(RESPONSE_IDLE_TIMEOUT, stream.read(&mut buffer)).await {
+                Ok(read) => read,
+                Err(_) => break,
+            }
+        }
+        .map_err(|e| format!("failed to read the response: {e}"))?;
+        if read == 0 {
+            break;
+        }
+        if received.is_empty() {
+            net::record_phase(phases, Phase::FirstByte, started.elapsed());
+        }
+        received.extend_from_slice(&buffer[..read]);
+        if regex.is_match(&String::from_utf8_lossy(&received)) {
+            return Ok(format!("response of {address} matches /{pattern}/"));
+        }
+    }
+
+    if received.is_empty() {
+        return Err(format!(
+            "{address} closed the connection without responding"
+        ));
+    }
+    let excerpt = String::from_utf8_lossy(&received)
+        .chars()
+        .take(EXCERPT_LENGTH)
+        .collect::<String>();
+    Err(format!(
+        "response \"{}\" does not match /{pattern}/",
+        excerpt.escape_debug()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accepts connections, sending `banner` then echoing what it receives
+    async fn stand_in(banner: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    stream.write_all(banner.as_bytes()).await.unwrap();
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        port
+    }
+
+    fn tcp_probe(host: &str, port: u16, payload: Option<&str>, expect: Option<&str>) -> TcpProbe {
+        TcpProbe {
+            host: host.to_string(),
+            port,
+            payload: payload.map(str::to_string),
+            expect: expect.map(str::to_string),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tcp_probe_connect() {
+        let port = stand_in("").await;
+        let outcome = execute(&tcp_probe("localhost", port, None, None)).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert_eq!(outcome.phase_list(), [Phase::Dns, Phase::Connect]);
+
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let outcome = execute(&tcp_probe("127.0.0.1", closed, None, None)).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome.message.starts_with("failed to connect"),
+            "{}",
+            outcome.message
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tcp_probe_banner() {
+        let port = stand_in("SSH-2.0-stand-in\r\n").await;
+        let outcome = execute(&tcp_probe("127.0.0.1", port, None, Some(r"^SSH-2\.0-"))).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert_eq!(outcome.phase_list(), [Phase::Connect, Phase::FirstByte]);
+
+        let outcome = execute(&tcp_probe("127.0.0.1", port, None, Some("^220 "))).await;
+        assert!(!outcome.success);
+        assert_eq!(
+            outcome.message,
+            r#"response "SSH-2.0-stand-in\r\n" does not match /^220 /"#
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tcp_probe_payload() {
+        let port = stand_in("").await;
+        let outcome = execute(&tcp_probe(
+            "127.0.0.1",
+            port,
+            Some("PING\r\n"),
+            Some(r"^PING\r\n$"),
+        ))
+        .await;
+        assert!(outcome.success, "{}", outcome.message);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tcp_probe_closed_without_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+        });
+
+        let outcome = execute(&tcp_probe("127.0.0.1", port, None, Some("^OK"))).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome
+                .message
+                .ends_with("closed the connection without responding"),
+            "{}",
+            outcome.message
+        );
+    }
+}
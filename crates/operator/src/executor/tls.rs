@@ -0,0 +1,637 @@
+mod certificate;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use chrono::Utc;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::Api;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject},
+};
+use tokio::time::Instant;
+use tokio_rustls::TlsConnector;
+use tracing::warn;
+
+use crate::{
+    executor::{Phase, ProbeContext, ProbeOutcome, net},
+    probe::{ConfigMapKeyRef, TlsProbe},
+};
+
+/// How long a CA bundle read from a `ConfigMap` is used before it is read again
+const CA_BUNDLE_TTL: Duration = Duration::from_secs(60);
+
+/// The cryptography used by the TLS connections of the probes
+pub(super) fn crypto_provider() -> Arc<CryptoProvider> {
+    static PROVIDER: OnceLock<Arc<CryptoProvider>> = OnceLock::new();
+    PROVIDER
+        .get_or_init(|| Arc::new(rustls::crypto::ring::default_provider()))
+        .clone()
+}
+
+/// The configuration of the TLS connections trusting the system roots, negotiating one of
+/// `alpn_protocols`
+pub(super) fn client_config(alpn_protocols: &[&[u8]]) -> Arc<ClientConfig> {
+    let mut config = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .with_root_certificates(system_roots())
+        .with_no_client_auth();
+    config.alpn_protocols = alpn_protocols
+        .iter()
+        .map(|protocol| protocol.to_vec())
+        .collect();
+    Arc::new(config)
+}
+
+/// The root certificates of the system, loaded once
+pub(super) fn system_roots() -> Arc<RootCertStore> {
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    ROOTS
+        .get_or_init(|| {
+            let native = rustls_native_certs::load_native_certs();
+            for error in native.errors {
+                warn!("failed to load system root certificates: {error}");
+            }
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(native.certs);
+            Arc::new(roots)
+        })
+        .clone()
+}
+
+/// Runs a TLS probe once
+pub async fn execute(probe: &TlsProbe, context: &ProbeContext) -> ProbeOutcome {
+    let roots = match &probe.ca_bundle {
+        Some(reference) => match ca_bundle(reference, context).await {
+            Ok(roots) => roots,
+            Err(message) => return ProbeOutcome::failure(Duration::ZERO, message),
+        },
+        None => system_roots(),
+    };
+    execute_with_roots(probe, roots).await
+}
+
+/// The CA bundles read from `ConfigMap`s by the probes of a worker, so that a probe does
+/// not get its `ConfigMap` on every run
+#[derive(Default)]
+pub(super) struct CaBundles(Mutex<HashMap<ConfigMapKeyRef, (Instant, Arc<RootCertStore>)>>);
+
+impl CaBundles {
+    /// The bundle of `reference`, unless it was read more than `CA_BUNDLE_TTL` ago
+    fn get(&self, reference: &ConfigMapKeyRef) -> Option<Arc<RootCertStore>> {
+        let bundles = self.0.lock().unwrap();
+        let (read_at, roots) = bundles.get(reference)?;
+        (read_at.elapsed() < CA_BUNDLE_TTL).then(|| roots.clone())
+    }
+
+    fn insert(&self, reference: &ConfigMapKeyRef, roots: Arc<RootCertStore>) {
+        let mut bundles = self.0.lock().unwrap();
+        bundles.retain(|_, (read_at, _)| read_at.elapsed() < CA_BUNDLE_TTL);
+        bundles.insert(reference.clone(), (Instant::now(), roots));
+    }
+}
+
+/// The certificates of a CA bundle stored in a `ConfigMap` in the namespace of the probe,
+/// read again once they are older than `CA_BUNDLE_TTL`
+async fn ca_bundle(
+    reference: &ConfigMapKeyRef,
+    context: &ProbeContext,
+) -> Result<Arc<RootCertStore>, String> {
+    if let Some(roots) = context.ca_bundles.get(reference) {
+        return Ok(roots);
+    }
+    let roots = read_ca_bundle(reference, context).await?;
+    context.ca_bundles.insert(reference, roots.clone());
+    Ok(roots)
+}
+
+/// Reads the certificates of a CA bundle from its `ConfigMap`
+async fn read_ca_bundle(
+    reference: &ConfigMapKeyRef,
+    context: &ProbeContext,
+) -> Result<Arc<RootCertStore>, String> {
+    let ConfigMapKeyRef { name, key } = reference;
+    let api = Api::<ConfigMap>::namespaced(context.client.clone(), &context.namespace);
+    let config_map = api
+        .get(name)
+        .await
+        .map_err(|e| format!("failed to get ConfigMap {name}: {e}"))?;
+    let pem = config_map
+        .data
+        .and_then(|mut data| data.remove(key))
+        .ok_or_else(|| format!("ConfigMap {name} has no key {key}"))?;
+
+    let mut roots = RootCertStore::empty();
+    for certificate in CertificateDer::pem_slice_iter(pem.as_bytes()) {
+        let certificate = certificate
+            .map_err(|e| format!("invalid PEM in key {key} of ConfigMap {name}: {e}"))?;
+        roots
+            .add(certificate)
+            .map_err(|e| format!("invalid certificate in key {key} of ConfigMap {name}: {e}"))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("key {key} of ConfigMap {name} has no certificate"));
+    }
+    Ok(Arc::new(roots))
+}
+
+/// Runs a TLS probe once, validating the chain against `roots`
+async fn execute_with_roots(probe: &TlsProbe, roots: Arc<RootCertStore>) -> ProbeOutcome {
+    let start = Instant::now();
+    let mut phases = Vec::new();
+    let (certificate, verification) = match handshake(probe, roots, &mut phases).await {
+        Ok(handshake) => handshake,
+        Err(message) => {
+            return ProbeOutcome::failure(start.elapsed(), message).with_phases(phases);
+        }
+    };
+
+    let days_remaining = (certificate.not_after - Utc::now()).num_days();
+    let mut failures = Vec::new();
+    if let Err(e) = verification {
+        failures.push(format!("the certificate is not trusted: {e}"));
+    }
+    if let Some(min) = probe.min_days_remaining
+        && days_remaining < i64::from(min)
+    {
+        failures.push(format!(
+            "the certificate expires in {days_remaining} days, fewer than {min}"
+        ));
+    }
+
+    let details = certificate.describe(days_remaining);
+    let outcome = if failures.is_empty() {
+        ProbeOutcome::success(start.elapsed(), details)
+    } else {
+        failures.push(details);
+        ProbeOutcome::failure(start.elapsed(), failures.join("; "))
+    };
+    outcome
+        .with_phases(phases)
+        .with_certificate_expiry(certificate.not_after)
+}
+
+/// Completes a handshake, returning the certificate of the server and whether it is trusted
+async fn handshake(
+    probe: &TlsProbe,
+    roots: Arc<RootCertStore>,
+    phases: &mut Vec<(Phase, Duration)>,
+) -> Result<(certificate::Certificate, Result<(), rustls::Error>), String> {
+    let name = probe.server_name.as_deref().unwrap_or(&probe.host);
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|e| format!("invalid server name {name}: {e}"))?;
+    let verifier = Arc::new(RecordingVerifier::new(roots)?);
+    let config = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+
+    let addresses = net::resolve(&probe.host, probe.port, phases).await?;
+    let stream = net::connect(&addresses, phases).await?;
+    let started = Instant::now();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| format!("TLS handshake failed: {e}"))?;
+    net::record_phase(phases, Phase::Tls, started.elapsed());
+
+    let leaf = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .ok_or_else(|| "the server presented no certificate".to_string())?;
+    let certificate = certificate::parse(leaf)?;
+    let verification = match verifier.error.lock().unwrap().take() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    };
+    Ok((certificate, verification))
+}
+
+/// Validates the chain of the server, recording the error instead of failing the handshake
+/// so the certificate can still be reported
+#[derive(Debug)]
+struct RecordingVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    error: Mutex<Option<rustls::Error>>,
+}
+
+impl RecordingVerifier {
+    fn new(roots: Arc<RootCertStore>) -> Result<Self, String> {
+        let inner = WebPkiServerVerifier::builder_with_provider(roots, crypto_provider())
+            .build()
+            .map_err(|e| format!("failed to build the certificate verifier: {e}"))?;
+        Ok(Self {
+            inner,
+            error: Mutex::new(None),
+        })
+    }
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Err(error) = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            *self.error.lock().unwrap() = Some(error);
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use chrono::{DateTime, SubsecRound, TimeDelta};
+    use http::{Request, Response};
+    use kube::{Client, client::Body};
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+    use rustls::{
+        ServerConfig,
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    };
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+
+    const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    const PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    const ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+    const BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+    const EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+    const SERVER_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let length = content.len();
+        let mut value = match length {
+            0..0x80 => vec![tag, length as u8],
+            0x80..0x100 => vec![tag, 0x81, length as u8],
+            _ => vec![tag, 0x82, (length >> 8) as u8, length as u8],
+        };
+        value.extend_from_slice(content);
+        value
+    }
+
+    fn sequence(values: &[Vec<u8>]) -> Vec<u8> {
+        der(0x30, &values.concat())
+    }
+
+    fn extension(oid: &[u8], value: Vec<u8>) -> Vec<u8> {
+        sequence(&[der(0x06, oid), der(0x04, &value)])
+    }
+
+    /// A certificate generated at test time, with its P-256 key
+    struct TestCertificate {
+        der: CertificateDer<'static>,
+        pkcs8: Vec<u8>,
+        not_after: DateTime<Utc>,
+        key: EcdsaKeyPair,
+        subject: Vec<u8>,
+    }
+
+    impl TestCertificate {
+        /// A self-signed CA, or a server certificate for `sans` signed by `issuer`
+        fn new(
+            subject: &str,
+            issuer: Option<&TestCertificate>,
+            sans: &[&str],
+            not_after: DateTime<Utc>,
+        ) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+                .unwrap()
+                .as_ref()
+                .to_vec();
+            let key =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &pkcs8, &rng).unwrap();
+
+            let name = sequence(
+                &subject
+                    .split(", ")
+                    .map(|attribute| {
+                        let (kind, value) = attribute.split_once('=').unwrap();
+                        let oid = if kind == "O" {
+                            ORGANIZATION
+                        } else {
+                            COMMON_NAME
+                        };
+                        der(
+                            0x31,
+                            &sequence(&[der(0x06, oid), der(0x0c, value.as_bytes())]),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            let not_before = Utc::now() - TimeDelta::days(365);
+            let validity = sequence(&[
+                der(
+                    0x17,
+                    not_before.format("%y%m%d%H%M%SZ").to_string().as_bytes(),
+                ),
+                der(
+                    0x18,
+                    not_after.format("%Y%m%d%H%M%SZ").to_string().as_bytes(),
+                ),
+            ]);
+            let public_key = sequence(&[
+                sequence(&[der(0x06, EC_PUBLIC_KEY), der(0x06, PRIME256V1)]),
+                der(0x03, &[&[0], key.public_key().as_ref()].concat()),
+            ]);
+            let extensions = match issuer {
+                None => vec![extension(
+                    BASIC_CONSTRAINTS,
+                    sequence(&[der(0x01, &[0xff])]),
+                )],
+                Some(_) => vec![
+                    extension(BASIC_CONSTRAINTS, sequence(&[])),
+                    extension(
+                        certificate::SUBJECT_ALT_NAME,
+                        sequence(
+                            &sans
+                                .iter()
+                                .map(|san| match san.parse::<std::net::IpAddr>() {
+                                    Ok(std::net::IpAddr::V4(ip)) => der(0x87, &ip.octets()),
+                                    Ok(std::net::IpAddr::V6(ip)) => der(0x87, &ip.octets()),
+                                    Err(_) => der(0x82, san.as_bytes()),
+                                })
+                                .collect::<Vec<_>>(),
+                        ),
+                    ),
+                    extension(EXTENDED_KEY_USAGE, sequence(&[der(0x06, SERVER_AUTH)])),
+                ],
+            };
+
+            let algorithm = sequence(&[der(0x06, ECDSA_WITH_SHA256)]);
+            let tbs = sequence(&[
+                der(0xa0, &der(0x02, &[2])),
+                der(0x02, &rand::random::<[u8; 8]>().map(|byte| byte & 0x7f)),
+                algorithm.clone(),
+                issuer.map_or(name.clone(), |issuer| issuer.subject.clone()),
+                validity,
+                name.clone(),
+                public_key,
+                der(0xa3, &sequence(&extensions)),
+            ]);
+            let signer = issuer.map_or(&key, |issuer| &issuer.key);
+            let signature = signer.sign(&rng, &tbs).unwrap();
+            let certificate = sequence(&[
+                tbs,
+                algorithm,
+                der(0x03, &[&[0], signature.as_ref()].concat()),
+            ]);
+
+            Self {
+                der: CertificateDer::from(certificate),
+                pkcs8,
+                not_after: not_after.trunc_subsecs(0),
+                key,
+                subject: name,
+            }
+        }
+
+        fn roots(&self) -> Arc<RootCertStore> {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.der.clone()).unwrap();
+            Arc::new(roots)
+        }
+
+        fn pem(&self) -> String {
+            pem::encode(&pem::Pem::new("CERTIFICATE", self.der.to_vec()))
+        }
+    }
+
+    /// Completes handshakes with `certificate` then closes the connections
+    async fn serve(certificate: &TestCertificate) -> u16 {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certificate.pkcs8.clone()));
+        let config = ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.der.clone()], key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(stream).await;
+                });
+            }
+        });
+        port
+    }
+
+    fn tls_probe(port: u16) -> TlsProbe {
+        TlsProbe {
+            host: "127.0.0.1".to_string(),
+            port,
+            server_name: Some("localhost".to_string()),
+            ca_bundle: None,
+            min_days_remaining: None,
+        }
+    }
+
+    /// Expires in `days` days and an hour, so whole days remain until then
+    fn in_days(days: i64) -> DateTime<Utc> {
+        Utc::now() + TimeDelta::days(days) + TimeDelta::hours(1)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tls_probe_trusted() {
+        let ca = TestCertificate::new("CN=Test CA", None, &[], in_days(3650));
+        let server = TestCertificate::new(
+            "CN=localhost, O=probelet",
+            Some(&ca),
+            &["localhost", "127.0.0.1"],
+            in_days(90),
+        );
+        let port = serve(&server).await;
+
+        let mut probe = tls_probe(port);
+        let outcome = execute_with_roots(&probe, ca.roots()).await;
+        assert!(outcome.success, "{}", outcome.message);
+        assert!(
+            outcome.message.starts_with(
+                "subject \"CN=localhost, O=probelet\", issuer \"CN=Test CA\", \
+                 SANs localhost, 127.0.0.1, not after "
+            ),
+            "{}",
+            outcome.message
+        );
+        assert!(outcome.message.ends_with(", expires in 90 days"));
+        let phases = outcome.phase_list();
+        assert_eq!(phases, [Phase::Connect, Phase::Tls]);
+        assert_eq!(outcome.certificate_expiry, Some(server.not_after));
+
+        probe.min_days_remaining = Some(120);
+        let outcome = execute_with_roots(&probe, ca.roots()).await;
+        assert!(!outcome.success);
+        assert!(
+            outcome
+                .message
+                .starts_with("the certificate expires in 90 days, fewer than 120; subject"),
+            "{}",
+            outcome.message
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_tls_probe_untrusted() {
+        let ca = TestCertificate::new("CN=Test CA", None, &[], in_days(3650));
+        let other_ca = TestCertificate::new("CN=Other CA", None, &[], in_days(3650));
+        let server = TestCertificate::new("CN=localhost", Some(&ca), &["localhost"], in_days(90));
+        let expired = TestCertificate::new(
+            "CN=localhost",
+            Some(&ca),
+            &["localhost"],
+            Utc::now() - TimeDelta::days(2) - TimeDelta::hours(1),
+        );
+        let port = serve(&server).await;
+        let expired_port = serve(&expired).await;
+
+        for (probe, roots, error) in [
+            (tls_probe(port), other_ca.roots(), "UnknownIssuer"),
+            (
+                TlsProbe {
+                    server_name: Some("example.com".to_string()),
+                    ..tls_probe(port)
+                },
+                ca.roots(),
+                "certificate not valid for name \"example.com\"",
+            ),
+            (tls_probe(expired_port), ca.roots(), "certificate expired"),
+        ] {
+            let outcome = execute_with_roots(&probe, roots).await;
+            assert!(!outcome.success);
+            assert!(
+                outcome
+                    .message
+                    .starts_with("the certificate is not trusted: invalid peer certificate: "),
+                "{}",
+                outcome.message
+            );
+            assert!(outcome.message.contains(error), "{}", outcome.message);
+        }
+
+        let outcome = execute_with_roots(&tls_probe(expired_port), ca.roots()).await;
+        assert!(
+            outcome.message.ends_with(", expired 2 days ago"),
+            "{}",
+            outcome.message
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_ca_bundle() {
+        let ca = TestCertificate::new("CN=Test CA", None, &[], in_days(3650));
+        let config_map = ConfigMap {
+            data: Some(BTreeMap::from([("ca.crt".to_string(), ca.pem())])),
+            ..Default::default()
+        };
+
+        let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let requests = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Some((request, send)) = handle.next_request().await {
+                    requests.fetch_add(1, Ordering::Relaxed);
+                    assert_eq!(
+                        request.uri().path(),
+                        "/api/v1/namespaces/default/configmaps/ca"
+                    );
+                    let body = serde_json::to_vec(&config_map).unwrap();
+                    send.send_response(Response::new(Body::from(body)));
+                }
+            }
+        });
+        let context = ProbeContext {
+            client: Client::new(service, "default"),
+            namespace: "default".to_string(),
+            ca_bundles: Default::default(),
+        };
+
+        let reference = ConfigMapKeyRef {
+            name: "ca".to_string(),
+            key: "ca.crt".to_string(),
+        };
+        let roots = ca_bundle(&reference, &context).await.unwrap();
+        assert_eq!(roots.len(), 1);
+
+        // the bundle is read again only once it expired
+        ca_bundle(&reference, &context).await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+        tokio::time::pause();
+        tokio::time::advance(CA_BUNDLE_TTL).await;
+        ca_bundle(&reference, &context).await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+
+        let reference = ConfigMapKeyRef {
+            name: "ca".to_string(),
+            key: "bundle.pem".to_string(),
+        };
+        assert_eq!(
+            ca_bundle(&reference, &context).await.unwrap_err(),
+            "ConfigMap ca has no key bundle.pem"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const BMP_STRING: u8 = 0x1e;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+/// `[0]`, the version of the certificate
+const VERSION: u8 = 0xa0;
+/// `[3]`, the extensions of the certificate
+const EXTENSIONS: u8 = 0xa3;
+/// `[2]` and `[7]` of a `GeneralName`
+const DNS_NAME: u8 = 0x82;
+const IP_ADDRESS: u8 = 0x87;
+
+/// 2.5.29.17
+pub(super) const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// What the probe reports of a certificate
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    /// The DNS names and IP addresses of the subject alternative names
+    pub sans: Vec<String>,
+    pub not_after: DateTime<Utc>,
+}
+
+impl Certificate {
+    pub fn describe(&self, days_remaining: i64) -> String {
+        let sans = match self.sans.is_empty() {
+            true => "none".to_string(),
+            false => self.sans.join(", "),
+        };
+        let expiry = match days_remaining {
+            days if days < 0 => format!("expired {} days ago", -days),
+            days => format!("expires in {days} days"),
+        };
+        format!(
+            "subject \"{}\", issuer \"{}\", SANs {sans}, not after {}, {expiry}",
+            self.subject,
+            self.issuer,
+            self.not_after.to_rfc3339()
+        )
+    }
+}
+
+/// Reads the fields the probe reports from a DER encoded X.509 certificate
+pub(super) fn parse(der: &[u8]) -> Result<Certificate, String> {
+    let certificate = Der(der).expect(SEQUENCE)?;
+    let mut tbs = Der(Der(certificate).expect(SEQUENCE)?);
+    if tbs.peek() == Some(VERSION) {
+        tbs.next()?;
+    }
+    tbs.expect(INTEGER)?;
+    tbs.expect(SEQUENCE)?;
+    let issuer = name(tbs.expect(SEQUENCE)?)?;
+    let mut validity = Der(tbs.expect(SEQUENCE)?);
+    validity.next()?;
+    let not_after = time(validity.next()?)?;
+    let subject = name(tbs.expect(SEQUENCE)?)?;
+    tbs.expect(SEQUENCE)?;
+
+    let mut sans = Vec::new();
+    while !tbs.0.is_empty() {
+        let (tag, content) = tbs.next()?;
+        if tag == EXTENSIONS {
+            sans = subject_alt_names(Der(content).expect(SEQUENCE)?)?;
+        }
+    }
+
+    Ok(Certificate {
+        subject,
+        issuer,
+        sans,
+        not_after,
+    })
+}
+
+/// A reader of DER values
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    /// The tag and the content of the next value
+    fn next(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let malformed = || "the certificate is malformed".to_string();
+        let (&tag, rest) = self.0.split_first().ok_or_else(malformed)?;
+        let (&first, rest) = rest.split_first().ok_or_else(malformed)?;
+        let (length, rest) = match first {
+            0..=0x7f => (first as usize, rest),
+            0x81..=0x84 => {
+                let (length, rest) = rest
+                    .split_at_checked((first & 0x7f) as usize)
+                    .ok_or_else(malformed)?;
+                let length = length
+                    .iter()
+                    .fold(0, |length, byte| (length << 8) | *byte as usize);
+                (length, rest)
+            }
+            _ => return Err(malformed()),
+        };
+        let (content, rest) = rest.split_at_checked(length).ok_or_else(malformed)?;
+        self.0 = rest;
+        Ok((tag, content))
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<&'a [u8], String> {
+        match self.next()? {
+            (tag, content) if tag == expected => Ok(content),
+            (tag, _) => Err(format!(
+                "the certificate is malformed, expected tag {expected:#04x} but found {tag:#04x}"
+            )),
+        }
+    }
+}
+
+/// Formats a distinguished name like `CN=example.com, O=Example`
+fn name(content: &[u8]) -> Result<String, String> {
+    let mut attributes = Vec::new();
+    let mut sets = Der(content);
+    while !sets.0.is_empty() {
+        let mut set = Der(sets.expect(SET)?);
+        while !set.0.is_empty() {
+            let mut attribute = Der(set.expect(SEQUENCE)?);
+            let oid = attribute.expect(OBJECT_IDENTIFIER)?;
+            let (tag, value) = attribute.next()?;
+            attributes.push(format!("{}={}", attribute_name(oid), string(tag, value)));
+        }
+    }
+    Ok(attributes.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        other => dotted_oid(other),
+    }
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for byte in oid {
+        arc = (arc << 7) | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn string(tag: u8, value: &[u8]) -> String {
+    match tag {
+        BMP_STRING => {
+            let units = value
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(value).into_owned(),
+    }
+}
+
+/// Parses a `UTCTime` or a `GeneralizedTime` in UTC
+fn time((tag, value): (u8, &[u8])) -> Result<DateTime<Utc>, String> {
+    let value = String::from_utf8_lossy(value);
+    let value = match tag {
+        // two digit years are in 1950..2050
+        UTC_TIME => match value.get(..2).and_then(|year| year.parse::<u8>().ok()) {
+            Some(year) if year >= 50 => format!("19{value}"),
+            Some(_) => format!("20{value}"),
+            None => return Err(format!("invalid time {value}")),
+        },
+        GENERALIZED_TIME => value.into_owned(),
+        _ => {
+            return Err(format!(
+                "the certificate is malformed, unknown time tag {tag:#04x}"
+            ));
+        }
+    };
+    NaiveDateTime::parse_from_str(&value, "%Y%m%d%H%M%SZ")
+        .map(|time| time.and_utc())
+        .map_err(|e| format!("invalid time {value}: {e}"))
+}
+
+/// The DNS names and IP addresses of the subject alternative name extension
+fn subject_alt_names(extensions: &[u8]) -> Result<Vec<String>, String> {
+    let mut extensions = Der(extensions);
+    while !extensions.0.is_empty() {
+        let mut extension = Der(extensions.expect(SEQUENCE)?);
+        let oid = extension.expect(OBJECT_IDENTIFIER)?;
+        if extension.peek() == Some(BOOLEAN) {
+            extension.next()?;
+        }
+        let value = extension.expect(OCTET_STRING)?;
+        if oid != SUBJECT_ALT_NAME {
+            continue;
+        }
+
+        let mut names = Der(Der(value).expect(SEQUENCE)?);
+        let mut sans = Vec::new();
+        while !names.0.is_empty() {
+            match names.next()? {
+                (DNS_NAME, name) => sans.push(String::from_utf8_lossy(name).into_owned()),
+                (IP_ADDRESS, ip) => {
+                    if let Ok(ip) = <[u8; 4]>::try_from(ip) {
+                        sans.push(Ipv4Addr::from(ip).to_string());
+                    } else if let Ok(ip) = <[u8; 16]>::try_from(ip) {
+                        sans.push(Ipv6Addr::from(ip).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Ok(sans);
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time() {
+        for (tag, value, expected) in [
+            (UTC_TIME, "491231235959Z", "2049-12-31T23:59:59+00:00"),
+            (UTC_TIME, "500101000000Z", "1950-01-01T00:00:00+00:00"),
+            (
+                GENERALIZED_TIME,
+                "20510101000000Z",
+                "2051-01-01T00:00:00+00:00",
+            ),
+        ] {
+            assert_eq!(
+                time((tag, value.as_bytes())).unwrap().to_rfc3339(),
+                expected
+            );
+        }
+        assert!(time((UTC_TIME, b"2501")).is_err());
+    }
+
+    #[test]
+    fn test_dotted_oid() {
+        assert_eq!(dotted_oid(&[0x55, 0x04, 0x2a]), "2.5.4.42");
+        assert_eq!(
+            dotted_oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01]),
+            "1.2.840.113549.1.9.1"
+        );
+    }
+}
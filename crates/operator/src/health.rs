@@ -0,0 +1,198 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Health of a single controller
+#[derive(Debug, Clone, Default)]
+struct ControllerHealth {
+    /// The CRD of the controller could be queried
+    crd_ready: bool,
+    /// The watch cache of the controller went through its initial list
+    cache_synced: bool,
+    /// Number of objects in the watch cache
+    objects: usize,
+    /// When the watcher started failing, `None` while it is healthy
+    watch_failing_since: Option<DateTime<Utc>>,
+}
+
+/// Health of the controllers, backing the `/readyz` and `/livez` routes
+#[derive(Debug, Default)]
+pub struct Health {
+    controllers: Mutex<BTreeMap<String, ControllerHealth>>,
+    /// This replica waits for the leader lease and runs no controller
+    standby: AtomicBool,
+}
+
+impl Health {
+    fn update(&self, controller: &str, f: impl FnOnce(&mut ControllerHealth)) {
+        let mut controllers = self.controllers.lock().unwrap();
+        f(controllers.entry(controller.to_string()).or_default());
+    }
+
+    /// Registers a controller, it is not ready until its CRD and cache are
+    pub fn register(&self, controller: &str) {
+        self.update(controller, |health| *health = ControllerHealth::default());
+    }
+
+    /// Registers a controller for as long as the returned guard lives
+    pub fn register_scoped(self: &Arc<Self>, controller: &str) -> Registration {
+        self.register(controller);
+        Registration {
+            health: self.clone(),
+            controller: controller.to_string(),
+        }
+    }
+
+    /// Forgets a controller that stopped
+    pub fn unregister(&self, controller: &str) {
+        self.controllers.lock().unwrap().remove(controller);
+    }
+
+    /// Marks this replica as a standby waiting for the leader lease, it is healthy
+    /// without running any controller
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Relaxed);
+    }
+
+    /// Marks the CRD of the controller as queryable
+    pub fn set_crd_ready(&self, controller: &str) {
+        self.update(controller, |health| health.crd_ready = true);
+    }
+
+    /// Marks the watch cache of the controller as synced
+    pub fn set_cache_synced(&self, controller: &str) {
+        self.update(controller, |health| health.cache_synced = true);
+    }
+
+    /// Records an item of the controller stream, `ok` is `false` when its watcher failed
+    pub fn record_watch(&self, controller: &str, ok: bool, objects: usize) {
+        self.update(controller, |health| {
+            health.objects = objects;
+            if ok {
+                health.watch_failing_since = None;
+            } else if health.watch_failing_since.is_none() {
+                health.watch_failing_since = Some(Utc::now());
+            }
+        });
+    }
+
+    /// Ready once every controller has its CRD installed and its cache synced
+    pub fn readiness(&self) -> Result<(), String> {
+        if self.standby.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let controllers = self.controllers.lock().unwrap();
+        if controllers.is_empty() {
+            return Err("no controller started".to_string());
+        }
+        for (name, health) in controllers.iter() {
+            if !health.crd_ready {
+                return Err(format!("{name}: CRD is not queryable"));
+            }
+            if !health.cache_synced {
+                return Err(format!("{name}: watch cache is not synced"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Alive unless a watcher kept failing, or reconciles stopped while there are objects
+    /// to reconcile, for longer than `threshold`
+    pub fn liveness(
+        &self,
+        last_event: DateTime<Utc>,
+        now: DateTime<Utc>,
+        threshold: TimeDelta,
+    ) -> Result<(), String> {
+        if self.standby.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let controllers = self.controllers.lock().unwrap();
+        for (name, health) in controllers.iter() {
+            if let Some(since) = health.watch_failing_since
+                && now - since > threshold
+            {
+                return Err(format!("{name}: watcher failing since {since}"));
+            }
+        }
+
+        let has_objects = controllers.values().any(|health| health.objects > 0);
+        if has_objects && now - last_event > threshold {
+            return Err(format!("no reconcile since {last_event}"));
+        }
+        Ok(())
+    }
+}
+
+/// Unregisters its controller from the health checks when dropped, e.g. when the
+/// controller of a namespace that is no longer watched is stopped
+pub struct Registration {
+    health: Arc<Health>,
+    controller: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.health.unregister(&self.controller);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_waits_for_crd_and_cache() {
+        let health = Health::default();
+        assert!(health.readiness().is_err());
+
+        health.register("workergroup");
+        assert!(health.readiness().is_err());
+        health.set_crd_ready("workergroup");
+        assert!(health.readiness().is_err());
+        health.set_cache_synced("workergroup");
+        assert!(health.readiness().is_ok());
+    }
+
+    #[test]
+    fn test_standby_is_healthy() {
+        let health = Health::default();
+        health.set_standby(true);
+        assert!(health.readiness().is_ok());
+
+        health.set_standby(false);
+        health.register("workergroup");
+        assert!(health.readiness().is_err());
+    }
+
+    #[test]
+    fn test_liveness_detects_stalls() {
+        let health = Health::default();
+        let now = Utc::now();
+        let threshold = TimeDelta::minutes(15);
+        let long_ago = now - TimeDelta::hours(1);
+
+        health.record_watch("workergroup", true, 0);
+        assert!(health.liveness(long_ago, now, threshold).is_ok(), "idle");
+
+        health.record_watch("workergroup", true, 2);
+        assert!(health.liveness(now, now, threshold).is_ok());
+        assert!(
+            health.liveness(long_ago, now, threshold).is_err(),
+            "stalled"
+        );
+
+        health.update("workergroup", |health| {
+            health.watch_failing_since = Some(long_ago)
+        });
+        assert!(health.liveness(now, now, threshold).is_err(), "watcher");
+        health.record_watch("workergroup", true, 2);
+        assert!(health.liveness(now, now, threshold).is_ok());
+    }
+}
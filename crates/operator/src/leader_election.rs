@@ -0,0 +1,304 @@
+use std::{future::Future, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+};
+use kube::{
+    Api, Client,
+    api::{ObjectMeta, PostParams},
+};
+use serde::Deserialize;
+use tokio::time::{Instant, sleep};
+use tracing::{info, warn};
+use validator::ValidationError;
+use validator_derive::Validate;
+
+use crate::AppState;
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+#[validate(schema(function = "validate_durations"))]
+pub struct LeaderElectionConfig {
+    /// Whether leader election is enabled, every replica runs the controllers otherwise
+    pub enabled: bool,
+    /// The name of the `Lease` object
+    #[validate(length(min = 1, max = 253))]
+    pub lease_name: String,
+    /// The namespace of the `Lease` object
+    #[validate(length(min = 1, max = 63))]
+    pub lease_namespace: String,
+    /// The identity of this replica in the `Lease`, usually the pod name
+    #[validate(length(min = 1))]
+    pub identity: String,
+    /// How long the lease is valid after its last renewal
+    pub lease_duration_seconds: u64,
+    /// How long the leader keeps trying to renew the lease before giving up leadership
+    pub renew_deadline_seconds: u64,
+    /// How often the lease is acquired or renewed
+    #[validate(range(min = 1))]
+    pub retry_period_seconds: u64,
+}
+
+fn validate_durations(config: &LeaderElectionConfig) -> Result<(), ValidationError> {
+    if config.renew_deadline_seconds >= config.lease_duration_seconds {
+        return Err(ValidationError::new(
+            "renewDeadlineSeconds must be shorter than leaseDurationSeconds",
+        ));
+    }
+    if config.retry_period_seconds >= config.renew_deadline_seconds {
+        return Err(ValidationError::new(
+            "retryPeriodSeconds must be shorter than renewDeadlineSeconds",
+        ));
+    }
+    Ok(())
+}
+
+impl Default for LeaderElectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lease_name: "probelet-operator".to_string(),
+            lease_namespace: "default".to_string(),
+            identity: "probelet-operator".to_string(),
+            lease_duration_seconds: 15,
+            renew_deadline_seconds: 10,
+            retry_period_seconds: 2,
+        }
+    }
+}
+
+impl LeaderElectionConfig {
+    fn lease_duration(&self) -> Duration {
+        Duration::from_secs(self.lease_duration_seconds)
+    }
+
+    fn renew_deadline(&self) -> Duration {
+        Duration::from_secs(self.renew_deadline_seconds)
+    }
+
+    fn retry_period(&self) -> Duration {
+        Duration::from_secs(self.retry_period_seconds)
+    }
+}
+
+/// Computes the lease spec to write for `identity` to hold the lease at `now`.
+///
+/// Returns `None` when another holder has a lease that did not expire yet.
+fn acquire(
+    current: Option<&LeaseSpec>,
+    identity: &str,
+    now: DateTime<Utc>,
+    lease_duration: Duration,
+) -> Option<LeaseSpec> {
+    let duration_seconds = i32::try_from(lease_duration.as_secs()).unwrap_or(i32::MAX);
+    let current = current.cloned().unwrap_or_default();
+    let holder = current.holder_identity.as_deref().unwrap_or_default();
+
+    if holder == identity {
+        return Some(LeaseSpec {
+            renew_time: Some(MicroTime(now)),
+            lease_duration_seconds: Some(duration_seconds),
+            ..current
+        });
+    }
+
+    let expired = match &current.renew_time {
+        Some(MicroTime(renewed)) => {
+            let current_duration = current.lease_duration_seconds.unwrap_or(duration_seconds);
+            *renewed + TimeDelta::seconds(current_duration.into()) < now
+        }
+        None => true,
+    };
+    if !holder.is_empty() && !expired {
+        return None;
+    }
+
+    Some(LeaseSpec {
+        holder_identity: Some(identity.to_string()),
+        acquire_time: Some(MicroTime(now)),
+        renew_time: Some(MicroTime(now)),
+        lease_duration_seconds: Some(duration_seconds),
+        lease_transitions: Some(current.lease_transitions.unwrap_or_default() + 1),
+        ..current
+    })
+}
+
+/// Elects a leader among the operator replicas with a `coordination.k8s.io` `Lease`
+pub struct LeaderElector {
+    api: Api<Lease>,
+    config: LeaderElectionConfig,
+    state: AppState,
+}
+
+impl LeaderElector {
+    pub fn new(client: Client, config: LeaderElectionConfig, state: AppState) -> Self {
+        Self {
+            api: Api::namespaced(client, &config.lease_namespace),
+            config,
+            state,
+        }
+    }
+
+    /// Tries to acquire or renew the lease, returns whether this replica holds it
+    async fn try_acquire_or_renew(&self) -> Result<bool, kube::Error> {
+        let name = &self.config.lease_name;
+        let current = self.api.get_opt(name).await?;
+        let spec = acquire(
+            current.as_ref().and_then(|lease| lease.spec.as_ref()),
+            &self.config.identity,
+            Utc::now(),
+            self.config.lease_duration(),
+        );
+        let Some(spec) = spec else {
+            return Ok(false);
+        };
+
+        let result = match current {
+            Some(lease) => {
+                let lease = Lease {
+                    spec: Some(spec),
+                    ..lease
+                };
+                self.api.replace(name, &PostParams::default(), &lease).await
+            }
+            None => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(name.clone()),
+                        ..Default::default()
+                    },
+                    spec: Some(spec),
+                };
+                self.api.create(&PostParams::default(), &lease).await
+            }
+        };
+
+        match result {
+            Ok(_) => Ok(true),
+            // another replica updated the lease first
+            Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gives up the lease so a standby can take over without waiting for it to expire
+    async fn release(&self) {
+        let name = &self.config.lease_name;
+        let Ok(Some(mut lease)) = self.api.get_opt(name).await else {
+            return;
+        };
+        let Some(spec) = lease.spec.as_mut() else {
+            return;
+        };
+        if spec.holder_identity.as_deref() != Some(self.config.identity.as_str()) {
+            return;
+        }
+        spec.holder_identity = None;
+        spec.renew_time = None;
+        if let Err(e) = self.api.replace(name, &PostParams::default(), &lease).await {
+            warn!("failed to release lease \"{name}\": {e}");
+        }
+    }
+
+    /// Runs the controllers built by `controllers` while this replica holds the lease.
+    ///
+    /// Returns once the controllers stop on their own, usually on shutdown.
+    pub async fn run<F, Fut>(self, controllers: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        self.state.set_leader(false);
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    sleep(self.config.retry_period()).await;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("failed to acquire lease: {e}");
+                    sleep(self.config.retry_period()).await;
+                    continue;
+                }
+            }
+
+            info!("acquired lease \"{}\"", self.config.lease_name);
+            self.state.set_leader(true);
+            let running = controllers();
+            tokio::pin!(running);
+            let mut renewed_at = Instant::now();
+            let stopped = loop {
+                tokio::select! {
+                    _ = &mut running => break true,
+                    _ = sleep(self.config.retry_period()) => {}
+                }
+                match self.try_acquire_or_renew().await {
+                    Ok(true) => renewed_at = Instant::now(),
+                    Ok(false) => break false,
+                    Err(e) => warn!("failed to renew lease: {e}"),
+                }
+                if renewed_at.elapsed() > self.config.renew_deadline() {
+                    break false;
+                }
+            };
+
+            self.state.set_leader(false);
+            if stopped {
+                self.release().await;
+                return;
+            }
+            warn!(
+                "lost lease \"{}\", stopping controllers",
+                self.config.lease_name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DURATION: Duration = Duration::from_secs(15);
+
+    fn held_by(holder: &str, renewed: DateTime<Utc>) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some(holder.to_string()),
+            renew_time: Some(MicroTime(renewed)),
+            lease_duration_seconds: Some(15),
+            lease_transitions: Some(3),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_acquire_free_lease() {
+        let now = Utc::now();
+        let spec = acquire(None, "a", now, DURATION).unwrap();
+        assert_eq!(spec.holder_identity.as_deref(), Some("a"));
+        assert_eq!(spec.renew_time, Some(MicroTime(now)));
+    }
+
+    #[test]
+    fn test_acquire_held_lease() {
+        let now = Utc::now();
+        let current = held_by("b", now - TimeDelta::seconds(5));
+        assert_eq!(acquire(Some(&current), "a", now, DURATION), None);
+
+        let renewed = acquire(Some(&current), "b", now, DURATION).unwrap();
+        assert_eq!(renewed.renew_time, Some(MicroTime(now)));
+        assert_eq!(renewed.lease_transitions, Some(3));
+    }
+
+    #[test]
+    fn test_acquire_expired_lease() {
+        let now = Utc::now();
+        let current = held_by("b", now - TimeDelta::seconds(30));
+        let spec = acquire(Some(&current), "a", now, DURATION).unwrap();
+        assert_eq!(spec.holder_identity.as_deref(), Some("a"));
+        assert_eq!(spec.lease_transitions, Some(4));
+    }
+}
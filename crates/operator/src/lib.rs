@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use chrono::{DateTime, Utc};
 use kube::{
@@ -8,9 +8,20 @@ use kube::{
 use serde::Serialize;
 use tokio::sync::RwLock;
 
-use crate::metrics::Metrics;
+use crate::{backoff::Backoff, config::ControllerConfig, health::Health, metrics::Metrics};
 
+mod backoff;
+mod conditions;
+pub mod config;
+pub mod crds;
+mod error;
+pub mod executor;
+pub mod health;
+pub mod leader_election;
 mod metrics;
+pub mod probe;
+pub mod scope;
+mod status;
 pub mod telemetry;
 pub mod worker_group;
 
@@ -20,6 +31,8 @@ pub struct Diagnostics {
     pub last_event: DateTime<Utc>,
     #[serde(skip)]
     pub reporter: Reporter,
+    /// Reconcile statistics, by controller name
+    pub controllers: BTreeMap<String, ControllerDiagnostics>,
 }
 
 impl Default for Diagnostics {
@@ -27,6 +40,7 @@ impl Default for Diagnostics {
         Self {
             last_event: Utc::now(),
             reporter: "probelet-operator".into(),
+            controllers: BTreeMap::new(),
         }
     }
 }
@@ -35,6 +49,36 @@ impl Diagnostics {
     fn recorder(&self, client: Client) -> Recorder {
         Recorder::new(client, self.reporter.clone())
     }
+
+    /// Records the outcome of a reconcile of `controller`
+    pub fn record_reconcile<E: std::fmt::Display>(
+        &mut self,
+        controller: &str,
+        result: Result<(), &E>,
+    ) {
+        let now = Utc::now();
+        self.last_event = now;
+        let stats = self.controllers.entry(controller.to_string()).or_default();
+        stats.reconciles += 1;
+        stats.last_reconcile = Some(now);
+        if let Err(error) = result {
+            stats.failures += 1;
+            stats.last_error = Some(error.to_string());
+        }
+    }
+}
+
+/// Reconcile statistics of a controller
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ControllerDiagnostics {
+    /// Number of reconciles run
+    pub reconciles: u64,
+    /// Number of reconciles that failed
+    pub failures: u64,
+    /// When the last reconcile ran
+    pub last_reconcile: Option<DateTime<Utc>>,
+    /// The error of the last failed reconcile
+    pub last_error: Option<String>,
 }
 
 /// State shared between the controller and the web server.
@@ -42,18 +86,31 @@ impl Diagnostics {
 pub struct AppState {
     diagnostics: Arc<RwLock<Diagnostics>>,
     metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    config: ControllerConfig,
 }
 
 impl Default for AppState {
     fn default() -> Self {
+        Self::new(ControllerConfig::default())
+    }
+}
+
+impl AppState {
+    pub fn new(config: ControllerConfig) -> Self {
         Self {
             diagnostics: Arc::new(RwLock::new(Diagnostics::default())),
             metrics: Arc::new(Metrics::default()),
+            health: Arc::new(Health::default()),
+            config,
         }
     }
-}
 
-impl AppState {
+    /// Get the tuning of the controllers.
+    pub fn controller_config(&self) -> &ControllerConfig {
+        &self.config
+    }
+
     /// Get the metrics as a string.
     pub fn metrics(&self) -> String {
         let mut buffer = String::new();
@@ -67,6 +124,35 @@ impl AppState {
         self.diagnostics.read().await.clone()
     }
 
+    /// Get the health of the controllers.
+    pub fn health(&self) -> Arc<Health> {
+        self.health.clone()
+    }
+
+    /// Record whether this replica is the leader running the controllers.
+    ///
+    /// The gauges of the `WorkerGroup`s are cleared when it is not, as only the leader
+    /// keeps them up to date.
+    pub fn set_leader(&self, leader: bool) {
+        self.health.set_standby(!leader);
+        self.metrics.leader.set(i64::from(leader));
+        if !leader {
+            self.metrics.worker_group.clear();
+        }
+    }
+
+    /// Whether the controllers are ready to reconcile.
+    pub fn readiness(&self) -> Result<(), String> {
+        self.health.readiness()
+    }
+
+    /// Whether the controllers are still making progress.
+    pub async fn liveness(&self) -> Result<(), String> {
+        let last_event = self.diagnostics.read().await.last_event;
+        self.health
+            .liveness(last_event, Utc::now(), self.config.liveness_threshold())
+    }
+
     /// Create a controller context that can update the state.
     pub async fn controller_context(&self, client: Client) -> Arc<Context> {
         Arc::new(Context {
@@ -74,6 +160,8 @@ impl AppState {
             recorder: self.diagnostics.read().await.recorder(client),
             metrics: self.metrics.clone(),
             diagnostics: self.diagnostics.clone(),
+            backoff: Arc::new(Backoff::default()),
+            config: self.config.clone(),
         })
     }
 }
@@ -87,4 +175,6 @@ pub struct Context {
     pub recorder: Recorder,
     pub diagnostics: Arc<RwLock<Diagnostics>>,
     pub metrics: Arc<Metrics>,
+    pub backoff: Arc<Backoff>,
+    pub config: ControllerConfig,
 }
@@ -1,14 +1,20 @@
-use axum::{Json, Router};
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
 use axum_extra::routing::RouterExt;
 use axum_extra::routing::TypedPath;
+use clap::Parser;
 use kube::Client;
-use kube::runtime::watcher::Config;
-use operator::AppState;
+use operator::config::{Cli, OperatorConfig};
+use operator::leader_election::LeaderElector;
+use operator::probe;
 use operator::telemetry;
-use operator::telemetry::TelemetryConfig;
 use operator::worker_group;
+use operator::{AppState, Diagnostics};
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tracing::info;
@@ -21,28 +27,108 @@ async fn health(_: HealthRoute) -> Json<&'static str> {
     Json("healthy")
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/readyz")]
+pub struct ReadyRoute;
+
+/// Ready once the CRDs are installed and the watch caches are synced
+async fn ready(_: ReadyRoute, State(state): State<AppState>) -> impl IntoResponse {
+    match state.readiness() {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/livez")]
+pub struct LiveRoute;
+
+/// Alive as long as the watchers and reconciles make progress
+async fn live(_: LiveRoute, State(state): State<AppState>) -> impl IntoResponse {
+    match state.liveness().await {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/metrics")]
+pub struct MetricsRoute;
+
+async fn metrics(_: MetricsRoute, State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        state.metrics(),
+    )
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/diagnostics")]
+pub struct DiagnosticsRoute;
+
+async fn diagnostics(_: DiagnosticsRoute, State(state): State<AppState>) -> Json<Diagnostics> {
+    Json(state.diagnostics().await)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let tracing_config = TelemetryConfig::from_env()?;
-    telemetry::init(&tracing_config).await;
+    let config = OperatorConfig::load(Cli::parse())?;
+    telemetry::init(&config.telemetry).await?;
 
-    let state = AppState::default();
+    let state = AppState::new(config.controller.clone());
 
-    info!("starting worker group controller");
     let client = Client::try_default().await?;
-    let watcher_config = Config::default();
-    let worker_group_controller = worker_group::run(client, watcher_config, state.clone());
+    let scope = config.watch.scope();
+    let leader_election_config = config.leader_election;
+    let controllers = {
+        let client = client.clone();
+        let state = state.clone();
+        move || {
+            info!("starting worker group and probe controllers");
+            let worker_groups = worker_group::run(client.clone(), scope.clone(), state.clone());
+            let probes = probe::run(client.clone(), scope.clone(), state.clone());
+            async move {
+                futures::future::join(worker_groups, probes).await;
+            }
+        }
+    };
+    let controllers = {
+        let state = state.clone();
+        async move {
+            if leader_election_config.enabled {
+                info!(
+                    "waiting for leader lease \"{}\"",
+                    leader_election_config.lease_name
+                );
+                LeaderElector::new(client, leader_election_config, state.clone())
+                    .run(controllers)
+                    .await;
+            } else {
+                state.set_leader(true);
+                controllers().await;
+            }
+        }
+    };
 
-    let app = Router::new().typed_get(health).with_state(state);
+    let app = Router::new()
+        .typed_get(health)
+        .typed_get(ready)
+        .typed_get(live)
+        .typed_get(metrics)
+        .typed_get(diagnostics)
+        .with_state(state);
 
-    info!("Starting server on port 8080");
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let addr = config.server.bind_address;
+    info!("Starting server on {addr}");
     let listener = TcpListener::bind(addr).await?;
     let server =
         axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal());
 
     tokio::select! {
-        _ = worker_group_controller => {},
+        _ = controllers => {},
         _ = server => {},
     }
 
@@ -1,10 +1,13 @@
 use opentelemetry::trace::TraceId;
 use prometheus_client::{
     encoding::EncodeLabelSet,
-    metrics::{counter::Counter, exemplar::HistogramWithExemplars, family::Family},
+    metrics::{counter::Counter, exemplar::HistogramWithExemplars, family::Family, gauge::Gauge},
     registry::{Registry, Unit},
 };
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
 use tokio::time::Instant;
 
 pub trait MetricLabel {
@@ -14,16 +17,30 @@ pub trait MetricLabel {
 #[derive(Clone)]
 pub struct Metrics {
     pub reconcile: ReconcileMetrics,
+    pub worker_group: WorkerGroupMetrics,
+    /// 1 while this replica holds the leader lease and runs the controllers
+    pub leader: Gauge,
     pub registry: Arc<Registry>,
 }
 
 impl Default for Metrics {
     fn default() -> Self {
-        let mut registry = Registry::with_prefix("doc_ctrl_reconcile");
-        let reconcile = ReconcileMetrics::default().register(&mut registry);
+        let mut registry = Registry::with_prefix("probelet_operator");
+        let reconcile =
+            ReconcileMetrics::default().register(registry.sub_registry_with_prefix("reconcile"));
+        let worker_group = WorkerGroupMetrics::default()
+            .register(registry.sub_registry_with_prefix("worker_group"));
+        let leader = Gauge::default();
+        registry.register(
+            "leader",
+            "whether this replica is the leader",
+            leader.clone(),
+        );
         Self {
             registry: Arc::new(registry),
             reconcile,
+            worker_group,
+            leader,
         }
     }
 }
@@ -45,27 +62,37 @@ impl TryFrom<&TraceId> for TraceLabel {
     }
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ControllerLabels {
+    pub controller: String,
+}
+
+type DurationHistogram = HistogramWithExemplars<TraceLabel>;
+
+fn duration_histogram() -> DurationHistogram {
+    HistogramWithExemplars::new([0.01, 0.1, 0.25, 0.5, 1., 5., 15., 60.].into_iter())
+}
+
 #[derive(Clone)]
 pub struct ReconcileMetrics {
-    pub runs: Counter,
+    pub runs: Family<ControllerLabels, Counter>,
     pub failures: Family<ErrorLabels, Counter>,
-    pub duration: HistogramWithExemplars<TraceLabel>,
+    pub duration: Family<ControllerLabels, DurationHistogram, fn() -> DurationHistogram>,
 }
 
 impl Default for ReconcileMetrics {
     fn default() -> Self {
         Self {
-            runs: Counter::default(),
-            failures: Family::<ErrorLabels, Counter>::default(),
-            duration: HistogramWithExemplars::new(
-                [0.01, 0.1, 0.25, 0.5, 1., 5., 15., 60.].into_iter(),
-            ),
+            runs: Family::default(),
+            failures: Family::default(),
+            duration: Family::new_with_constructor(duration_histogram),
         }
     }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct ErrorLabels {
+    pub controller: String,
     pub instance: String,
     pub error: String,
 }
@@ -84,25 +111,126 @@ impl ReconcileMetrics {
         self
     }
 
-    pub fn set_failure(&self, doc: &impl MetricLabel, e: &impl MetricLabel) {
+    pub fn set_failure(&self, controller: &str, doc: &impl MetricLabel, e: &impl MetricLabel) {
         self.failures
             .get_or_create(&ErrorLabels {
+                controller: controller.to_string(),
                 instance: doc.metric_label(),
                 error: e.metric_label(),
             })
             .inc();
     }
 
-    pub fn count_and_measure(&self, trace_id: &TraceId) -> ReconcileMeasurer {
-        self.runs.inc();
+    pub fn count_and_measure(&self, controller: &str, trace_id: &TraceId) -> ReconcileMeasurer {
+        let labels = ControllerLabels {
+            controller: controller.to_string(),
+        };
+        self.runs.get_or_create(&labels).inc();
         ReconcileMeasurer {
             start: Instant::now(),
             labels: trace_id.try_into().ok(),
-            metric: self.duration.clone(),
+            metric: self.duration.get_or_create(&labels).clone(),
         }
     }
 }
 
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct WorkerGroupLabels {
+    pub namespace: String,
+    pub worker_group: String,
+}
+
+/// Observed state of a `WorkerGroup`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkerGroupObservation {
+    pub desired_replicas: i64,
+    pub instances: i64,
+    pub ready_instances: i64,
+    pub crashlooping_pods: i64,
+}
+
+/// Gauges of each `WorkerGroup`, set on reconcile and removed once it is deleted or
+/// no longer watched
+#[derive(Clone, Default)]
+pub struct WorkerGroupMetrics {
+    pub desired_replicas: Family<WorkerGroupLabels, Gauge>,
+    pub instances: Family<WorkerGroupLabels, Gauge>,
+    pub ready_instances: Family<WorkerGroupLabels, Gauge>,
+    pub crashlooping_pods: Family<WorkerGroupLabels, Gauge>,
+    /// The groups having gauges, as the families cannot be iterated
+    groups: Arc<Mutex<HashSet<WorkerGroupLabels>>>,
+}
+
+impl WorkerGroupMetrics {
+    pub fn register(self, r: &mut Registry) -> Self {
+        r.register(
+            "desired_replicas",
+            "replicas requested by the spec",
+            self.desired_replicas.clone(),
+        );
+        r.register("instances", "worker pods", self.instances.clone());
+        r.register(
+            "ready_instances",
+            "ready worker pods",
+            self.ready_instances.clone(),
+        );
+        r.register(
+            "crashlooping_pods",
+            "worker pods with a container in CrashLoopBackOff",
+            self.crashlooping_pods.clone(),
+        );
+        self
+    }
+
+    pub fn set(&self, labels: &WorkerGroupLabels, observation: WorkerGroupObservation) {
+        self.groups.lock().unwrap().insert(labels.clone());
+        self.desired_replicas
+            .get_or_create(labels)
+            .set(observation.desired_replicas);
+        self.instances
+            .get_or_create(labels)
+            .set(observation.instances);
+        self.ready_instances
+            .get_or_create(labels)
+            .set(observation.ready_instances);
+        self.crashlooping_pods
+            .get_or_create(labels)
+            .set(observation.crashlooping_pods);
+    }
+
+    pub fn remove(&self, labels: &WorkerGroupLabels) {
+        self.groups.lock().unwrap().remove(labels);
+        self.remove_gauges(labels);
+    }
+
+    /// Removes the gauges of the groups `keep` returns false for
+    pub fn retain(&self, keep: impl Fn(&WorkerGroupLabels) -> bool) {
+        self.groups.lock().unwrap().retain(|labels| {
+            let kept = keep(labels);
+            if !kept {
+                self.remove_gauges(labels);
+            }
+            kept
+        });
+    }
+
+    /// Removes the gauges of every group
+    pub fn clear(&self) {
+        self.groups.lock().unwrap().clear();
+        self.desired_replicas.clear();
+        self.instances.clear();
+        self.ready_instances.clear();
+        self.crashlooping_pods.clear();
+    }
+
+    fn remove_gauges(&self, labels: &WorkerGroupLabels) {
+        self.desired_replicas.remove(labels);
+        self.instances.remove(labels);
+        self.ready_instances.remove(labels);
+        self.crashlooping_pods.remove(labels);
+    }
+}
+
 /// Smart function duration measurer
 ///
 /// Relies on Drop to calculate duration and register the observation in the histogram
@@ -120,3 +248,76 @@ impl Drop for ReconcileMeasurer {
         self.metric.observe(duration, labels);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::encoding::text::encode;
+
+    use super::*;
+
+    #[test]
+    fn test_worker_group_gauges() {
+        let metrics = Metrics::default();
+        let labels = WorkerGroupLabels {
+            namespace: "default".to_string(),
+            worker_group: "test".to_string(),
+        };
+        metrics.worker_group.set(
+            &labels,
+            WorkerGroupObservation {
+                desired_replicas: 3,
+                instances: 2,
+                ready_instances: 1,
+                crashlooping_pods: 1,
+            },
+        );
+
+        let mut output = String::new();
+        encode(&mut output, &metrics.registry).unwrap();
+        for expected in [
+            r#"probelet_operator_worker_group_desired_replicas{namespace="default",worker_group="test"} 3"#,
+            r#"probelet_operator_worker_group_instances{namespace="default",worker_group="test"} 2"#,
+            r#"probelet_operator_worker_group_ready_instances{namespace="default",worker_group="test"} 1"#,
+            r#"probelet_operator_worker_group_crashlooping_pods{namespace="default",worker_group="test"} 1"#,
+        ] {
+            assert!(output.contains(expected), "{expected} in {output}");
+        }
+
+        metrics.worker_group.remove(&labels);
+        let mut output = String::new();
+        encode(&mut output, &metrics.registry).unwrap();
+        assert!(!output.contains(r#"worker_group="test""#), "removed");
+    }
+
+    #[test]
+    fn test_worker_group_gauges_cleanup() {
+        let metrics = Metrics::default();
+        let labels = |namespace: &str, worker_group: &str| WorkerGroupLabels {
+            namespace: namespace.to_string(),
+            worker_group: worker_group.to_string(),
+        };
+        for group in [labels("team-a", "probes"), labels("team-b", "probes")] {
+            metrics
+                .worker_group
+                .set(&group, WorkerGroupObservation::default());
+        }
+        let encoded = || {
+            let mut output = String::new();
+            encode(&mut output, &metrics.registry).unwrap();
+            output
+        };
+
+        metrics
+            .worker_group
+            .retain(|group| group.namespace != "team-b");
+        let output = encoded();
+        assert!(output.contains(r#"namespace="team-a""#), "kept");
+        assert!(
+            !output.contains(r#"namespace="team-b""#),
+            "not watched anymore"
+        );
+
+        metrics.worker_group.clear();
+        assert!(!encoded().contains("team-a"), "cleared");
+    }
+}
@@ -0,0 +1,230 @@
+mod crd;
+mod error;
+mod reconcile;
+
+use std::sync::Arc;
+
+pub use crd::{
+    ConfigMapKeyRef, DnsAssertions, DnsProbe, DnsProtocol, DnsRecordType, DnsResponseCode,
+    GrpcProbe, HeaderAssertion, HttpAssertions, HttpProbe, HttpVersion, IcmpAssertions, IcmpProbe,
+    JsonPathAssertion, Probe, ProbeKind, ProbeSpec, ProbeStatus, TcpProbe, TlsProbe,
+};
+pub(crate) use error::ProbeError;
+use error::Result;
+use futures::StreamExt;
+use kube::{
+    Api, Client, ResourceExt,
+    api::ListParams,
+    runtime::{
+        Controller,
+        controller::{self, Action},
+        finalizer,
+        reflector::ObjectRef,
+        watcher::Config,
+    },
+};
+use snafu::ResultExt;
+use tracing::{Span, instrument, warn};
+
+use crate::{
+    AppState, Context, error::error_policy, probe::error::FinalizerSnafu, scope::WatchScope,
+    status::ReconciledResource, telemetry, worker_group::WorkerGroup,
+};
+
+const PROBE_FINALIZER: &str = "probelet.io/probe";
+
+/// The name of the controller in diagnostics
+const PROBE_CONTROLLER: &str = "probe";
+
+#[instrument(
+    skip(probe, context),
+    fields(
+        trace_id,
+        probe = %probe.name_any(),
+        namespace = probe.namespace().unwrap_or_default(),
+    )
+)]
+async fn reconcile(probe: Arc<Probe>, context: Arc<Context>) -> Result<Action> {
+    let trace_id = telemetry::get_trace_id();
+    if trace_id != opentelemetry::trace::TraceId::INVALID {
+        Span::current().record("trace_id", tracing::field::display(trace_id));
+    }
+    let _timer = context
+        .metrics
+        .reconcile
+        .count_and_measure(PROBE_CONTROLLER, &trace_id);
+    // we can unwrap because the probe is namespace scoped
+    let ns = probe.namespace().unwrap();
+    let probes = Api::<Probe>::namespaced(context.client.clone(), &ns);
+
+    tracing::info!(
+        "reconciling probe \"{}\" in ns \"{}\"",
+        probe.name_any(),
+        ns
+    );
+    let result = finalizer(&probes, PROBE_FINALIZER, probe.clone(), |event| async {
+        match event {
+            finalizer::Event::Apply(probe) => probe.reconcile(context.clone()).await,
+            finalizer::Event::Cleanup(probe) => probe.cleanup(context.clone()).await,
+        }
+    })
+    .await
+    .context(FinalizerSnafu);
+
+    context
+        .diagnostics
+        .write()
+        .await
+        .record_reconcile(PROBE_CONTROLLER, result.as_ref().map(|_| ()));
+
+    match &result {
+        Ok(_) => context
+            .backoff
+            .reset(&ObjectRef::from_obj(&*probe).to_string()),
+        Err(error) => {
+            if let Err(e) = probe.patch_reconcile_error(error, context.clone()).await {
+                warn!(
+                    "failed to report reconcile error of probe \"{}\": {e}",
+                    probe.name_any()
+                );
+            }
+        }
+    }
+
+    result
+}
+
+/// The probes run by `worker_group`, they are reconciled again when it changes
+fn probes_of(worker_group: &WorkerGroup, probes: &[Arc<Probe>]) -> Vec<ObjectRef<Probe>> {
+    probes
+        .iter()
+        .filter(|probe| {
+            probe.namespace() == worker_group.namespace()
+                && probe.spec.worker_group == worker_group.name_any()
+        })
+        .map(|probe| ObjectRef::from_obj(&**probe))
+        .collect()
+}
+
+/// Runs the `Probe` controllers of the namespaces in `scope`
+pub async fn run(client: Client, scope: WatchScope, state: AppState) {
+    let health = state.health();
+    scope
+        .run_in_namespaces(client.clone(), &health, |namespace| {
+            run_controller(client.clone(), namespace, &scope, state.clone())
+        })
+        .await;
+}
+
+/// Runs the `Probe` controller of `namespace`, or of all namespaces
+async fn run_controller(
+    client: Client,
+    namespace: Option<String>,
+    scope: &WatchScope,
+    state: AppState,
+) {
+    let (probes, worker_groups, name) = match &namespace {
+        Some(ns) => (
+            Api::<Probe>::namespaced(client.clone(), ns),
+            Api::<WorkerGroup>::namespaced(client.clone(), ns),
+            format!("{PROBE_CONTROLLER}/{ns}"),
+        ),
+        None => (
+            Api::<Probe>::all(client.clone()),
+            Api::<WorkerGroup>::all(client.clone()),
+            PROBE_CONTROLLER.to_string(),
+        ),
+    };
+
+    let health = state.health();
+    let _registration = health.register_scoped(&name);
+
+    if let Err(e) = probes.list(&ListParams::default().limit(1)).await {
+        tracing::error!("CRD is not queryable; {e:?}. Is the CRD installed?");
+        std::process::exit(1);
+    }
+    health.set_crd_ready(&name);
+
+    let concurrency = state.controller_config().concurrency;
+    let controller = Controller::new(probes, Config::default())
+        .with_config(controller::Config::default().concurrency(concurrency));
+    let reader = controller.store();
+    tokio::spawn({
+        let (health, reader, name) = (health.clone(), reader.clone(), name.clone());
+        async move {
+            if reader.wait_until_ready().await.is_ok() {
+                health.set_cache_synced(&name);
+            }
+        }
+    });
+
+    controller
+        .watches(worker_groups, scope.worker_group_config(), {
+            let reader = reader.clone();
+            move |worker_group| probes_of(&worker_group, &reader.state())
+        })
+        .shutdown_on_signal()
+        .run(
+            reconcile,
+            |probe, error, context| error_policy(PROBE_CONTROLLER, probe, error, context),
+            state.controller_context(client).await,
+        )
+        .for_each(|result| {
+            // watcher failures surface as queue errors
+            let watch_ok = !matches!(result, Err(controller::Error::QueueError(_)));
+            health.record_watch(&name, watch_ok, reader.len());
+            futures::future::ready(())
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::Resource;
+
+    use super::*;
+    use crate::worker_group::WorkerGroupSpec;
+
+    fn probe(name: &str, namespace: &str, worker_group: &str) -> Arc<Probe> {
+        let mut probe = Probe::new(
+            name,
+            ProbeSpec {
+                worker_group: worker_group.to_string(),
+                interval_seconds: 60,
+                timeout_seconds: 10,
+                kind: ProbeKind::Http(HttpProbe {
+                    url: "https://example.com".to_string(),
+                    ..Default::default()
+                }),
+            },
+        );
+        probe.meta_mut().namespace = Some(namespace.to_string());
+        Arc::new(probe)
+    }
+
+    #[test]
+    fn test_probes_of_worker_group() {
+        let mut worker_group = WorkerGroup::new(
+            "workers",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: Some("test".to_string()),
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        );
+        worker_group.meta_mut().namespace = Some("default".to_string());
+
+        let probes = [
+            probe("a", "default", "workers"),
+            probe("b", "default", "others"),
+            probe("c", "other", "workers"),
+        ];
+        let names = probes_of(&worker_group, &probes)
+            .into_iter()
+            .map(|object| object.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a"]);
+    }
+}
@@ -0,0 +1,984 @@
+use std::{collections::BTreeMap, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
+
+use http::{HeaderName, HeaderValue};
+use jsonpath_rust::JsonPath;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use kube::{
+    Api, CustomResource, Resource, ResourceExt,
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+    },
+};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue};
+use validator::{Validate, ValidationError, ValidationErrors};
+use validator_derive::Validate;
+
+use super::Result;
+use crate::{
+    Context,
+    error::KubeSnafu,
+    executor::{dns, http::StatusRange},
+    metrics::MetricLabel,
+    probe::reconcile::{CONDITION_ACCEPTED, EventReason},
+    status::ReconciledResource,
+    worker_group::WorkerGroup,
+};
+
+/// A `Probe` is a check run periodically by the workers of a `WorkerGroup`
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[kube(kind = "Probe", group = "probelet.dev", version = "v0", namespaced)]
+#[kube(status = "ProbeStatus", shortname = "probe")]
+#[kube(
+    printcolumn = r#"{"name":"Worker Group", "type":"string", "jsonPath":".spec.workerGroup"}"#,
+    printcolumn = r#"{"name":"Interval", "type":"integer", "jsonPath":".spec.intervalSeconds"}"#,
+    printcolumn = r#"{"name":"Accepted", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Accepted\")].status"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_timeout"))]
+pub struct ProbeSpec {
+    /// The `WorkerGroup` whose workers run the probe, in the namespace of the probe
+    #[schemars(length(min = 1, max = 253))]
+    pub worker_group: String,
+    /// How often the probe runs
+    #[serde(default = "default_interval_seconds")]
+    #[schemars(range(min = 1))]
+    pub interval_seconds: u32,
+    /// How long a run of the probe can take before it fails, at most `intervalSeconds`
+    #[serde(default = "default_timeout_seconds")]
+    #[schemars(range(min = 1))]
+    pub timeout_seconds: u32,
+    /// The kind of probe to use
+    #[validate(nested)]
+    pub kind: ProbeKind,
+}
+
+fn default_interval_seconds() -> u32 {
+    60
+}
+
+fn default_timeout_seconds() -> u32 {
+    10
+}
+
+fn validate_timeout(spec: &ProbeSpec) -> std::result::Result<(), ValidationError> {
+    if spec.timeout_seconds == 0 || spec.timeout_seconds > spec.interval_seconds {
+        return Err(ValidationError::new("timeout")
+            .with_message("timeoutSeconds must be between 1 and intervalSeconds".into()));
+    }
+    if let ProbeKind::Icmp(icmp) = &spec.kind
+        && icmp.duration() > Duration::from_secs(spec.timeout_seconds.into())
+    {
+        return Err(ValidationError::new("timeout").with_message(
+            "the echo requests of the probe and the wait for the last reply must fit in \
+             timeoutSeconds"
+                .into(),
+        ));
+    }
+    Ok(())
+}
+
+/// The kind of probe to use
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum ProbeKind {
+    /// A HTTP probe
+    Http(HttpProbe),
+    /// A TCP probe
+    Tcp(TcpProbe),
+    /// A DNS probe
+    Dns(DnsProbe),
+    /// A TLS probe
+    Tls(TlsProbe),
+    /// A gRPC health probe
+    Grpc(GrpcProbe),
+    /// An ICMP echo probe
+    Icmp(IcmpProbe),
+}
+
+impl Validate for ProbeKind {
+    fn validate(&self) -> std::result::Result<(), ValidationErrors> {
+        match self {
+            ProbeKind::Http(http) => http.validate(),
+            ProbeKind::Tcp(tcp) => tcp.validate(),
+            ProbeKind::Dns(dns) => dns.validate(),
+            ProbeKind::Tls(tls) => tls.validate(),
+            ProbeKind::Grpc(grpc) => grpc.validate(),
+            ProbeKind::Icmp(icmp) => icmp.validate(),
+        }
+    }
+}
+
+/// The HTTP methods a probe can use
+const HTTP_METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Requests an URL over HTTP and checks the response
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpProbe {
+    /// The HTTP method to use
+    #[validate(custom(function = "validate_http_method"))]
+    pub method: String,
+    /// The URL to monitor
+    #[validate(custom(function = "validate_http_url"))]
+    pub url: String,
+    /// Headers sent with the request. `Authorization`, `Cookie` and `Proxy-Authorization`
+    /// are not sent to redirects leaving the scheme, host and port of the url
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[validate(custom(function = "validate_http_headers"))]
+    pub headers: BTreeMap<String, String>,
+    /// Body sent with the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Whether redirects are followed, the assertions then apply to the last response
+    #[serde(default)]
+    pub follow_redirects: bool,
+    /// How many redirects are followed before the probe fails
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: u32,
+    /// The HTTP version of the request
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// What the response must satisfy for the probe to succeed
+    #[serde(default)]
+    #[validate(nested)]
+    pub assertions: HttpAssertions,
+}
+
+impl Default for HttpProbe {
+    fn default() -> Self {
+        Self {
+            method: "GET".to_string(),
+            url: String::new(),
+            headers: BTreeMap::new(),
+            body: None,
+            follow_redirects: false,
+            max_redirects: default_max_redirects(),
+            http_version: HttpVersion::default(),
+            assertions: HttpAssertions::default(),
+        }
+    }
+}
+
+fn default_max_redirects() -> u32 {
+    5
+}
+
+/// The HTTP version of a request
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum HttpVersion {
+    /// HTTP/2 when the server negotiates it over TLS, HTTP/1.1 otherwise
+    #[default]
+    Auto,
+    #[serde(rename = "HTTP/1.1")]
+    Http1,
+    /// HTTP/2, with prior knowledge over plain text
+    #[serde(rename = "HTTP/2")]
+    Http2,
+}
+
+/// The checks run against the response of a HTTP probe
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpAssertions {
+    /// The accepted status codes, as codes (`200`), ranges (`200-299`) or classes (`2xx`)
+    #[serde(default = "default_status_codes")]
+    #[validate(custom(function = "validate_status_codes"))]
+    pub status_codes: Vec<String>,
+    /// Headers the response must have
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[validate(nested)]
+    pub headers: Vec<HeaderAssertion>,
+    /// Text the body must contain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    /// Regular expression the body must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_regex"))]
+    pub body_matches: Option<String>,
+    /// Values the body must hold, it must then be JSON
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[validate(nested)]
+    pub json_path: Vec<JsonPathAssertion>,
+    /// How long the request can take, redirects included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_latency_ms: Option<u64>,
+}
+
+impl Default for HttpAssertions {
+    fn default() -> Self {
+        Self {
+            status_codes: default_status_codes(),
+            headers: Vec::new(),
+            body_contains: None,
+            body_matches: None,
+            json_path: Vec::new(),
+            max_latency_ms: None,
+        }
+    }
+}
+
+fn default_status_codes() -> Vec<String> {
+    vec!["2xx".to_string()]
+}
+
+/// A header the response must have, with a value equal to `equals` and matching `matches`
+/// when they are set
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+pub struct HeaderAssertion {
+    /// The name of the header, case insensitive
+    #[validate(custom(function = "validate_header_name"))]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    /// A regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_regex"))]
+    pub matches: Option<String>,
+}
+
+/// A value of the JSON body, selected by a JSONPath expression.
+///
+/// It holds if any selected value equals `equals`: strings are compared as is, other
+/// values by their JSON text.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+pub struct JsonPathAssertion {
+    /// A JSONPath expression, like `$.status`
+    #[validate(custom(function = "validate_json_path"))]
+    pub path: String,
+    pub equals: String,
+}
+
+fn validate_http_method(method: &str) -> std::result::Result<(), ValidationError> {
+    if !HTTP_METHODS.contains(&method) {
+        return Err(ValidationError::new("method")
+            .with_message(format!("method must be one of {}", HTTP_METHODS.join(", ")).into()));
+    }
+    Ok(())
+}
+
+fn validate_http_url(value: &str) -> std::result::Result<(), ValidationError> {
+    let url = url::Url::parse(value).map_err(|e| {
+        ValidationError::new("url").with_message(format!("url is invalid: {e}").into())
+    })?;
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(ValidationError::new("url")
+            .with_message("url must be an absolute http or https URL".into()));
+    }
+    Ok(())
+}
+
+fn validate_http_headers(
+    headers: &BTreeMap<String, String>,
+) -> std::result::Result<(), ValidationError> {
+    for (name, value) in headers {
+        validate_header_name(name)?;
+        if HeaderValue::from_str(value).is_err() {
+            return Err(ValidationError::new("headers")
+                .with_message(format!("header {name} has an invalid value").into()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_header_name(name: &str) -> std::result::Result<(), ValidationError> {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(ValidationError::new("header")
+            .with_message(format!("{name} is not a valid header name").into()));
+    }
+    Ok(())
+}
+
+fn validate_status_codes(patterns: &[String]) -> std::result::Result<(), ValidationError> {
+    if patterns.is_empty() {
+        return Err(ValidationError::new("statusCodes")
+            .with_message("statusCodes must not be empty".into()));
+    }
+    for pattern in patterns {
+        pattern
+            .parse::<StatusRange>()
+            .map_err(|e| ValidationError::new("statusCodes").with_message(e.into()))?;
+    }
+    Ok(())
+}
+
+fn validate_regex(pattern: &str) -> std::result::Result<(), ValidationError> {
+    Regex::new(pattern).map_err(|e| {
+        ValidationError::new("regex").with_message(format!("invalid regex: {e}").into())
+    })?;
+    Ok(())
+}
+
+fn validate_json_path(path: &str) -> std::result::Result<(), ValidationError> {
+    JsonPath::<serde_json::Value>::from_str(path).map_err(|e| {
+        ValidationError::new("jsonPath").with_message(format!("invalid JSONPath: {e}").into())
+    })?;
+    Ok(())
+}
+
+/// Connects to a port over TCP, optionally checking what the server answers
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+pub struct TcpProbe {
+    /// The host name or IP address to connect to
+    #[validate(custom(function = "validate_host"))]
+    pub host: String,
+    #[validate(range(min = 1))]
+    #[schemars(range(min = 1))]
+    pub port: u16,
+    /// Data sent once connected, like `"PING\r\n"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    /// A regular expression the banner of the server, or its response to `payload`, must match.
+    ///
+    /// The response is read until it matches, or the server closes the connection or stays
+    /// silent for a second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_regex"))]
+    pub expect: Option<String>,
+}
+
+fn validate_host(host: &str) -> std::result::Result<(), ValidationError> {
+    if host.parse::<IpAddr>().is_err() && url::Host::parse(host).is_err() {
+        return Err(ValidationError::new("host")
+            .with_message(format!("{host} is not a host name or IP address").into()));
+    }
+    Ok(())
+}
+
+/// Queries a nameserver and checks its answer
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsProbe {
+    /// The name to query, like `example.com`
+    #[validate(custom(function = "validate_dns_name"))]
+    pub name: String,
+    /// The type of the records to query
+    #[serde(default)]
+    pub record_type: DnsRecordType,
+    /// The address of the nameserver, like `10.96.0.10` or `10.96.0.10:5353`, the first
+    /// nameserver of `/etc/resolv.conf` of the worker by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_nameserver"))]
+    pub nameserver: Option<String>,
+    /// The transport of the query, queries over UDP are retried over TCP when the answer is
+    /// truncated
+    #[serde(default)]
+    pub protocol: DnsProtocol,
+    /// What the answer must satisfy for the probe to succeed
+    #[serde(default)]
+    #[validate(nested)]
+    pub assertions: DnsAssertions,
+}
+
+/// The type of DNS records a probe queries
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum DnsRecordType {
+    #[default]
+    A,
+    #[serde(rename = "AAAA")]
+    Aaaa,
+    #[serde(rename = "CNAME")]
+    Cname,
+    #[serde(rename = "MX")]
+    Mx,
+    #[serde(rename = "TXT")]
+    Txt,
+    #[serde(rename = "SRV")]
+    Srv,
+    #[serde(rename = "CAA")]
+    Caa,
+}
+
+/// The transport of a DNS query
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+/// The response code of a DNS answer
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsResponseCode {
+    #[default]
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NotImp,
+    Refused,
+}
+
+/// The checks run against the answer of a DNS probe.
+///
+/// Values are in their zone file format: `192.0.2.1` for A, `10 mail.example.com.` for MX,
+/// `0 5 443 web.example.com.` for SRV, `0 issue "letsencrypt.org"` for CAA and the
+/// concatenated strings for TXT. Only the records of the queried type are checked.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "validate_ttl_bounds"))]
+pub struct DnsAssertions {
+    /// The expected response code
+    #[serde(default)]
+    pub rcode: DnsResponseCode,
+    /// How many records the answer must have at least, 1 when `rcode` is `NOERROR` and 0
+    /// otherwise by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_answers: Option<u32>,
+    /// Values the answer must contain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contains: Vec<String>,
+    /// The values of the answer, in any order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Vec<String>>,
+    /// The minimum TTL of the records, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ttl: Option<u32>,
+    /// The maximum TTL of the records, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ttl: Option<u32>,
+    /// How long the nameserver can take to answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_time_ms: Option<u64>,
+}
+
+fn validate_dns_name(name: &str) -> std::result::Result<(), ValidationError> {
+    let labels = name.strip_suffix('.').unwrap_or(name);
+    let valid = !labels.is_empty()
+        && labels.len() <= 253
+        && labels
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63 && label.is_ascii());
+    if !valid {
+        return Err(ValidationError::new("name")
+            .with_message(format!("{name} is not a valid DNS name").into()));
+    }
+    Ok(())
+}
+
+fn validate_ttl_bounds(assertions: &DnsAssertions) -> std::result::Result<(), ValidationError> {
+    if let (Some(min), Some(max)) = (assertions.min_ttl, assertions.max_ttl)
+        && min > max
+    {
+        return Err(
+            ValidationError::new("ttl").with_message("minTtl must not exceed maxTtl".into())
+        );
+    }
+    Ok(())
+}
+
+fn validate_nameserver(nameserver: &str) -> std::result::Result<(), ValidationError> {
+    dns::parse_nameserver(nameserver)
+        .map_err(|e| ValidationError::new("nameserver").with_message(e.into()))?;
+    Ok(())
+}
+
+/// Connects to a port over TLS and checks the certificate it presents
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsProbe {
+    /// The host name or IP address to connect to
+    #[validate(custom(function = "validate_host"))]
+    pub host: String,
+    #[serde(default = "default_tls_port")]
+    #[validate(range(min = 1))]
+    #[schemars(range(min = 1))]
+    pub port: u16,
+    /// The name sent through SNI, which the certificate must be valid for, `host` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_host"))]
+    pub server_name: Option<String>,
+    /// The certificates of the CAs the chain must lead to, the system roots of the worker by
+    /// default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(nested)]
+    pub ca_bundle: Option<ConfigMapKeyRef>,
+    /// Fails the probe when the certificate expires in fewer days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_days_remaining: Option<u32>,
+}
+
+fn default_tls_port() -> u16 {
+    443
+}
+
+/// A key of a `ConfigMap` in the namespace of the probe
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash, JsonSchema, Validate)]
+pub struct ConfigMapKeyRef {
+    /// The name of the `ConfigMap`, like `kube-root-ca.crt`
+    #[validate(length(min = 1, max = 253))]
+    pub name: String,
+    /// The key holding the PEM encoded certificates
+    #[serde(default = "default_ca_bundle_key")]
+    #[validate(length(min = 1))]
+    pub key: String,
+}
+
+fn default_ca_bundle_key() -> String {
+    "ca.crt".to_string()
+}
+
+/// Calls `grpc.health.v1.Health/Check` and expects the service to be `SERVING`
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct GrpcProbe {
+    /// The host name or IP address to connect to
+    #[validate(custom(function = "validate_host"))]
+    pub host: String,
+    #[validate(range(min = 1))]
+    #[schemars(range(min = 1))]
+    pub port: u16,
+    /// The service to check, the whole server by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Connects over TLS, trusting the system roots of the worker, instead of plaintext
+    #[serde(default)]
+    pub tls: bool,
+    /// Metadata sent with the call, like `authorization`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[validate(custom(function = "validate_grpc_metadata"))]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Sends ICMP echo requests to a host and checks the replies
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct IcmpProbe {
+    /// The host name or IP address to ping
+    #[validate(custom(function = "validate_host"))]
+    pub host: String,
+    /// How many echo requests to send in each run
+    #[serde(default = "default_icmp_count")]
+    #[validate(range(min = 1, max = 100))]
+    #[schemars(range(min = 1, max = 100))]
+    pub count: u16,
+    /// How long to wait between two echo requests
+    #[serde(default = "default_icmp_interval_ms")]
+    #[validate(range(min = 10))]
+    #[schemars(range(min = 10))]
+    pub interval_ms: u32,
+    /// How long to wait for the reply to the last echo request
+    #[serde(default = "default_icmp_reply_timeout_ms")]
+    #[validate(range(min = 1))]
+    #[schemars(range(min = 1))]
+    pub reply_timeout_ms: u32,
+    /// Without assertions, the probe fails only when no reply is received
+    #[serde(default)]
+    #[validate(nested)]
+    pub assertions: IcmpAssertions,
+}
+
+impl IcmpProbe {
+    /// How long a run takes at most: the requests are sent `intervalMs` apart, then the
+    /// probe waits `replyTimeoutMs` for the last reply
+    pub fn duration(&self) -> Duration {
+        let interval = Duration::from_millis(self.interval_ms.into());
+        interval * u32::from(self.count.saturating_sub(1))
+            + Duration::from_millis(self.reply_timeout_ms.into())
+    }
+}
+
+fn default_icmp_count() -> u16 {
+    5
+}
+
+fn default_icmp_interval_ms() -> u32 {
+    200
+}
+
+fn default_icmp_reply_timeout_ms() -> u32 {
+    1000
+}
+
+/// Thresholds on the replies of an `Icmp` probe
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct IcmpAssertions {
+    /// The share of the echo requests left without reply, in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 0.0, max = 100.0))]
+    #[schemars(range(min = 0.0, max = 100.0))]
+    pub max_loss_percent: Option<f64>,
+    /// The average round-trip time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_avg_rtt_ms: Option<u64>,
+    /// The round-trip time of the slowest reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rtt_ms: Option<u64>,
+    /// The mean difference between the round-trip times of consecutive replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_jitter_ms: Option<u64>,
+}
+
+fn validate_grpc_metadata(
+    metadata: &BTreeMap<String, String>,
+) -> std::result::Result<(), ValidationError> {
+    for (key, value) in metadata {
+        if AsciiMetadataKey::from_bytes(key.as_bytes()).is_err() {
+            return Err(ValidationError::new("metadata")
+                .with_message(format!("{key} is not a valid ASCII metadata key").into()));
+        }
+        if AsciiMetadataValue::try_from(value.as_str()).is_err() {
+            return Err(ValidationError::new("metadata")
+                .with_message(format!("metadata {key} has an invalid value").into()));
+        }
+    }
+    Ok(())
+}
+
+/// The status object of `Probe`
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeStatus {
+    /// The `metadata.generation` of the `Probe` last reconciled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// The `Accepted`, `Scheduled` and `ReconcileError` conditions
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+impl MetricLabel for Probe {
+    fn metric_label(&self) -> String {
+        format!("probe__{}", self.name_any())
+    }
+}
+
+impl Probe {
+    pub(crate) async fn reconcile(&self, context: Arc<Context>) -> Result<Action> {
+        let worker_group = self.worker_group(context.clone()).await?;
+        let status = self.observed_status(worker_group.as_ref());
+        let rejected = status.condition(CONDITION_ACCEPTED) == Some(false)
+            && self
+                .status
+                .as_ref()
+                .and_then(|status| status.condition(CONDITION_ACCEPTED))
+                != Some(false);
+        self.patch_status(status, context.clone()).await?;
+
+        if rejected {
+            let note = self.spec.validate().err().map(|errors| errors.to_string());
+            self.publish(
+                EventType::Warning,
+                EventReason::ProbeRejected,
+                note,
+                context.clone(),
+            )
+            .await?;
+        }
+
+        match worker_group {
+            // the probe is requeued by the worker group watch once it is created
+            None => Ok(Action::await_change()),
+            Some(_) => Ok(Action::requeue(context.config.requeue_interval())),
+        }
+    }
+
+    /// Runs before the finalizer is removed, only recording the deletion: workers stop
+    /// running the probe as soon as it is marked for deletion, without waiting for it
+    pub(crate) async fn cleanup(&self, context: Arc<Context>) -> Result<Action> {
+        self.publish(
+            EventType::Normal,
+            EventReason::ProbeDeleted,
+            Some("Probe deleted".to_string()),
+            context,
+        )
+        .await?;
+        Ok(Action::await_change())
+    }
+
+    /// The `WorkerGroup` running the probe, `None` if it does not exist
+    async fn worker_group(&self, context: Arc<Context>) -> Result<Option<WorkerGroup>> {
+        let api =
+            Api::<WorkerGroup>::namespaced(context.client.clone(), &self.namespace().unwrap());
+        let worker_group = api
+            .get_opt(&self.spec.worker_group)
+            .await
+            .context(KubeSnafu {
+                message: format!(
+                    "Failed to get worker group {} of probe {}",
+                    self.spec.worker_group,
+                    self.name_any()
+                ),
+            })?;
+        Ok(worker_group)
+    }
+
+    async fn publish(
+        &self,
+        type_: EventType,
+        reason: EventReason,
+        note: Option<String>,
+        context: Arc<Context>,
+    ) -> Result<()> {
+        let event = Event {
+            type_,
+            reason: reason.to_string(),
+            note,
+            secondary: None,
+            action: reason.to_string(),
+        };
+        context
+            .recorder
+            .publish(&event, &self.object_ref(&()))
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to publish event for probe {}", self.name_any()),
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(method: &str, url: &str) -> ProbeSpec {
+        ProbeSpec {
+            worker_group: "test".to_string(),
+            interval_seconds: default_interval_seconds(),
+            timeout_seconds: default_timeout_seconds(),
+            kind: ProbeKind::Http(HttpProbe {
+                method: method.to_string(),
+                url: url.to_string(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_validate_http_probe() {
+        assert!(spec("GET", "https://example.com/health").validate().is_ok());
+        assert!(spec("HEAD", "http://10.0.0.1:8080").validate().is_ok());
+
+        for (method, url) in [
+            ("get", "https://example.com"),
+            ("CONNECT", "https://example.com"),
+            ("GET", "example.com"),
+            ("GET", "ftp://example.com"),
+            ("GET", "unix:/run/socket"),
+        ] {
+            assert!(spec(method, url).validate().is_err(), "{method} {url}");
+        }
+    }
+
+    #[test]
+    fn test_validate_http_assertions() {
+        let valid = HttpProbe {
+            url: "https://example.com".to_string(),
+            headers: BTreeMap::from([("Authorization".to_string(), "Bearer token".to_string())]),
+            assertions: HttpAssertions {
+                status_codes: vec!["200".to_string(), "3xx".to_string(), "400-404".to_string()],
+                headers: vec![HeaderAssertion {
+                    name: "content-type".to_string(),
+                    equals: None,
+                    matches: Some("^application/json".to_string()),
+                }],
+                body_matches: Some("ok|up".to_string()),
+                json_path: vec![JsonPathAssertion {
+                    path: "$.status".to_string(),
+                    equals: "ok".to_string(),
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+
+        let mut invalid = valid.clone();
+        invalid
+            .headers
+            .insert("bad header".to_string(), "value".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = valid.clone();
+        invalid.assertions.status_codes = vec!["20x".to_string()];
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = valid.clone();
+        invalid.assertions.status_codes = vec![];
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = valid.clone();
+        invalid.assertions.headers[0].matches = Some("(".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = valid.clone();
+        invalid.assertions.json_path[0].path = "status[".to_string();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_tcp_probe() {
+        let tcp = |host: &str, port: u16| TcpProbe {
+            host: host.to_string(),
+            port,
+            payload: None,
+            expect: Some("^\\+PONG".to_string()),
+        };
+        for (host, port) in [("redis.default.svc", 6379), ("10.0.0.1", 5432), ("::1", 22)] {
+            assert!(tcp(host, port).validate().is_ok(), "{host}:{port}");
+        }
+        for (host, port) in [("", 6379), ("redis db", 6379), ("redis", 0)] {
+            assert!(tcp(host, port).validate().is_err(), "{host}:{port}");
+        }
+
+        let mut invalid = tcp("redis", 6379);
+        invalid.expect = Some("(".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_dns_probe() {
+        let dns = |name: &str, nameserver: Option<&str>| DnsProbe {
+            name: name.to_string(),
+            record_type: DnsRecordType::A,
+            nameserver: nameserver.map(str::to_string),
+            protocol: DnsProtocol::Udp,
+            assertions: DnsAssertions::default(),
+        };
+        for (name, nameserver) in [
+            ("example.com", None),
+            ("_http._tcp.example.com.", Some("10.96.0.10")),
+            ("example.com", Some("10.96.0.10:5353")),
+            ("example.com", Some("[fd00::10]:53")),
+            ("example.com", Some("fd00::10")),
+        ] {
+            assert!(
+                dns(name, nameserver).validate().is_ok(),
+                "{name} {nameserver:?}"
+            );
+        }
+        for (name, nameserver) in [
+            ("", None),
+            (".", None),
+            ("example..com", None),
+            (&format!("{}.com", "a".repeat(64)), None),
+            ("example.com", Some("dns.example.com")),
+            ("example.com", Some("10.96.0.10:dns")),
+        ] {
+            assert!(
+                dns(name, nameserver).validate().is_err(),
+                "{name} {nameserver:?}"
+            );
+        }
+
+        let spec: DnsProbe = serde_json::from_value(serde_json::json!({
+            "name": "example.com",
+            "recordType": "AAAA",
+            "protocol": "TCP",
+            "assertions": {"rcode": "NXDOMAIN"},
+        }))
+        .unwrap();
+        assert_eq!(spec.record_type, DnsRecordType::Aaaa);
+        assert_eq!(spec.protocol, DnsProtocol::Tcp);
+        assert_eq!(spec.assertions.rcode, DnsResponseCode::NxDomain);
+
+        let mut invalid = dns("example.com", None);
+        invalid.assertions.min_ttl = Some(300);
+        invalid.assertions.max_ttl = Some(60);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_tls_probe_format() {
+        let tls: TlsProbe = serde_json::from_value(serde_json::json!({
+            "host": "10.0.0.1",
+            "serverName": "api.example.com",
+            "caBundle": {"name": "kube-root-ca.crt"},
+            "minDaysRemaining": 14,
+        }))
+        .unwrap();
+        assert_eq!(tls.port, 443);
+        assert_eq!(tls.ca_bundle.as_ref().unwrap().key, "ca.crt");
+        assert!(tls.validate().is_ok());
+
+        let mut invalid = tls.clone();
+        invalid.server_name = Some("api example".to_string());
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = tls;
+        invalid.ca_bundle.as_mut().unwrap().name = String::new();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_grpc_probe_format() {
+        let grpc: GrpcProbe = serde_json::from_value(serde_json::json!({
+            "host": "payments.internal",
+            "port": 50051,
+            "service": "payments.v1.Payments",
+            "metadata": {"authorization": "Bearer token"},
+        }))
+        .unwrap();
+        assert!(!grpc.tls);
+        assert!(grpc.validate().is_ok());
+
+        let mut invalid = grpc.clone();
+        invalid.port = 0;
+        assert!(invalid.validate().is_err());
+
+        // binary metadata is not supported
+        let mut invalid = grpc;
+        invalid.metadata = BTreeMap::from([("trace-bin".to_string(), "AAEC".to_string())]);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_timeout() {
+        let mut probe = spec("GET", "https://example.com");
+        probe.timeout_seconds = probe.interval_seconds + 1;
+        assert!(probe.validate().is_err());
+        probe.timeout_seconds = probe.interval_seconds;
+        assert!(probe.validate().is_ok());
+    }
+
+    #[test]
+    fn test_icmp_probe_format() {
+        let icmp: IcmpProbe = serde_json::from_value(serde_json::json!({
+            "host": "10.0.0.1",
+            "assertions": {"maxLossPercent": 20, "maxJitterMs": 5},
+        }))
+        .unwrap();
+        assert_eq!((icmp.count, icmp.interval_ms), (5, 200));
+        assert_eq!(icmp.duration(), Duration::from_millis(1800));
+        assert!(icmp.validate().is_ok());
+
+        let mut invalid = icmp.clone();
+        invalid.assertions.max_loss_percent = Some(120.0);
+        assert!(invalid.validate().is_err());
+
+        // 50 requests 200ms apart do not fit in the default timeout
+        let mut probe = spec("GET", "https://example.com");
+        probe.kind = ProbeKind::Icmp(IcmpProbe { count: 50, ..icmp });
+        assert!(probe.validate().is_err());
+        probe.timeout_seconds = 11;
+        probe.interval_seconds = 60;
+        assert!(probe.validate().is_ok());
+    }
+
+    #[test]
+    fn test_spec_format() {
+        let spec: ProbeSpec = serde_json::from_value(serde_json::json!({
+            "workerGroup": "test",
+            "kind": {"Http": {"method": "GET", "url": "https://example.com"}},
+        }))
+        .unwrap();
+        assert_eq!(spec.interval_seconds, 60);
+        assert_eq!(spec.timeout_seconds, 10);
+        assert_eq!(
+            spec.kind,
+            ProbeKind::Http(HttpProbe {
+                url: "https://example.com".to_string(),
+                ..Default::default()
+            })
+        );
+    }
+}
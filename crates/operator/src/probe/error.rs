@@ -0,0 +1,40 @@
+use kube::runtime::finalizer;
+use snafu::Snafu;
+
+use crate::{
+    backoff::Retry,
+    error::{KubeError, ReconcileError, finalizer_metric_label, finalizer_retry},
+    metrics::MetricLabel,
+};
+
+#[derive(Snafu, Debug)]
+#[snafu(visibility(pub(crate)))]
+pub(crate) enum ProbeError {
+    #[snafu(transparent)]
+    Kube { source: KubeError },
+    #[snafu(display("Finalizer error: {source}"))]
+    Finalizer {
+        #[snafu(source(from(finalizer::Error<ProbeError>, Box::new)))]
+        source: Box<finalizer::Error<ProbeError>>,
+    },
+}
+
+impl ReconcileError for ProbeError {
+    fn retry(&self) -> Retry {
+        match self {
+            ProbeError::Kube { source } => source.retry(),
+            ProbeError::Finalizer { source } => finalizer_retry(source),
+        }
+    }
+}
+
+impl MetricLabel for ProbeError {
+    fn metric_label(&self) -> String {
+        match self {
+            ProbeError::Kube { source } => source.metric_label(),
+            ProbeError::Finalizer { source } => finalizer_metric_label(source),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ProbeError>;
@@ -0,0 +1,188 @@
+use std::fmt::Display;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Condition;
+use validator::Validate;
+
+use crate::{
+    probe::{Probe, ProbeStatus},
+    status::{ReconcileStatus, ReconciledResource},
+    worker_group::WorkerGroup,
+};
+
+/// The spec of the probe is valid
+pub const CONDITION_ACCEPTED: &str = "Accepted";
+/// The `WorkerGroup` of the probe exists
+pub const CONDITION_SCHEDULED: &str = "Scheduled";
+
+#[derive(Debug, Clone)]
+pub enum EventReason {
+    /// The spec of the probe failed validation
+    ProbeRejected,
+    /// The probe was deleted
+    ProbeDeleted,
+}
+
+impl Display for EventReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl ReconcileStatus for ProbeStatus {
+    const RESOURCE: &'static str = "probe";
+
+    fn conditions_mut(&mut self) -> &mut Vec<Condition> {
+        &mut self.conditions
+    }
+}
+
+impl ProbeStatus {
+    /// The status of a condition, `None` if it is not set
+    pub fn condition(&self, type_: &str) -> Option<bool> {
+        self.conditions
+            .iter()
+            .find(|condition| condition.type_ == type_)
+            .map(|condition| condition.status == "True")
+    }
+
+    /// Marks the probe as no longer scheduled on its `WorkerGroup`, which is being deleted
+    pub(crate) fn release(&mut self, worker_group: &str, generation: Option<i64>) {
+        self.set_condition(
+            CONDITION_SCHEDULED,
+            false,
+            "WorkerGroupDeleted",
+            format!("Worker group \"{worker_group}\" is being deleted"),
+            generation,
+        );
+    }
+}
+
+impl Probe {
+    /// Computes the status of the `Probe` from its spec and its `WorkerGroup`
+    pub(crate) fn observed_status(&self, worker_group: Option<&WorkerGroup>) -> ProbeStatus {
+        let generation = self.metadata.generation;
+        let mut status = ProbeStatus {
+            observed_generation: generation,
+            conditions: self
+                .status
+                .as_ref()
+                .map(|status| status.conditions.clone())
+                .unwrap_or_default(),
+        };
+
+        match self.spec.validate() {
+            Ok(()) => status.set_condition(
+                CONDITION_ACCEPTED,
+                true,
+                "Valid",
+                "The probe spec is valid".to_string(),
+                generation,
+            ),
+            Err(errors) => status.set_condition(
+                CONDITION_ACCEPTED,
+                false,
+                "InvalidSpec",
+                errors.to_string(),
+                generation,
+            ),
+        }
+
+        let name = &self.spec.worker_group;
+        match worker_group {
+            Some(worker_group) if worker_group.metadata.deletion_timestamp.is_some() => {
+                status.release(name, generation)
+            }
+            Some(_) => status.set_condition(
+                CONDITION_SCHEDULED,
+                true,
+                "WorkerGroupFound",
+                format!("The probe runs on worker group \"{name}\""),
+                generation,
+            ),
+            None => status.set_condition(
+                CONDITION_SCHEDULED,
+                false,
+                "WorkerGroupNotFound",
+                format!("Worker group \"{name}\" does not exist"),
+                generation,
+            ),
+        }
+
+        status.set_reconcile_error(None, generation);
+        status
+    }
+}
+
+impl ReconciledResource for Probe {}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use kube::Resource;
+
+    use crate::{
+        probe::{HttpProbe, ProbeKind, ProbeSpec},
+        status::CONDITION_RECONCILE_ERROR,
+        worker_group::WorkerGroupSpec,
+    };
+
+    use super::*;
+
+    fn probe(url: &str) -> Probe {
+        let mut probe = Probe::new(
+            "test",
+            ProbeSpec {
+                worker_group: "workers".to_string(),
+                interval_seconds: 60,
+                timeout_seconds: 10,
+                kind: ProbeKind::Http(HttpProbe {
+                    url: url.to_string(),
+                    ..Default::default()
+                }),
+            },
+        );
+        probe.meta_mut().generation = Some(2);
+        probe
+    }
+
+    #[test]
+    fn test_observed_status_conditions() {
+        let mut worker_group = WorkerGroup::new(
+            "workers",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: Some("test".to_string()),
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        );
+
+        let status = probe("https://example.com").observed_status(Some(&worker_group));
+        assert_eq!(status.observed_generation, Some(2));
+        assert_eq!(status.condition(CONDITION_ACCEPTED), Some(true));
+        assert_eq!(status.condition(CONDITION_SCHEDULED), Some(true));
+        assert_eq!(status.condition(CONDITION_RECONCILE_ERROR), Some(false));
+
+        worker_group.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        let status = probe("https://example.com").observed_status(Some(&worker_group));
+        let scheduled = status
+            .conditions
+            .iter()
+            .find(|condition| condition.type_ == CONDITION_SCHEDULED)
+            .unwrap();
+        assert_eq!(scheduled.status, "False");
+        assert_eq!(scheduled.reason, "WorkerGroupDeleted");
+
+        let status = probe("example.com").observed_status(None);
+        assert_eq!(status.condition(CONDITION_ACCEPTED), Some(false));
+        assert_eq!(status.condition(CONDITION_SCHEDULED), Some(false));
+        let accepted = status
+            .conditions
+            .iter()
+            .find(|condition| condition.type_ == CONDITION_ACCEPTED)
+            .unwrap();
+        assert_eq!(accepted.reason, "InvalidSpec");
+        assert!(accepted.message.contains("url"), "{}", accepted.message);
+    }
+}
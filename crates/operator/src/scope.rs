@@ -0,0 +1,144 @@
+use std::{collections::HashMap, future::Future};
+
+use futures::{
+    StreamExt,
+    future::{AbortHandle, Abortable},
+    stream::FuturesUnordered,
+};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    Api, Client, ResourceExt,
+    runtime::watcher::{Config, Event, watcher},
+};
+use tracing::{info, warn};
+
+use crate::health::Health;
+
+/// The name of the namespace watcher in the health checks
+const NAMESPACE_WATCHER: &str = "namespaces";
+
+/// The namespaces watched by the controllers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Namespaces {
+    /// Every namespace of the cluster, requires cluster-wide permissions
+    #[default]
+    All,
+    /// A fixed list of namespaces, only requires permissions in those namespaces
+    List(Vec<String>),
+    /// The namespaces matching a label selector, followed as they are labelled
+    Selector(String),
+}
+
+/// Which objects the controllers watch
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WatchScope {
+    pub namespaces: Namespaces,
+    /// Label selector of the watched `WorkerGroup`s
+    pub worker_group_selector: Option<String>,
+}
+
+impl WatchScope {
+    /// The watcher config of the `WorkerGroup`s
+    pub fn worker_group_config(&self) -> Config {
+        match &self.worker_group_selector {
+            Some(selector) => Config::default().labels(selector),
+            None => Config::default(),
+        }
+    }
+
+    /// Runs `run` for each watched namespace, or once with `None` when watching all of them
+    pub async fn run_in_namespaces<F, Fut>(&self, client: Client, health: &Health, run: F)
+    where
+        F: Fn(Option<String>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        match &self.namespaces {
+            Namespaces::All => run(None).await,
+            Namespaces::List(namespaces) => {
+                futures::future::join_all(namespaces.iter().map(|ns| run(Some(ns.clone())))).await;
+            }
+            Namespaces::Selector(selector) => {
+                run_in_selected_namespaces(client, selector, health, run).await;
+            }
+        }
+    }
+}
+
+/// Runs `run` for each namespace matching `selector`, starting and stopping it as
+/// namespaces are labelled, unlabelled or deleted.
+///
+/// Returns once a run stops on its own, which happens on shutdown.
+async fn run_in_selected_namespaces<F, Fut>(client: Client, selector: &str, health: &Health, run: F)
+where
+    F: Fn(Option<String>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    health.register(NAMESPACE_WATCHER);
+    health.set_crd_ready(NAMESPACE_WATCHER);
+
+    let namespaces = Api::<Namespace>::all(client);
+    let mut events = watcher(namespaces, Config::default().labels(selector)).boxed();
+    let mut running: HashMap<String, AbortHandle> = HashMap::new();
+    let mut runs = FuturesUnordered::<Abortable<Fut>>::new();
+    let mut listed = Vec::new();
+
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            Some(result) = runs.next(), if !runs.is_empty() => {
+                if result.is_ok() {
+                    // not aborted, the controllers are shutting down
+                    break;
+                }
+                continue;
+            }
+        };
+        let start = |ns: String, running: &mut HashMap<String, AbortHandle>| {
+            if running.contains_key(&ns) {
+                return;
+            }
+            info!("watching namespace \"{ns}\"");
+            let (handle, registration) = AbortHandle::new_pair();
+            running.insert(ns.clone(), handle);
+            runs.push(Abortable::new(run(Some(ns)), registration));
+        };
+        match event {
+            Some(Ok(Event::Init)) => listed.clear(),
+            Some(Ok(Event::InitApply(ns))) => listed.push(ns.name_any()),
+            Some(Ok(Event::InitDone)) => {
+                running.retain(|ns, handle| {
+                    let keep = listed.contains(ns);
+                    if !keep {
+                        info!("no longer watching namespace \"{ns}\"");
+                        handle.abort();
+                    }
+                    keep
+                });
+                for ns in listed.drain(..) {
+                    start(ns, &mut running);
+                }
+                health.set_cache_synced(NAMESPACE_WATCHER);
+                health.record_watch(NAMESPACE_WATCHER, true, running.len());
+            }
+            Some(Ok(Event::Apply(ns))) => {
+                start(ns.name_any(), &mut running);
+                health.record_watch(NAMESPACE_WATCHER, true, running.len());
+            }
+            Some(Ok(Event::Delete(ns))) => {
+                if let Some(handle) = running.remove(&ns.name_any()) {
+                    info!("no longer watching namespace \"{}\"", ns.name_any());
+                    handle.abort();
+                }
+                health.record_watch(NAMESPACE_WATCHER, true, running.len());
+            }
+            Some(Err(e)) => {
+                warn!("namespace watcher failed: {e}");
+                health.record_watch(NAMESPACE_WATCHER, false, running.len());
+            }
+            None => break,
+        }
+    }
+
+    // let the other controllers shut down as well
+    while runs.next().await.is_some() {}
+}
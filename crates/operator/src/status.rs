@@ -0,0 +1,136 @@
+use std::{
+    fmt::{Debug, Display},
+    sync::Arc,
+};
+
+use k8s_openapi::{NamespaceResourceScope, apimachinery::pkg::apis::meta::v1::Condition};
+use kube::{
+    Api, Resource, ResourceExt,
+    api::{Patch, PatchParams},
+    core::object::HasStatus,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
+use snafu::ResultExt;
+
+use crate::{
+    Context, conditions,
+    error::{KubeError, KubeSnafu},
+};
+
+/// The field manager used when applying the objects and statuses owned by the operator
+pub(crate) const FIELD_MANAGER: &str = "probelet-operator";
+
+/// The last reconcile of the resource failed
+pub(crate) const CONDITION_RECONCILE_ERROR: &str = "ReconcileError";
+
+/// The status of a resource, which reports the outcome of its reconciles in its conditions
+pub(crate) trait ReconcileStatus: Serialize + PartialEq + Clone + Default {
+    /// How the resource is called in the messages of its conditions
+    const RESOURCE: &'static str;
+
+    fn conditions_mut(&mut self) -> &mut Vec<Condition>;
+
+    /// Sets a condition, keeping its `lastTransitionTime` when the status does not change
+    fn set_condition(
+        &mut self,
+        type_: &str,
+        status: bool,
+        reason: &str,
+        message: String,
+        generation: Option<i64>,
+    ) {
+        conditions::set_condition(
+            self.conditions_mut(),
+            type_,
+            status,
+            reason,
+            message,
+            generation,
+        );
+    }
+
+    /// Reports the error of the last reconcile in the `ReconcileError` condition,
+    /// or clears it when `error` is `None`
+    fn set_reconcile_error(&mut self, error: Option<&dyn Display>, generation: Option<i64>) {
+        match error {
+            Some(error) => self.set_condition(
+                CONDITION_RECONCILE_ERROR,
+                true,
+                "ReconcileFailed",
+                error.to_string(),
+                generation,
+            ),
+            None => self.set_condition(
+                CONDITION_RECONCILE_ERROR,
+                false,
+                "ReconcileSucceeded",
+                format!("The {} was reconciled", Self::RESOURCE),
+                generation,
+            ),
+        }
+    }
+}
+
+/// A namespaced resource of the operator whose status it applies
+pub(crate) trait ReconciledResource:
+    Resource<DynamicType = (), Scope = NamespaceResourceScope>
+    + HasStatus<Status: ReconcileStatus>
+    + Clone
+    + Debug
+    + DeserializeOwned
+    + Serialize
+{
+    /// Reports a failed reconcile in the `ReconcileError` condition
+    async fn patch_reconcile_error(
+        &self,
+        error: &(dyn Display + Sync),
+        context: Arc<Context>,
+    ) -> Result<(), KubeError> {
+        // the status may have been applied earlier in the failed reconcile
+        let api = Api::<Self>::namespaced(context.client.clone(), &self.namespace().unwrap());
+        let latest = api.get_status(&self.name_any()).await.context(KubeSnafu {
+            message: format!(
+                "Failed to get status of {} {}",
+                Self::Status::RESOURCE,
+                self.name_any()
+            ),
+        })?;
+        let mut status = latest.status().cloned().unwrap_or_default();
+        status.set_reconcile_error(Some(error), self.meta().generation);
+        latest.patch_status(status, context).await
+    }
+
+    /// Server-side applies the status of the resource, skipping the call if it did not change
+    async fn patch_status(
+        &self,
+        status: Self::Status,
+        context: Arc<Context>,
+    ) -> Result<(), KubeError> {
+        if self.status() == Some(&status) {
+            return Ok(());
+        }
+
+        let api = Api::<Self>::namespaced(context.client.clone(), &self.namespace().unwrap());
+        let patch = json!({
+            "apiVersion": Self::api_version(&()),
+            "kind": Self::kind(&()),
+            "status": status,
+        });
+        api.patch_status(
+            &self.name_any(),
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(patch),
+        )
+        .await
+        .context(KubeSnafu {
+            message: format!(
+                "Failed to patch status of {} {}",
+                Self::Status::RESOURCE,
+                self.name_any()
+            ),
+        })?;
+
+        Ok(())
+    }
+}
@@ -1,33 +1,155 @@
 #![allow(unused_imports)] // some used only for telemetry feature
-use opentelemetry::trace::{TraceId, TracerProvider};
+use std::collections::{BTreeMap, HashMap};
+
+use clap::ValueEnum;
+use opentelemetry::{
+    KeyValue,
+    trace::{TraceId, TracerProvider},
+};
+use opentelemetry_otlp::{
+    Protocol, SpanExporter, WithExportConfig, WithHttpConfig, WithTonicConfig,
+    tonic_types::metadata::MetadataMap,
+};
 use opentelemetry_sdk::{Resource, trace as sdktrace};
-use sdktrace::{SdkTracer, SdkTracerProvider};
-use snafu::{ResultExt, Whatever};
-use tracing_subscriber::{EnvFilter, Registry, prelude::*};
-use validator::Validate;
+use percent_encoding::percent_decode_str;
+use sdktrace::{Sampler, SdkTracerProvider};
+use serde::Deserialize;
+use snafu::{ResultExt, Whatever, whatever};
+use tracing_subscriber::{EnvFilter, Registry, fmt::format::JsonFields, prelude::*};
+use validator::{Validate, ValidationError};
 use validator_derive::Validate;
 
-#[derive(Debug, Validate, Default)]
+mod json;
+
+/// Format of the logs written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Compact human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the span fields, trace id and service version
+    Json,
+}
+
+/// Transport of the OTLP exporter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum OtlpProtocol {
+    #[default]
+    #[serde(rename = "grpc")]
+    #[value(name = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    #[value(name = "http/protobuf")]
+    HttpProtobuf,
+}
+
+/// Sampler of the traces, named like the values of `OTEL_TRACES_SAMPLER`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum SamplerKind {
+    #[serde(rename = "always_on")]
+    #[value(name = "always_on")]
+    AlwaysOn,
+    #[serde(rename = "always_off")]
+    #[value(name = "always_off")]
+    AlwaysOff,
+    #[serde(rename = "traceidratio")]
+    #[value(name = "traceidratio")]
+    TraceIdRatio,
+    #[default]
+    #[serde(rename = "parentbased_always_on")]
+    #[value(name = "parentbased_always_on")]
+    ParentBasedAlwaysOn,
+    #[serde(rename = "parentbased_always_off")]
+    #[value(name = "parentbased_always_off")]
+    ParentBasedAlwaysOff,
+    #[serde(rename = "parentbased_traceidratio")]
+    #[value(name = "parentbased_traceidratio")]
+    ParentBasedTraceIdRatio,
+}
+
+#[derive(Debug, Clone, Validate, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
 pub struct TelemetryConfig {
-    /// Enabled telemetry
-    enabled: bool,
-    /// The endpoint to send telemetry to
+    /// Enabled telemetry, set when an endpoint is configured
+    #[serde(skip)]
+    pub enabled: bool,
+    /// The endpoint to send telemetry to, `/v1/traces` is appended to it over HTTP
     #[validate(url)]
-    endpoint: Option<String>,
+    pub endpoint: Option<String>,
+    /// The endpoint to send the traces to, used as is instead of `endpoint`
+    #[validate(url)]
+    pub traces_endpoint: Option<String>,
+    /// Transport of the exporter
+    pub protocol: OtlpProtocol,
+    /// Headers sent with every export, e.g. for authentication
+    #[validate(custom(function = "validate_headers"))]
+    pub headers: BTreeMap<String, String>,
+    pub sampler: SamplerKind,
+    /// Ratio of the sampled traces for the ratio based samplers
+    #[validate(range(min = 0.0, max = 1.0))]
+    pub sampler_ratio: f64,
+    /// Name of the service in the traces, the name of the crate by default
+    pub service_name: Option<String>,
+    /// Additional attributes of the service resource
+    pub resource_attributes: BTreeMap<String, String>,
+    /// Format of the logs
+    pub log_format: LogFormat,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            traces_endpoint: None,
+            protocol: OtlpProtocol::default(),
+            headers: BTreeMap::new(),
+            sampler: SamplerKind::default(),
+            sampler_ratio: 1.0,
+            service_name: None,
+            resource_attributes: BTreeMap::new(),
+            log_format: LogFormat::default(),
+        }
+    }
+}
+
+/// Parses a `key1=value1,key2=value2` list with percent-encoded values, as used by the
+/// `OTEL_*` variables
+fn parse_key_values(input: &str) -> Result<BTreeMap<String, String>, Whatever> {
+    input
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => {
+                let value = percent_decode_str(value.trim())
+                    .decode_utf8()
+                    .with_whatever_context(|_| format!("Invalid value in \"{pair}\""))?;
+                Ok((key.trim().to_string(), value.into_owned()))
+            }
+            None => whatever!("Invalid key-value pair \"{pair}\""),
+        })
+        .collect()
+}
+
+fn validate_headers(headers: &BTreeMap<String, String>) -> Result<(), ValidationError> {
+    for (name, value) in headers {
+        if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(ValidationError::new("headers")
+                .with_message(format!("{name} is not a valid header name").into()));
+        }
+        if http::HeaderValue::from_str(value).is_err() {
+            return Err(ValidationError::new("headers")
+                .with_message(format!("header {name} has an invalid value").into()));
+        }
+    }
+    Ok(())
 }
 
 impl TelemetryConfig {
     pub fn from_env() -> Result<Self, Whatever> {
-        let config = match std::env::var("OPENTELEMETRY_ENDPOINT_URL") {
-            Ok(endpoint) => Self {
-                enabled: true,
-                endpoint: Some(endpoint),
-            },
-            Err(_) => Self {
-                enabled: false,
-                endpoint: None,
-            },
-        };
+        let mut config = Self::default();
+        config.apply_env()?;
         config.validate().whatever_context("Invalid config")?;
         Ok(config)
     }
@@ -36,10 +158,89 @@ impl TelemetryConfig {
         let config = Self {
             enabled: endpoint.is_some(),
             endpoint,
+            ..Default::default()
         };
         config.validate().whatever_context("Invalid config")?;
         Ok(config)
     }
+
+    /// Overrides the config with `OPENTELEMETRY_ENDPOINT_URL` and the standard `OTEL_*`
+    /// environment variables of the exporter, sampler and resource
+    pub fn apply_env(&mut self) -> Result<(), Whatever> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        if let Some(endpoint) =
+            var("OPENTELEMETRY_ENDPOINT_URL").or_else(|| var("OTEL_EXPORTER_OTLP_ENDPOINT"))
+        {
+            self.endpoint = Some(endpoint);
+        }
+        if let Some(endpoint) = var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+            self.traces_endpoint = Some(endpoint);
+        }
+        if let Some(protocol) =
+            var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL").or_else(|| var("OTEL_EXPORTER_OTLP_PROTOCOL"))
+        {
+            self.protocol = match OtlpProtocol::from_str(&protocol, false) {
+                Ok(protocol) => protocol,
+                Err(_) => whatever!("Invalid OTLP protocol \"{protocol}\""),
+            };
+        }
+        for name in [
+            "OTEL_EXPORTER_OTLP_HEADERS",
+            "OTEL_EXPORTER_OTLP_TRACES_HEADERS",
+        ] {
+            if let Some(headers) = var(name) {
+                self.headers.extend(parse_key_values(&headers)?);
+            }
+        }
+        if let Some(sampler) = var("OTEL_TRACES_SAMPLER") {
+            self.sampler = match SamplerKind::from_str(&sampler, false) {
+                Ok(sampler) => sampler,
+                Err(_) => whatever!("Invalid sampler \"{sampler}\""),
+            };
+        }
+        if let Some(ratio) = var("OTEL_TRACES_SAMPLER_ARG") {
+            self.sampler_ratio = ratio
+                .parse()
+                .with_whatever_context(|_| format!("Invalid sampler ratio \"{ratio}\""))?;
+        }
+        if let Some(name) = var("OTEL_SERVICE_NAME") {
+            self.service_name = Some(name);
+        }
+        if let Some(attributes) = var("OTEL_RESOURCE_ATTRIBUTES") {
+            self.resource_attributes
+                .extend(parse_key_values(&attributes)?);
+        }
+        self.enabled = (self.endpoint.is_some() || self.traces_endpoint.is_some())
+            && var("OTEL_SDK_DISABLED").is_none_or(|v| v != "true");
+        Ok(())
+    }
+
+    /// The URL the spans are exported to
+    fn export_url(&self) -> Option<String> {
+        if let Some(endpoint) = &self.traces_endpoint {
+            return Some(endpoint.clone());
+        }
+        let endpoint = self.endpoint.as_ref()?;
+        Some(match self.protocol {
+            OtlpProtocol::Grpc => endpoint.clone(),
+            OtlpProtocol::HttpProtobuf => {
+                format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+            }
+        })
+    }
+
+    fn sampler(&self) -> Sampler {
+        let ratio = || Sampler::TraceIdRatioBased(self.sampler_ratio);
+        match self.sampler {
+            SamplerKind::AlwaysOn => Sampler::AlwaysOn,
+            SamplerKind::AlwaysOff => Sampler::AlwaysOff,
+            SamplerKind::TraceIdRatio => ratio(),
+            SamplerKind::ParentBasedAlwaysOn => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+            SamplerKind::ParentBasedAlwaysOff => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+            SamplerKind::ParentBasedTraceIdRatio => Sampler::ParentBased(Box::new(ratio())),
+        }
+    }
 }
 
 ///  Fetch an opentelemetry::trace::TraceId as hex through the full tracing stack
@@ -53,33 +254,81 @@ pub fn get_trace_id() -> TraceId {
         .trace_id()
 }
 
-fn resource() -> Resource {
-    use opentelemetry::KeyValue;
+/// The service resource, with the Kubernetes attributes of the pod when they are exposed
+/// through the downward API
+fn resource(config: &TelemetryConfig) -> Resource {
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+    let kubernetes = [
+        ("k8s.pod.name", "POD_NAME"),
+        ("k8s.namespace.name", "POD_NAMESPACE"),
+        ("k8s.node.name", "NODE_NAME"),
+    ]
+    .into_iter()
+    .filter_map(|(key, var)| Some(KeyValue::new(key, std::env::var(var).ok()?)));
+
     Resource::builder()
-        .with_service_name(env!("CARGO_PKG_NAME"))
+        .with_service_name(service_name)
         .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+        .with_attributes(kubernetes)
+        .with_attributes(
+            config
+                .resource_attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+        )
         .build()
 }
 
-fn init_tracer(endpoint: &str) -> SdkTracer {
-    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
-    let exporter = SpanExporter::builder()
-        .with_tonic()
-        .with_endpoint(endpoint)
-        .build()
-        .unwrap();
-
-    let provider = SdkTracerProvider::builder()
-        .with_resource(resource())
-        .with_batch_exporter(exporter)
-        .build();
+fn span_exporter(config: &TelemetryConfig, endpoint: &str) -> Result<SpanExporter, Whatever> {
+    let exporter = match config.protocol {
+        OtlpProtocol::Grpc => {
+            let headers = config
+                .headers
+                .clone()
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            let headers =
+                http::HeaderMap::try_from(&headers).whatever_context("Invalid OTLP headers")?;
+            let metadata = MetadataMap::from_headers(headers);
+            SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .with_metadata(metadata)
+                .build()
+        }
+        OtlpProtocol::HttpProtobuf => SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpBinary)
+            .with_endpoint(endpoint)
+            .with_headers(config.headers.clone().into_iter().collect())
+            .build(),
+    };
+    exporter.whatever_context("Failed to build the OTLP exporter")
+}
 
-    provider.tracer("tracing-otel-subscriber")
+fn tracer_provider(
+    config: &TelemetryConfig,
+    endpoint: &str,
+) -> Result<SdkTracerProvider, Whatever> {
+    Ok(SdkTracerProvider::builder()
+        .with_resource(resource(config))
+        .with_sampler(config.sampler())
+        .with_batch_exporter(span_exporter(config, endpoint)?)
+        .build())
 }
 
 /// Initialize tracing
-pub async fn init(config: &TelemetryConfig) {
-    let logger = tracing_subscriber::fmt::layer().compact();
+pub async fn init(config: &TelemetryConfig) -> Result<(), Whatever> {
+    let logger = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().compact().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(json::JsonFormat::new(&resource(config)))
+            .boxed(),
+    };
     let env_filter = EnvFilter::try_from_default_env()
         .or(EnvFilter::try_new("info"))
         .unwrap();
@@ -87,14 +336,15 @@ pub async fn init(config: &TelemetryConfig) {
     // Decide on layers
     let reg = Registry::default().with(env_filter).with(logger);
 
-    if config.enabled && config.endpoint.is_some() {
-        let otel = tracing_opentelemetry::OpenTelemetryLayer::new(init_tracer(
-            config.endpoint.as_ref().unwrap(),
-        ));
-        reg.with(otel).init();
-    } else {
-        reg.init();
+    match config.export_url() {
+        Some(endpoint) if config.enabled => {
+            let tracer = tracer_provider(config, &endpoint)?.tracer("tracing-otel-subscriber");
+            let otel = tracing_opentelemetry::OpenTelemetryLayer::new(tracer);
+            reg.with(otel).init();
+        }
+        _ => reg.init(),
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -133,4 +383,138 @@ mod test {
             assert!(result.is_err(), "invalid endpoint");
         });
     }
+
+    #[test_log::test(tokio::test)]
+    async fn telemetry_config_from_otel_env() {
+        temp_env::with_vars(
+            [
+                ("OPENTELEMETRY_ENDPOINT_URL", None),
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", Some("http://collector:4318")),
+                ("OTEL_EXPORTER_OTLP_PROTOCOL", Some("http/protobuf")),
+                (
+                    "OTEL_EXPORTER_OTLP_HEADERS",
+                    Some("x-api-key=secret, x-tenant=team%20a"),
+                ),
+                ("OTEL_TRACES_SAMPLER", Some("parentbased_traceidratio")),
+                ("OTEL_TRACES_SAMPLER_ARG", Some("0.25")),
+                ("OTEL_SERVICE_NAME", Some("probelet")),
+                (
+                    "OTEL_RESOURCE_ATTRIBUTES",
+                    Some("deployment.environment=test"),
+                ),
+            ],
+            || {
+                let config = TelemetryConfig::from_env().unwrap();
+                assert!(config.enabled);
+                assert_eq!(config.endpoint.as_deref(), Some("http://collector:4318"));
+                assert_eq!(config.protocol, OtlpProtocol::HttpProtobuf);
+                assert_eq!(
+                    config.export_url().as_deref(),
+                    Some("http://collector:4318/v1/traces")
+                );
+                assert_eq!(config.headers["x-api-key"], "secret");
+                assert_eq!(config.headers["x-tenant"], "team a");
+                assert_eq!(config.sampler, SamplerKind::ParentBasedTraceIdRatio);
+                assert_eq!(config.sampler_ratio, 0.25);
+                assert_eq!(config.service_name.as_deref(), Some("probelet"));
+                assert_eq!(config.resource_attributes["deployment.environment"], "test");
+            },
+        );
+
+        for (name, value) in [
+            ("OTEL_TRACES_SAMPLER", "sometimes"),
+            ("OTEL_TRACES_SAMPLER_ARG", "2"),
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/json"),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "x-api-key"),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "x api key=secret"),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "x-api-key=line%0Abreak"),
+        ] {
+            temp_env::with_var(name, Some(value), || {
+                assert!(TelemetryConfig::from_env().is_err(), "{name}={value}");
+            });
+        }
+    }
+
+    #[test]
+    fn telemetry_export_url() {
+        let mut config = TelemetryConfig::new(Some("http://collector:4317/".to_string())).unwrap();
+        assert_eq!(
+            config.export_url().as_deref(),
+            Some("http://collector:4317/"),
+            "grpc"
+        );
+
+        config.protocol = OtlpProtocol::HttpProtobuf;
+        config.endpoint = Some("http://collector:4318/otlp/".to_string());
+        assert_eq!(
+            config.export_url().as_deref(),
+            Some("http://collector:4318/otlp/v1/traces"),
+            "appended to the base endpoint"
+        );
+
+        config.traces_endpoint = Some("http://collector:4318/custom".to_string());
+        assert_eq!(
+            config.export_url().as_deref(),
+            Some("http://collector:4318/custom"),
+            "signal endpoint used as is"
+        );
+    }
+
+    /// Exports a span over OTLP HTTP to a stand-in collector
+    #[test_log::test(tokio::test(flavor = "multi_thread"))]
+    async fn export_spans_over_http() {
+        use std::sync::{Arc, Mutex};
+
+        use axum::{Router, body::Bytes, extract::State, http::HeaderMap, routing::post};
+        use opentelemetry::trace::{Tracer, TracerProvider as _};
+
+        type Requests = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+        async fn collect(State(requests): State<Requests>, headers: HeaderMap, body: Bytes) {
+            requests.lock().unwrap().push((headers, body));
+        }
+
+        let requests = Requests::default();
+        let collector = Router::new()
+            .route("/v1/traces", post(collect))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+
+        let config = TelemetryConfig {
+            enabled: true,
+            endpoint: Some(endpoint.clone()),
+            protocol: OtlpProtocol::HttpProtobuf,
+            headers: BTreeMap::from([("x-api-key".to_string(), "secret".to_string())]),
+            resource_attributes: BTreeMap::from([(
+                "deployment.environment".to_string(),
+                "test".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let provider = tracer_provider(&config, &config.export_url().unwrap()).unwrap();
+        provider
+            .tracer("test")
+            .in_span("reconcile-worker-group", |_| {});
+        // the exporter blocks while sending
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1, "one export");
+        let (headers, body) = &requests[0];
+        assert_eq!(headers["x-api-key"], "secret");
+        assert_eq!(headers["content-type"], "application/x-protobuf");
+        let body = String::from_utf8_lossy(body);
+        for expected in [
+            "reconcile-worker-group",
+            env!("CARGO_PKG_NAME"),
+            "deployment.environment",
+        ] {
+            assert!(body.contains(expected), "{expected} exported");
+        }
+    }
 }
@@ -0,0 +1,195 @@
+use std::fmt;
+
+use chrono::{SecondsFormat, Utc};
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Value};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, format::Writer},
+    registry::LookupSpan,
+};
+
+/// Formats events as one JSON object per line.
+///
+/// Besides the fields of the event, records carry the fields of the spans the event
+/// happened in, the trace id of the span when tracing is enabled, and the attributes of
+/// the service resource, like its version. Span fields must be formatted with
+/// `JsonFields` for them to be included.
+pub struct JsonFormat {
+    resource: Map<String, Value>,
+}
+
+impl JsonFormat {
+    pub fn new(resource: &Resource) -> Self {
+        let resource = resource
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+            .collect();
+        Self { resource }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut record = Map::new();
+        record.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        record.insert("level".to_string(), metadata.level().as_str().into());
+        record.insert("target".to_string(), metadata.target().into());
+
+        let mut fields = JsonVisitor::default();
+        event.record(&mut fields);
+        record.extend(fields.0);
+
+        let mut spans = Vec::new();
+        let mut trace_id = TraceId::INVALID;
+        for span in ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            let extensions = span.extensions();
+            let mut entry = Map::new();
+            entry.insert("name".to_string(), span.name().into());
+            if let Some(formatted) = extensions.get::<FormattedFields<N>>()
+                && let Ok(Value::Object(fields)) = serde_json::from_str(&formatted.fields)
+            {
+                entry.extend(fields);
+            }
+            if let Some(otel) = extensions.get::<OtelData>() {
+                let id = otel
+                    .builder
+                    .trace_id
+                    .unwrap_or_else(|| otel.parent_cx.span().span_context().trace_id());
+                if id != TraceId::INVALID {
+                    trace_id = id;
+                }
+            }
+            spans.push(Value::Object(entry));
+        }
+
+        if let Some(span) = spans.last() {
+            record.insert("span".to_string(), span.clone());
+            record.insert("spans".to_string(), Value::Array(spans));
+        }
+        if trace_id != TraceId::INVALID {
+            record.insert("trace_id".to_string(), trace_id.to_string().into());
+        }
+        record.insert("resource".to_string(), Value::Object(self.resource.clone()));
+
+        writeln!(writer, "{}", Value::Object(record))
+    }
+}
+
+/// Collects the fields of an event as JSON values
+#[derive(Default)]
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_subscriber::{Registry, fmt::format::JsonFields, prelude::*};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_records() {
+        let buffer = Buffer::default();
+        let logger = tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat::new(
+                &super::super::resource(&Default::default()),
+            ))
+            .with_writer({
+                let buffer = buffer.clone();
+                move || buffer.clone()
+            });
+        let tracer = SdkTracerProvider::builder().build().tracer("test");
+        let subscriber = Registry::default()
+            .with(logger)
+            .with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                tracing::info_span!("reconcile", worker_group = "test", namespace = "default");
+            let _guard = span.enter();
+            tracing::info!(replicas = 3, "reconciling");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let record: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["message"], "reconciling");
+        assert_eq!(record["replicas"], 3);
+        assert_eq!(record["span"]["name"], "reconcile");
+        assert_eq!(record["span"]["worker_group"], "test");
+        assert_eq!(record["span"]["namespace"], "default");
+        assert_eq!(
+            record["resource"]["service.version"],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(record["trace_id"].as_str().map(str::len), Some(32));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use axum_extra::routing::{RouterExt, TypedPath};
+use clap::Parser;
+use kube::Client;
+use operator::executor::{self, WorkerIdentity, WorkerState};
+use operator::telemetry::{self, LogFormat, TelemetryConfig};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, signal};
+use tracing::info;
+
+/// Runs the probes assigned to a worker of a `WorkerGroup`
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Name of the worker, from its `probelet.dev/workerName` label
+    #[arg(long, env = "WORKER_NAME")]
+    name: String,
+    /// Name of the `WorkerGroup` of the worker, from its `probelet.dev/workerGroupName` label
+    #[arg(long, env = "WORKER_GROUP_NAME")]
+    worker_group: String,
+    /// Namespace of the worker and its probes
+    #[arg(long, env = "POD_NAMESPACE")]
+    namespace: String,
+    /// Annotations of the worker pod, written by the downward API
+    #[arg(
+        long,
+        env = "WORKER_ANNOTATIONS_FILE",
+        default_value = "/etc/probelet/podinfo/annotations"
+    )]
+    annotations_file: PathBuf,
+    /// Address of the health and metrics server
+    #[arg(long, env = "BIND_ADDRESS", default_value = "0.0.0.0:8080")]
+    bind_address: SocketAddr,
+    /// Format of the logs
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    log_format: Option<LogFormat>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/healthz")]
+pub struct HealthRoute;
+
+async fn health(_: HealthRoute) -> Json<&'static str> {
+    Json("healthy")
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/readyz")]
+pub struct ReadyRoute;
+
+/// Ready once the probes are listed, until the worker is drained
+async fn ready(_: ReadyRoute, State(state): State<WorkerState>) -> impl IntoResponse {
+    match state.readiness() {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, TypedPath)]
+#[typed_path("/metrics")]
+pub struct MetricsRoute;
+
+async fn metrics(_: MetricsRoute, State(state): State<WorkerState>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        state.metrics.encode(),
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let mut telemetry_config = TelemetryConfig::from_env()?;
+    if let Some(log_format) = cli.log_format {
+        telemetry_config.log_format = log_format;
+    }
+    telemetry_config
+        .service_name
+        .get_or_insert_with(|| "probelet-worker".to_string());
+    telemetry::init(&telemetry_config).await?;
+
+    let identity = WorkerIdentity {
+        name: cli.name,
+        worker_group: cli.worker_group,
+        namespace: cli.namespace,
+    };
+    info!(
+        "starting worker \"{}\" of worker group \"{}\" in ns \"{}\"",
+        identity.name, identity.worker_group, identity.namespace
+    );
+
+    let state = WorkerState::default();
+    let client = Client::try_default().await?;
+    let executor = executor::run(client, identity, cli.annotations_file, state.clone());
+
+    let app = Router::new()
+        .typed_get(health)
+        .typed_get(ready)
+        .typed_get(metrics)
+        .with_state(state);
+
+    info!("Starting server on {}", cli.bind_address);
+    let listener = TcpListener::bind(cli.bind_address).await?;
+    let server =
+        axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal());
+
+    tokio::select! {
+        _ = executor => {},
+        _ = server => {},
+    }
+
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,38 +1,76 @@
 mod crd;
 mod error;
+mod rbac;
 mod reconcile;
+mod status;
 mod worker;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
-use chrono::Utc;
-pub use crd::WorkerGroup;
+pub use crd::{WorkerGroup, WorkerGroupSpec};
 use error::Result;
 use futures::StreamExt;
 use k8s_openapi::api::core::v1::Pod;
 use kube::{
     Api, Client, ResourceExt,
     api::ListParams,
-    runtime::{Controller, controller::Action, finalizer, watcher::Config},
+    runtime::{
+        Controller, WatchStreamExt,
+        controller::{self, Action},
+        finalizer,
+        reflector::{self, ObjectRef, Store, reflector},
+        watcher::{Config, watcher},
+    },
 };
 use snafu::ResultExt;
 use tracing::{Span, instrument, warn};
 
 use crate::{
-    AppState, Context, telemetry,
-    worker_group::error::{FinalizerSnafu, WorkerGroupError},
+    AppState, Context, error::error_policy, metrics::WorkerGroupLabels, probe::Probe,
+    scope::WatchScope, status::ReconciledResource, telemetry, worker_group::error::FinalizerSnafu,
 };
 
 const WORKER_GROUP_FINALIZER: &str = "probelet.io/worker-group";
 
-#[instrument(skip(worker_group, context), fields(trace_id))]
-async fn reconcile(worker_group: Arc<WorkerGroup>, context: Arc<Context>) -> Result<Action> {
+/// The label holding the name of the `WorkerGroup` of a worker pod
+pub const WORKER_GROUP_NAME_LABEL: &str = "probelet.dev/workerGroupName";
+
+/// The label holding the name of a worker pod
+pub const WORKER_NAME_LABEL: &str = "probelet.dev/workerName";
+
+/// Annotation telling a worker to stop accepting probe assignments
+pub const DRAINING_ANNOTATION: &str = "probelet.dev/draining";
+
+/// Annotation holding the part of the probes of its group a worker runs, `<index>/<count>`
+pub const SHARD_ANNOTATION: &str = "probelet.dev/shard";
+
+/// The name of the controller in diagnostics
+const WORKER_GROUP_CONTROLLER: &str = "workergroup";
+
+#[instrument(
+    skip(worker_group, probes, context),
+    fields(
+        trace_id,
+        worker_group = %worker_group.name_any(),
+        namespace = worker_group.namespace().unwrap_or_default(),
+    )
+)]
+async fn reconcile(
+    worker_group: Arc<WorkerGroup>,
+    probes: Store<Probe>,
+    context: Arc<Context>,
+) -> Result<Action> {
     let trace_id = telemetry::get_trace_id();
     if trace_id != opentelemetry::trace::TraceId::INVALID {
         Span::current().record("trace_id", tracing::field::display(trace_id));
     }
-    let _timer = context.metrics.reconcile.count_and_measure(&trace_id);
-    context.diagnostics.write().await.last_event = Utc::now();
+    let _timer = context
+        .metrics
+        .reconcile
+        .count_and_measure(WORKER_GROUP_CONTROLLER, &trace_id);
     // we can unwrap because the worker_group is namespace scoped
     let ns = worker_group.namespace().unwrap();
     let worker_groups = Api::<WorkerGroup>::namespaced(context.client.clone(), &ns);
@@ -42,53 +80,301 @@ async fn reconcile(worker_group: Arc<WorkerGroup>, context: Arc<Context>) -> Res
         worker_group.name_any(),
         ns
     );
-    finalizer(
+    // the pod spec of the workers depends on the probes of the group, which must be known
+    // first; the store only fails to sync when the controller is shutting down
+    let _ = probes.wait_until_ready().await;
+    let result = finalizer(
         &worker_groups,
         WORKER_GROUP_FINALIZER,
-        worker_group,
+        worker_group.clone(),
         |event| async {
             match event {
-                finalizer::Event::Apply(wg) => wg.reconcile(context.clone()).await,
+                finalizer::Event::Apply(wg) => wg.reconcile(&probes.state(), context.clone()).await,
                 finalizer::Event::Cleanup(wg) => wg.cleanup(context.clone()).await,
             }
         },
     )
     .await
-    .context(FinalizerSnafu)
+    .context(FinalizerSnafu);
+
+    context
+        .diagnostics
+        .write()
+        .await
+        .record_reconcile(WORKER_GROUP_CONTROLLER, result.as_ref().map(|_| ()));
+
+    match &result {
+        Ok(_) => context
+            .backoff
+            .reset(&ObjectRef::from_obj(&*worker_group).to_string()),
+        Err(error) => {
+            if let Err(e) = worker_group
+                .patch_reconcile_error(error, context.clone())
+                .await
+            {
+                warn!(
+                    "failed to report reconcile error of worker group \"{}\": {e}",
+                    worker_group.name_any()
+                );
+            }
+        }
+    }
+
+    result
 }
 
-fn error_policy(
-    worker_group: Arc<WorkerGroup>,
-    error: &WorkerGroupError,
-    context: Arc<Context>,
-) -> Action {
-    warn!(
-        "reconcile failed for worker group \"{}\" in ns \"{}\": {error:?}",
-        worker_group.name_any(),
-        worker_group.namespace().unwrap()
-    );
-    context.metrics.reconcile.set_failure(&*worker_group, error);
-    Action::requeue(Duration::from_secs(5 * 60))
+/// The worker group each probe was last seen assigned to
+#[derive(Default)]
+struct ProbeAssignments(Mutex<HashMap<ObjectRef<Probe>, String>>);
+
+impl ProbeAssignments {
+    /// The worker groups to reconcile again after a change of `probe`: the group it runs
+    /// on, and the one it ran on before it was moved
+    fn worker_groups_of(&self, probe: &Probe) -> Vec<ObjectRef<WorkerGroup>> {
+        let Some(namespace) = probe.namespace() else {
+            return Vec::new();
+        };
+        let key = ObjectRef::from_obj(probe);
+        let mut assignments = self.0.lock().unwrap();
+        let previous = if probe.metadata.deletion_timestamp.is_some() {
+            assignments.remove(&key)
+        } else {
+            assignments.insert(key, probe.spec.worker_group.clone())
+        };
+        let mut worker_groups = vec![probe.spec.worker_group.clone()];
+        worker_groups.extend(previous.filter(|previous| *previous != probe.spec.worker_group));
+        worker_groups
+            .into_iter()
+            .map(|name| ObjectRef::new(&name).within(&namespace))
+            .collect()
+    }
+}
+
+/// Runs the `WorkerGroup` controllers of the namespaces in `scope`
+pub async fn run(client: Client, scope: WatchScope, state: AppState) {
+    let health = state.health();
+    scope
+        .run_in_namespaces(client.clone(), &health, |namespace| {
+            run_controller(client.clone(), namespace, &scope, state.clone())
+        })
+        .await;
 }
 
-/// Runs the `WorkerGroup` controller
-pub async fn run(client: Client, watcher_config: Config, state: AppState) {
-    let worker_groups = Api::<WorkerGroup>::all(client.clone());
-    let pods = Api::<Pod>::all(client.clone());
+/// Runs the `WorkerGroup` controller of `namespace`, or of all namespaces
+async fn run_controller(
+    client: Client,
+    namespace: Option<String>,
+    scope: &WatchScope,
+    state: AppState,
+) {
+    let (worker_groups, pods, probes, name) = match &namespace {
+        Some(ns) => (
+            Api::<WorkerGroup>::namespaced(client.clone(), ns),
+            Api::<Pod>::namespaced(client.clone(), ns),
+            Api::<Probe>::namespaced(client.clone(), ns),
+            format!("{WORKER_GROUP_CONTROLLER}/{ns}"),
+        ),
+        None => (
+            Api::<WorkerGroup>::all(client.clone()),
+            Api::<Pod>::all(client.clone()),
+            Api::<Probe>::all(client.clone()),
+            WORKER_GROUP_CONTROLLER.to_string(),
+        ),
+    };
+
+    let health = state.health();
+    let _registration = health.register_scoped(&name);
 
     if let Err(e) = worker_groups.list(&ListParams::default().limit(1)).await {
         tracing::error!("CRD is not queryable; {e:?}. Is the CRD installed?");
         std::process::exit(1);
     }
-    Controller::new(worker_groups, watcher_config.clone())
-        .owns(pods, watcher_config)
+    health.set_crd_ready(&name);
+
+    let (probe_reader, probe_writer) = reflector::store();
+    let probe_events = reflector(probe_writer, watcher(probes, Config::default()))
+        .default_backoff()
+        .touched_objects();
+    let assignments = ProbeAssignments::default();
+
+    let concurrency = state.controller_config().concurrency;
+    let controller = Controller::new(worker_groups, scope.worker_group_config())
+        .with_config(controller::Config::default().concurrency(concurrency));
+    let reader = controller.store();
+    tokio::spawn({
+        let (health, reader, name) = (health.clone(), reader.clone(), name.clone());
+        async move {
+            if reader.wait_until_ready().await.is_ok() {
+                health.set_cache_synced(&name);
+            }
+        }
+    });
+
+    // only the pods created by the operator
+    let pods_config = Config::default().labels(WORKER_GROUP_NAME_LABEL);
+    let context = state.controller_context(client).await;
+    let metrics = context.metrics.clone();
+    // the gauges of the groups no longer watched, e.g. relabelled out of the worker group
+    // selector, are removed as the other groups are reconciled
+    let in_scope = |labels: &WorkerGroupLabels, watched: &HashSet<WorkerGroupLabels>| {
+        namespace.as_ref().is_some_and(|ns| *ns != labels.namespace) || watched.contains(labels)
+    };
+    controller
+        .owns(pods, pods_config)
+        // the workers of a group are allowed ICMP sockets once it runs ICMP probes
+        .watches_stream(probe_events, move |probe| {
+            assignments.worker_groups_of(&probe)
+        })
         .shutdown_on_signal()
         .run(
-            reconcile,
-            error_policy,
-            state.controller_context(client).await,
+            move |worker_group, context| reconcile(worker_group, probe_reader.clone(), context),
+            |worker_group, error, context| {
+                error_policy(WORKER_GROUP_CONTROLLER, worker_group, error, context)
+            },
+            context,
         )
-        .filter_map(|x| async move { std::result::Result::ok(x) })
-        .for_each(|_| futures::future::ready(()))
+        .for_each(|result| {
+            // watcher failures surface as queue errors
+            let watch_ok = !matches!(result, Err(controller::Error::QueueError(_)));
+            health.record_watch(&name, watch_ok, reader.len());
+            let watched = reader
+                .state()
+                .iter()
+                .map(|worker_group| worker_group.metric_labels())
+                .collect();
+            metrics
+                .worker_group
+                .retain(|labels| in_scope(labels, &watched));
+            futures::future::ready(())
+        })
         .await;
+
+    // the namespace left the scope, or the controller is shutting down
+    metrics
+        .worker_group
+        .retain(|labels| in_scope(labels, &HashSet::new()));
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+    use kube::Resource;
+
+    use super::*;
+    use crate::{
+        probe::{HttpProbe, IcmpProbe, ProbeKind, ProbeSpec},
+        worker_group::WorkerGroupSpec,
+    };
+
+    fn probe(name: &str, worker_group: &str, kind: ProbeKind) -> Probe {
+        let mut probe = Probe::new(
+            name,
+            ProbeSpec {
+                worker_group: worker_group.to_string(),
+                interval_seconds: 60,
+                timeout_seconds: 10,
+                kind,
+            },
+        );
+        probe.meta_mut().namespace = Some("default".to_string());
+        probe
+    }
+
+    fn http_probe(name: &str, worker_group: &str) -> Probe {
+        let kind = ProbeKind::Http(HttpProbe {
+            url: "https://example.com".to_string(),
+            ..Default::default()
+        });
+        probe(name, worker_group, kind)
+    }
+
+    #[test]
+    fn test_moved_probes_requeue_both_worker_groups() {
+        let assignments = ProbeAssignments::default();
+        let names = |probe: &Probe| {
+            assignments
+                .worker_groups_of(probe)
+                .into_iter()
+                .map(|object| (object.name, object.namespace.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let group = |name: &str| (name.to_string(), "default".to_string());
+
+        assert_eq!(names(&http_probe("a", "first")), [group("first")]);
+        assert_eq!(names(&http_probe("a", "first")), [group("first")]);
+        assert_eq!(
+            names(&http_probe("a", "second")),
+            [group("second"), group("first")]
+        );
+
+        let mut deleted = http_probe("a", "second");
+        deleted.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        assert_eq!(names(&deleted), [group("second")]);
+        assert_eq!(names(&http_probe("a", "third")), [group("third")]);
+    }
+
+    #[test]
+    fn test_drain_deadline() {
+        let mut worker_group = WorkerGroup::new(
+            "workers",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: None,
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        );
+        let deleted_at = chrono::Utc::now();
+        worker_group.metadata.deletion_timestamp = Some(Time(deleted_at));
+        assert_eq!(
+            worker_group.drain_deadline(),
+            deleted_at + chrono::TimeDelta::seconds(60)
+        );
+
+        // out of range timeouts do not wait
+        for timeout in [i64::MAX, i64::MAX / 1000] {
+            worker_group.spec.drain_timeout_seconds = timeout;
+            assert_eq!(worker_group.drain_deadline(), deleted_at, "{timeout}");
+        }
+    }
+
+    #[test]
+    fn test_runs_icmp_probes() {
+        let mut worker_group = WorkerGroup::new(
+            "workers",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: None,
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        );
+        worker_group.meta_mut().namespace = Some("default".to_string());
+        let icmp = |name: &str, worker_group: &str| {
+            let kind = ProbeKind::Icmp(IcmpProbe {
+                host: "10.0.0.1".to_string(),
+                count: 5,
+                interval_ms: 200,
+                reply_timeout_ms: 1000,
+                assertions: Default::default(),
+            });
+            probe(name, worker_group, kind)
+        };
+
+        let mut probes = vec![
+            Arc::new(http_probe("a", "workers")),
+            Arc::new(icmp("b", "others")),
+        ];
+        assert!(!worker_group.runs_icmp_probes(&probes));
+
+        let mut other_namespace = icmp("c", "workers");
+        other_namespace.meta_mut().namespace = Some("other".to_string());
+        probes.push(Arc::new(other_namespace));
+        assert!(!worker_group.runs_icmp_probes(&probes));
+
+        probes.push(Arc::new(icmp("d", "workers")));
+        assert!(worker_group.runs_icmp_probes(&probes));
+    }
 }
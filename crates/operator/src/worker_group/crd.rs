@@ -1,15 +1,37 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
-    time::Duration,
-};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
-use kube::{CustomResource, ResourceExt, runtime::controller::Action};
+use chrono::{DateTime, TimeDelta, Utc};
+use k8s_openapi::{
+    api::core::v1::{
+        Affinity, EnvVar, LocalObjectReference, Pod, PodSecurityContext, ResourceRequirements,
+        SecurityContext, Toleration,
+    },
+    apimachinery::pkg::apis::meta::v1::Condition,
+};
+use kube::{
+    Api, CustomResource, Resource, ResourceExt,
+    api::ListParams,
+    runtime::{
+        controller::Action,
+        events::{Event, EventType},
+    },
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
 
-use super::Result;
-use crate::{Context, metrics::MetricLabel, worker_group::reconcile::ReconcileWorkerGroupTask};
+use super::{Result, SHARD_ANNOTATION, WORKER_GROUP_NAME_LABEL};
+use crate::{
+    Context,
+    error::KubeSnafu,
+    metrics::{MetricLabel, WorkerGroupLabels, WorkerGroupObservation},
+    probe::{Probe, ProbeKind},
+    status::{ReconcileStatus, ReconciledResource},
+    worker_group::{
+        reconcile::{EventReason, ReconcileWorkerGroupTask},
+        worker::{Worker, is_crash_looping, is_draining, is_ready, is_terminating, shards},
+    },
+};
 
 /// The `WorkerGroup` is a resource that manages a group of `Worker` instances (Pods).
 /// `Workers` are where the probes are going to be executed.
@@ -21,15 +43,135 @@ use crate::{Context, metrics::MetricLabel, worker_group::reconcile::ReconcileWor
     namespaced
 )]
 #[kube(status = "WorkerGroupStatus", shortname = "workergroup")]
+#[kube(
+    printcolumn = r#"{"name":"Desired", "type":"integer", "jsonPath":".spec.replicas"}"#,
+    printcolumn = r#"{"name":"Instances", "type":"integer", "jsonPath":".status.instances"}"#,
+    printcolumn = r#"{"name":"Ready", "type":"integer", "jsonPath":".status.readyInstances"}"#,
+    printcolumn = r#"{"name":"Available", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Available\")].status"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
 pub struct WorkerGroupSpec {
     /// The number of replicas to create
-    /// The max is `1` for the first version of the CRD
+    /// Workers are named `<name>-<ordinal>`, with ordinals starting at `0`
+    /// The probes of the group are split across its ready workers, each probe running on
+    /// a single one of them
     pub replicas: i32,
-    /// The image to use for the `WorkerGroup`
-    pub image: String,
+    /// Overrides the image of the workers, which must provide the `/app/worker` binary.
+    /// Defaults to the worker image configured in the operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// How workers are replaced when their pod spec changes
+    #[serde(default)]
+    pub rolling_update: RollingUpdate,
+    /// Overrides merged into the worker pods created by the operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<WorkerPodTemplate>,
+    /// How long workers are given to finish their probes when the `WorkerGroup` is deleted,
+    /// at most a day
+    #[serde(default = "default_drain_timeout_seconds")]
+    #[schemars(range(min = 0, max = 86400))]
+    pub drain_timeout_seconds: i64,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq, Hash)]
+fn default_drain_timeout_seconds() -> i64 {
+    60
+}
+
+/// A restricted pod template for the workers.
+///
+/// It is merged with the operator defaults: the `worker` container, the `probelet.dev/*`
+/// labels and annotations and the owner references always come from the operator.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerPodTemplate {
+    /// Extra labels added to the worker pods
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Extra annotations added to the worker pods
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Compute resources of the `worker` container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceRequirements>,
+    /// Extra environment variables of the `worker` container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<EnvVar>,
+    /// Security context of the `worker` container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_context: Option<SecurityContext>,
+    /// Security context of the worker pods. The operator sets the `net.ipv4.ping_group_range`
+    /// sysctl when the group runs `Icmp` probes, unless it is already set here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pod_security_context: Option<PodSecurityContext>,
+    /// Node labels the worker pods must be scheduled on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_selector: BTreeMap<String, String>,
+    /// Tolerations of the worker pods
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tolerations: Vec<Toleration>,
+    /// Scheduling constraints of the worker pods
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<Affinity>,
+    /// The service account the worker pods run as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        length(min = 1, max = 253),
+        regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$")
+    )]
+    pub service_account_name: Option<String>,
+    /// Secrets used to pull the worker image from a private registry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_pull_secrets: Vec<LocalObjectReference>,
+}
+
+/// Controls how outdated workers are replaced, one step per reconcile
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RollingUpdate {
+    /// The number of workers that can be unavailable during the update
+    #[serde(default = "default_max_unavailable")]
+    #[schemars(range(min = 0))]
+    pub max_unavailable: i32,
+    /// The number of workers that can be created above `replicas` during the update
+    #[serde(default)]
+    #[schemars(range(min = 0))]
+    pub max_surge: i32,
+}
+
+fn default_max_unavailable() -> i32 {
+    1
+}
+
+impl Default for RollingUpdate {
+    fn default() -> Self {
+        Self {
+            max_unavailable: default_max_unavailable(),
+            max_surge: 0,
+        }
+    }
+}
+
+impl RollingUpdate {
+    /// The number of workers that can be unavailable, at least `1` when no surge is
+    /// allowed so the update can make progress
+    pub fn max_unavailable(&self) -> usize {
+        let max_unavailable = usize::try_from(self.max_unavailable).unwrap_or_default();
+        if self.max_surge() == 0 {
+            max_unavailable.max(1)
+        } else {
+            max_unavailable
+        }
+    }
+
+    pub fn max_surge(&self) -> usize {
+        usize::try_from(self.max_surge).unwrap_or_default()
+    }
+}
+
+#[derive(
+    Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 pub struct WorkerInstanceName(pub String);
 
 impl TryFrom<String> for WorkerInstanceName {
@@ -73,24 +215,32 @@ pub enum WorkerGroupInstanceStatus {
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
 pub struct WorkerGroupReportedInstanceState {
     /// The state of the instance, can be `Ready` or `NotReady`
     pub status: WorkerGroupInstanceStatus,
-    /// The last updated time of the instance
+    /// The last updated time of the instance, as an RFC3339 timestamp
     pub last_updated: String,
 }
 
 /// The status object of `WorkerGroup`
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct WorkerGroupStatus {
     /// Runnning instances names
     pub instance_names: Vec<WorkerInstanceName>,
     /// Number of instances
     pub instances: i32,
     /// State of instances
-    pub instances_reported_state: HashMap<WorkerInstanceName, WorkerGroupReportedInstanceState>,
+    pub instances_reported_state: BTreeMap<WorkerInstanceName, WorkerGroupReportedInstanceState>,
     /// Ready instances
     pub ready_instances: i32,
+    /// The `metadata.generation` of the `WorkerGroup` last reconciled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// The `Available`, `Progressing`, `Degraded` and `ReconcileError` conditions
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
 }
 
 impl MetricLabel for WorkerGroup {
@@ -100,15 +250,176 @@ impl MetricLabel for WorkerGroup {
 }
 
 impl WorkerGroup {
-    pub(crate) async fn reconcile(&self, context: Arc<Context>) -> Result<Action> {
-        match ReconcileWorkerGroupTask::from_worker_group(self.clone(), context)? {
-            Some(task) => task.run().await,
-            None => Ok(Action::requeue(Duration::from_secs(5 * 60))),
+    /// Reconciles the workers of the group, `probes` being the probes of the watched
+    /// namespaces
+    pub(crate) async fn reconcile(
+        &self,
+        probes: &[Arc<Probe>],
+        context: Arc<Context>,
+    ) -> Result<Action> {
+        let all_pods = Worker::list(self, context.clone()).await?;
+        let pods = all_pods
+            .iter()
+            .filter(|pod| !is_terminating(pod))
+            .cloned()
+            .collect::<Vec<_>>();
+        let icmp = self.runs_icmp_probes(probes);
+        let status = self.observed_status(&pods, &context.config, icmp);
+        context.metrics.worker_group.set(
+            &self.metric_labels(),
+            WorkerGroupObservation {
+                desired_replicas: self.spec.replicas.into(),
+                instances: status.instances.into(),
+                ready_instances: status.ready_instances.into(),
+                crashlooping_pods: pods.iter().filter(|pod| is_crash_looping(pod)).count() as i64,
+            },
+        );
+        self.patch_status(status.clone(), context.clone()).await?;
+        self.apply_worker_rbac(context.clone()).await?;
+        self.assign_shards(&pods, context.clone()).await?;
+
+        let action = match ReconcileWorkerGroupTask::from_worker_group(
+            self.clone(),
+            all_pods,
+            icmp,
+            context.clone(),
+        )? {
+            Some(task) => task.run().await?,
+            None => Action::requeue(context.config.requeue_interval()),
+        };
+
+        // the reconcile went through, clear a previously reported error
+        let mut succeeded = status.clone();
+        succeeded.set_reconcile_error(None, self.metadata.generation);
+        if succeeded != status {
+            self.patch_status(succeeded, context).await?;
+        }
+
+        Ok(action)
+    }
+
+    /// Splits the probes of the group across its ready workers by updating the shard
+    /// annotation of the pods whose shard changed
+    async fn assign_shards(&self, pods: &[Pod], context: Arc<Context>) -> Result<()> {
+        let worker_group = Arc::new(self.clone());
+        for (pod, shard) in shards(pods) {
+            if pod.annotations().get(SHARD_ANNOTATION) != shard.as_ref() {
+                Worker::from_pod(pod, worker_group.clone())
+                    .set_shard(shard.as_deref(), context.clone())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drains and deletes the workers before the finalizer is removed.
+    ///
+    /// Workers are first marked as draining so they stop accepting probe assignments,
+    /// and they are deleted once they all stopped being ready or the drain timeout expired.
+    /// The probes of the group are then released, reporting they are no longer scheduled.
+    pub(crate) async fn cleanup(&self, context: Arc<Context>) -> Result<Action> {
+        let mut pods = Worker::list(self, context.clone()).await?;
+        pods.retain(|pod| !is_terminating(pod));
+        let worker_group = Arc::new(self.clone());
+        let workers = pods
+            .iter()
+            .map(|pod| Worker::from_pod(pod, worker_group.clone()))
+            .collect::<Vec<_>>();
+
+        for (pod, worker) in pods.iter().zip(&workers) {
+            if !is_draining(pod) {
+                worker.drain(context.clone()).await?;
+            }
+        }
+
+        let remaining = self.drain_deadline() - Utc::now();
+        if remaining > TimeDelta::zero() && pods.iter().any(is_ready) {
+            let remaining = remaining.to_std().unwrap_or_default();
+            return Ok(Action::requeue(remaining.min(Duration::from_secs(5))));
+        }
+
+        for worker in &workers {
+            worker.delete(context.clone()).await?;
+        }
+        let released = self.release_probes(context.clone()).await?;
+
+        let event = Event {
+            type_: EventType::Normal,
+            reason: EventReason::WorkerGroupDeleted.to_string(),
+            note: Some(format!(
+                "Worker group deleted, {} workers removed and {released} probes released",
+                workers.len()
+            )),
+            secondary: None,
+            action: EventReason::WorkerGroupDeleted.to_string(),
+        };
+        context
+            .recorder
+            .publish(&event, &self.object_ref(&()))
+            .await
+            .context(KubeSnafu {
+                message: format!(
+                    "Failed to publish event for worker group {}",
+                    self.name_any()
+                ),
+            })?;
+
+        context.metrics.worker_group.remove(&self.metric_labels());
+        Ok(Action::await_change())
+    }
+
+    /// Sets the `Scheduled` condition of the probes of the group to false, returning how
+    /// many there are
+    async fn release_probes(&self, context: Arc<Context>) -> Result<usize> {
+        let name = self.name_any();
+        let api = Api::<Probe>::namespaced(context.client.clone(), &self.namespace().unwrap());
+        let probes = api.list(&ListParams::default()).await.context(KubeSnafu {
+            message: format!("Failed to list probes of worker group {name}"),
+        })?;
+        let probes = probes
+            .items
+            .into_iter()
+            .filter(|probe| probe.spec.worker_group == name)
+            .collect::<Vec<_>>();
+        for probe in &probes {
+            let mut status = probe.status.clone().unwrap_or_default();
+            status.release(&name, probe.metadata.generation);
+            probe.patch_status(status, context.clone()).await?;
+        }
+        Ok(probes.len())
+    }
+
+    /// Whether the worker group runs ICMP probes, whose workers need the
+    /// `net.ipv4.ping_group_range` sysctl
+    pub(super) fn runs_icmp_probes(&self, probes: &[Arc<Probe>]) -> bool {
+        probes.iter().any(|probe| {
+            probe.namespace() == self.namespace()
+                && probe.spec.worker_group == self.name_any()
+                && probe.metadata.deletion_timestamp.is_none()
+                && matches!(probe.spec.kind, ProbeKind::Icmp(_))
+        })
+    }
+
+    /// The labels of the gauges of the worker group
+    pub(super) fn metric_labels(&self) -> WorkerGroupLabels {
+        WorkerGroupLabels {
+            namespace: self.namespace().unwrap_or_default(),
+            worker_group: self.name_any(),
         }
     }
 
-    pub(crate) async fn cleanup(&self, _context: Arc<Context>) -> Result<Action> {
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+    /// The time after which workers are deleted even if they did not finish draining,
+    /// already passed when the drain timeout is out of range
+    pub fn drain_deadline(&self) -> DateTime<Utc> {
+        let deleted_at = self
+            .metadata
+            .deletion_timestamp
+            .as_ref()
+            .map(|time| time.0)
+            .unwrap_or_else(Utc::now);
+        TimeDelta::try_seconds(self.spec.drain_timeout_seconds.max(0))
+            .and_then(|timeout| deleted_at.checked_add_signed(timeout))
+            .unwrap_or(deleted_at)
     }
 
     pub fn default_annotations(&self) -> BTreeMap<String, String> {
@@ -122,7 +433,7 @@ impl WorkerGroup {
 
     pub fn default_labels(&self) -> BTreeMap<String, String> {
         let mut labels = BTreeMap::new();
-        labels.insert("probelet.dev/workerGroupName".to_string(), self.name_any());
+        labels.insert(WORKER_GROUP_NAME_LABEL.to_string(), self.name_any());
         labels
     }
 }
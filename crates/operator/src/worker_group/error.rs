@@ -1,27 +1,89 @@
+use kube::runtime::finalizer;
 use snafu::Snafu;
 
-use crate::metrics::MetricLabel;
+use crate::{
+    backoff::Retry,
+    error::{KubeError, ReconcileError, finalizer_metric_label, finalizer_retry},
+    metrics::MetricLabel,
+    probe::ProbeError,
+};
 
 #[derive(Snafu, Debug)]
 #[snafu(visibility(pub(crate)))]
 pub(crate) enum WorkerGroupError {
-    #[snafu(display("Kubernetes error: {message}: {source}"))]
-    Kube {
-        message: String,
-        #[snafu(source(from(kube::Error, Box::new)))]
-        source: Box<kube::Error>,
+    #[snafu(transparent)]
+    Kube { source: KubeError },
+    /// Failed to update a `Probe` of the group
+    #[snafu(context(false), display("Probe error: {source}"))]
+    Probe {
+        #[snafu(source(from(ProbeError, Box::new)))]
+        source: Box<ProbeError>,
     },
+    #[snafu(display("Invalid worker name: {message}"))]
+    InvalidWorkerName { message: String },
     #[snafu(display("Finalizer error: {source}"))]
     Finalizer {
-        #[snafu(source(from(kube::runtime::finalizer::Error<WorkerGroupError>, Box::new)))]
-        source: Box<kube::runtime::finalizer::Error<WorkerGroupError>>,
+        #[snafu(source(from(finalizer::Error<WorkerGroupError>, Box::new)))]
+        source: Box<finalizer::Error<WorkerGroupError>>,
     },
 }
 
+impl ReconcileError for WorkerGroupError {
+    fn retry(&self) -> Retry {
+        match self {
+            WorkerGroupError::Kube { source } => source.retry(),
+            WorkerGroupError::Probe { source } => source.retry(),
+            // the spec has to change for the reconcile to succeed
+            WorkerGroupError::InvalidWorkerName { .. } => Retry::Never,
+            WorkerGroupError::Finalizer { source } => finalizer_retry(source),
+        }
+    }
+}
+
 impl MetricLabel for WorkerGroupError {
     fn metric_label(&self) -> String {
-        "worker_group_error".to_string()
+        match self {
+            WorkerGroupError::Kube { source } => source.metric_label(),
+            WorkerGroupError::Probe { source } => source.metric_label(),
+            WorkerGroupError::InvalidWorkerName { .. } => "invalid_worker_name".to_string(),
+            WorkerGroupError::Finalizer { source } => finalizer_metric_label(source),
+        }
     }
 }
 
 pub type Result<T> = std::result::Result<T, WorkerGroupError>;
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ErrorResponse;
+    use snafu::IntoError;
+
+    use crate::error::KubeSnafu;
+
+    use super::*;
+
+    fn conflict() -> WorkerGroupError {
+        KubeSnafu { message: "test" }
+            .into_error(kube::Error::Api(ErrorResponse {
+                status: "Failure".to_string(),
+                message: String::new(),
+                reason: String::new(),
+                code: 409,
+            }))
+            .into()
+    }
+
+    #[test]
+    fn test_finalizer_errors_use_the_inner_error() {
+        let error = WorkerGroupError::Finalizer {
+            source: Box::new(finalizer::Error::ApplyFailed(conflict())),
+        };
+        assert_eq!(error.metric_label(), "conflict");
+        assert_eq!(error.retry(), Retry::backoff(1, 30));
+
+        let error = WorkerGroupError::InvalidWorkerName {
+            message: "too long".to_string(),
+        };
+        assert_eq!(error.retry(), Retry::Never);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use k8s_openapi::api::{
+    core::v1::ServiceAccount,
+    rbac::v1::{RoleBinding, RoleRef, Subject},
+};
+use kube::{
+    Api, Resource, ResourceExt,
+    api::{ObjectMeta, Patch, PatchParams},
+};
+use snafu::ResultExt;
+
+use super::Result;
+use crate::{Context, error::KubeSnafu, status::FIELD_MANAGER, worker_group::WorkerGroup};
+
+impl WorkerGroup {
+    /// The service account created for the workers when the pod template does not set one
+    pub fn worker_service_account_name(&self) -> String {
+        format!("{}-worker", self.name_any())
+    }
+
+    /// The service account of the workers and its binding to `cluster_role`, which lets
+    /// the workers read their probes. `None` when the pod template sets a service account,
+    /// its permissions are then managed by the user.
+    pub fn worker_rbac(&self, cluster_role: &str) -> Option<(ServiceAccount, RoleBinding)> {
+        if self
+            .spec
+            .template
+            .as_ref()
+            .is_some_and(|template| template.service_account_name.is_some())
+        {
+            return None;
+        }
+
+        let metadata = ObjectMeta {
+            name: Some(self.worker_service_account_name()),
+            namespace: self.namespace(),
+            labels: Some(self.default_labels()),
+            owner_references: Some(vec![self.owner_ref(&()).unwrap()]),
+            ..Default::default()
+        };
+        let service_account = ServiceAccount {
+            metadata: metadata.clone(),
+            ..Default::default()
+        };
+        let role_binding = RoleBinding {
+            metadata,
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_string(),
+                kind: "ClusterRole".to_string(),
+                name: cluster_role.to_string(),
+            },
+            subjects: Some(vec![Subject {
+                kind: "ServiceAccount".to_string(),
+                name: self.worker_service_account_name(),
+                namespace: self.namespace(),
+                ..Default::default()
+            }]),
+        };
+        Some((service_account, role_binding))
+    }
+
+    /// Server-side applies the service account of the workers and its role binding
+    pub(crate) async fn apply_worker_rbac(&self, context: Arc<Context>) -> Result<()> {
+        let Some((service_account, role_binding)) =
+            self.worker_rbac(&context.config.worker_cluster_role)
+        else {
+            return Ok(());
+        };
+
+        let ns = self.namespace().unwrap();
+        let name = self.worker_service_account_name();
+        let params = PatchParams::apply(FIELD_MANAGER).force();
+        Api::<ServiceAccount>::namespaced(context.client.clone(), &ns)
+            .patch(&name, &params, &Patch::Apply(&service_account))
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to apply service account {name}"),
+            })?;
+        Api::<RoleBinding>::namespaced(context.client.clone(), &ns)
+            .patch(&name, &params, &Patch::Apply(&role_binding))
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to apply role binding {name}"),
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_json_snapshot;
+    use kube::Resource;
+
+    use super::*;
+    use crate::worker_group::crd::{WorkerGroupSpec, WorkerPodTemplate};
+
+    fn worker_group(template: Option<WorkerPodTemplate>) -> WorkerGroup {
+        let mut worker_group = WorkerGroup::new(
+            "test",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: None,
+                rolling_update: Default::default(),
+                template,
+                drain_timeout_seconds: 60,
+            },
+        );
+        worker_group.meta_mut().namespace = Some("default".to_string());
+        worker_group.meta_mut().uid = Some("test".to_string());
+        worker_group
+    }
+
+    #[test]
+    fn test_worker_rbac() {
+        let rbac = worker_group(None).worker_rbac("probelet-worker");
+        assert_json_snapshot!(rbac);
+
+        let template = WorkerPodTemplate {
+            service_account_name: Some("custom".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            worker_group(Some(template))
+                .worker_rbac("probelet-worker")
+                .is_none()
+        );
+    }
+}
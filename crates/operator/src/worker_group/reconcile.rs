@@ -1,17 +1,29 @@
-use std::{fmt::Display, sync::Arc, time::Duration};
+use std::{fmt::Display, sync::Arc};
 
-use kube::{ResourceExt, runtime::controller::Action};
+use k8s_openapi::api::core::v1::Pod;
+use kube::runtime::controller::Action;
 
 use super::Result;
 use crate::{
     Context,
-    worker_group::{crd::WorkerGroup, worker::Worker},
+    worker_group::{
+        crd::{RollingUpdate, WorkerGroup},
+        worker::{Worker, is_outdated, is_ready, is_terminating, missing_ordinals, select_victims},
+    },
 };
 
+// the variants are the event reasons shown to users, they keep their prefix
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone)]
 pub enum EventReason {
     /// The worker was created
     WorkerCreated,
+    /// The worker was deleted
+    WorkerDeleted,
+    /// The worker was asked to stop accepting probe assignments
+    WorkerDraining,
+    /// The workers of the group were drained and deleted
+    WorkerGroupDeleted,
 }
 
 impl Display for EventReason {
@@ -24,61 +36,266 @@ impl Display for EventReason {
 #[derive(Debug, Clone)]
 pub enum Tasks {
     /// If the number of instances is less than the desired number of instances,
-    /// we need to create a new worker
-    CreateWorker,
+    /// we need to create new workers
+    CreateWorkers(Vec<Worker>),
+    /// If there are more workers than the desired number of instances,
+    /// we need to delete the surplus workers
+    DeleteWorkers(Vec<Worker>),
+    /// If workers run an outdated pod spec, we need to create surge workers
+    /// and delete outdated ones within the rolling update budget
+    ReplaceOutdated {
+        create: Vec<Worker>,
+        delete: Vec<Worker>,
+    },
 }
 
 #[derive(Clone)]
 pub struct ReconcileWorkerGroupTask {
-    worker_group: WorkerGroup,
     task: Tasks,
+    /// Whether the workers run ICMP probes
+    icmp: bool,
     context: Arc<Context>,
 }
 
 impl ReconcileWorkerGroupTask {
     /// Determines wether a task should be run based on the state of the `WorkerGroup`
+    /// and the worker pods observed for it, terminating ones included
     pub fn from_worker_group(
         worker_group: WorkerGroup,
+        all_pods: Vec<Pod>,
+        icmp: bool,
         context: Arc<Context>,
     ) -> Result<Option<Self>> {
-        if worker_group.spec.replicas
-            > worker_group
-                .status
-                .as_ref()
-                .map(|status| status.instances)
-                .unwrap_or(-1)
-        {
-            return Ok(Some(Self::new(worker_group, context, Tasks::CreateWorker)));
+        let desired = usize::try_from(worker_group.spec.replicas).unwrap_or_default();
+        let worker_group_ref = Arc::new(worker_group.clone());
+        let pods = all_pods
+            .iter()
+            .filter(|pod| !is_terminating(pod))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if pods.len() < desired {
+            let workers = missing_ordinals(&all_pods, desired - pods.len())
+                .into_iter()
+                .map(|ordinal| Worker::new(ordinal, worker_group_ref.clone()))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Some(Self::new(
+                context,
+                Tasks::CreateWorkers(workers),
+                icmp,
+            )));
+        }
+
+        let pod_spec = Worker::pod_spec_annotation(&worker_group, &context.config, icmp);
+        if pods.iter().any(|pod| is_outdated(pod, &pod_spec)) {
+            let (surge, victims) =
+                rolling_update_step(&pods, desired, &worker_group.spec.rolling_update, &pod_spec);
+            if surge == 0 && victims.is_empty() {
+                // waiting for replaced workers to become ready
+                return Ok(None);
+            }
+
+            let create = missing_ordinals(&all_pods, surge)
+                .into_iter()
+                .map(|ordinal| Worker::new(ordinal, worker_group_ref.clone()))
+                .collect::<Result<Vec<_>>>()?;
+            let delete = victims
+                .iter()
+                .map(|pod| Worker::from_pod(pod, worker_group_ref.clone()))
+                .collect();
+            return Ok(Some(Self::new(
+                context,
+                Tasks::ReplaceOutdated { create, delete },
+                icmp,
+            )));
+        }
+
+        if pods.len() > desired {
+            let surplus = pods.len() - desired;
+            let victims = select_victims(pods, surplus)
+                .iter()
+                .map(|pod| Worker::from_pod(pod, worker_group_ref.clone()))
+                .collect();
+            return Ok(Some(Self::new(
+                context,
+                Tasks::DeleteWorkers(victims),
+                icmp,
+            )));
         }
 
         Ok(None)
     }
 
-    pub fn new(worker_group: WorkerGroup, context: Arc<Context>, task: Tasks) -> Self {
+    pub fn new(context: Arc<Context>, task: Tasks, icmp: bool) -> Self {
         Self {
-            worker_group,
             context,
             task,
+            icmp,
         }
     }
 }
 
 impl ReconcileWorkerGroupTask {
-    async fn create_worker(&self) -> Result<Action> {
-        let worker = Worker::new(
-            self.worker_group.name_any(),
-            self.worker_group.spec.image.clone(),
-            Arc::new(self.worker_group.clone()),
-        );
+    async fn create_workers(&self, workers: &[Worker]) -> Result<Action> {
+        for worker in workers {
+            worker.create(self.icmp, self.context.clone()).await?;
+        }
+
+        Ok(Action::requeue(self.context.config.requeue_interval()))
+    }
+
+    async fn delete_workers(&self, workers: &[Worker]) -> Result<Action> {
+        for worker in workers {
+            worker.delete(self.context.clone()).await?;
+        }
 
-        worker.create(self.context.clone()).await?;
+        Ok(Action::requeue(self.context.config.requeue_interval()))
+    }
 
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+    async fn replace_outdated(&self, create: &[Worker], delete: &[Worker]) -> Result<Action> {
+        self.create_workers(create).await?;
+        self.delete_workers(delete).await
     }
 
     pub async fn run(&self) -> Result<Action> {
-        match self.task {
-            Tasks::CreateWorker => self.create_worker().await,
+        match &self.task {
+            Tasks::CreateWorkers(workers) => self.create_workers(workers).await,
+            Tasks::DeleteWorkers(workers) => self.delete_workers(workers).await,
+            Tasks::ReplaceOutdated { create, delete } => {
+                self.replace_outdated(create, delete).await
+            }
+        }
+    }
+}
+
+/// Computes the next step of a rolling update of the worker pods.
+///
+/// Returns the number of surge workers to create and the outdated pods to delete.
+/// Outdated pods that are not ready can always be deleted, ready ones only as long
+/// as at least `desired - maxUnavailable` workers stay ready.
+fn rolling_update_step(
+    pods: &[Pod],
+    desired: usize,
+    rolling_update: &RollingUpdate,
+    pod_spec: &str,
+) -> (usize, Vec<Pod>) {
+    let outdated = pods
+        .iter()
+        .filter(|pod| is_outdated(pod, pod_spec))
+        .cloned()
+        .collect::<Vec<_>>();
+    let surge = (desired + rolling_update.max_surge())
+        .saturating_sub(pods.len())
+        .min(outdated.len());
+
+    let ready = pods.iter().filter(|pod| is_ready(pod)).count();
+    let mut budget = ready.saturating_sub(desired.saturating_sub(rolling_update.max_unavailable()));
+    let outdated_count = outdated.len();
+    let victims = select_victims(outdated, outdated_count)
+        .into_iter()
+        .filter(|pod| {
+            if !is_ready(pod) {
+                true
+            } else if budget > 0 {
+                budget -= 1;
+                true
+            } else {
+                false
+            }
+        })
+        .collect();
+
+    (surge, victims)
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::{PodCondition, PodStatus};
+    use kube::{ResourceExt, api::ObjectMeta};
+
+    use super::*;
+
+    fn pod(name: &str, ready: bool, pod_spec: &str) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                annotations: Some(
+                    [("probelet.dev/podSpec".to_string(), pod_spec.to_string())].into(),
+                ),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                conditions: Some(vec![PodCondition {
+                    type_: "Ready".to_string(),
+                    status: if ready { "True" } else { "False" }.to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
         }
     }
+
+    fn names(pods: Vec<Pod>) -> Vec<String> {
+        pods.iter().map(|pod| pod.name_any()).collect()
+    }
+
+    #[test]
+    fn test_rolling_update_replaces_one_at_a_time() {
+        let pods = vec![
+            pod("a", true, "old"),
+            pod("b", true, "old"),
+            pod("c", true, "new"),
+        ];
+        let (surge, victims) = rolling_update_step(&pods, 3, &RollingUpdate::default(), "new");
+        assert_eq!(surge, 0);
+        assert_eq!(names(victims), vec!["b"]);
+
+        // the replacement is not ready yet, so nothing else can be deleted
+        let pods = vec![
+            pod("a", true, "old"),
+            pod("b", false, "new"),
+            pod("c", true, "new"),
+        ];
+        let (surge, victims) = rolling_update_step(&pods, 3, &RollingUpdate::default(), "new");
+        assert_eq!(surge, 0);
+        assert!(victims.is_empty());
+    }
+
+    #[test]
+    fn test_rolling_update_deletes_not_ready_outdated_workers() {
+        let pods = vec![
+            pod("a", false, "old"),
+            pod("b", false, "old"),
+            pod("c", true, "old"),
+        ];
+        let rolling_update = RollingUpdate {
+            max_unavailable: 0,
+            max_surge: 1,
+        };
+        let (surge, victims) = rolling_update_step(&pods, 3, &rolling_update, "new");
+        assert_eq!(surge, 1);
+        assert_eq!(names(victims), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_rolling_update_surges_before_deleting() {
+        let rolling_update = RollingUpdate {
+            max_unavailable: 0,
+            max_surge: 1,
+        };
+        let pods = vec![pod("a", true, "old"), pod("b", true, "old")];
+        let (surge, victims) = rolling_update_step(&pods, 2, &rolling_update, "new");
+        assert_eq!(surge, 1);
+        assert!(victims.is_empty());
+
+        let pods = vec![
+            pod("a", true, "old"),
+            pod("b", true, "old"),
+            pod("c", true, "new"),
+        ];
+        let (surge, victims) = rolling_update_step(&pods, 2, &rolling_update, "new");
+        assert_eq!(surge, 0);
+        assert_eq!(names(victims), vec!["b"]);
+    }
 }
@@ -0,0 +1,305 @@
+use chrono::Utc;
+use k8s_openapi::{api::core::v1::Pod, apimachinery::pkg::apis::meta::v1::Condition};
+use kube::ResourceExt;
+use tracing::warn;
+
+use crate::{
+    config::ControllerConfig,
+    status::{CONDITION_RECONCILE_ERROR, ReconcileStatus, ReconciledResource},
+    worker_group::{
+        WorkerGroup,
+        crd::{
+            WorkerGroupInstanceStatus, WorkerGroupReportedInstanceState, WorkerGroupStatus,
+            WorkerInstanceName,
+        },
+        worker::{Worker, is_outdated, is_ready},
+    },
+};
+
+/// Enough workers are ready to run probes
+pub const CONDITION_AVAILABLE: &str = "Available";
+/// Workers are being created, deleted or replaced
+pub const CONDITION_PROGRESSING: &str = "Progressing";
+/// Some workers are not ready
+pub const CONDITION_DEGRADED: &str = "Degraded";
+
+impl WorkerGroupReportedInstanceState {
+    /// Derives the reported state of an instance from its pod conditions
+    ///
+    /// `last_updated` is the last transition of the `Ready` condition, so it only
+    /// changes when the instance state does.
+    pub fn from_pod(pod: &Pod) -> Self {
+        let ready_condition = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.conditions.as_ref())
+            .and_then(|conditions| conditions.iter().find(|c| c.type_ == "Ready"));
+        let last_updated = ready_condition
+            .and_then(|condition| condition.last_transition_time.as_ref())
+            .or(pod.metadata.creation_timestamp.as_ref())
+            .map(|time| time.0)
+            .unwrap_or_else(Utc::now);
+
+        Self {
+            status: if is_ready(pod) {
+                WorkerGroupInstanceStatus::Ready
+            } else {
+                WorkerGroupInstanceStatus::NotReady
+            },
+            last_updated: last_updated.to_rfc3339(),
+        }
+    }
+}
+
+impl ReconcileStatus for WorkerGroupStatus {
+    const RESOURCE: &'static str = "worker group";
+
+    fn conditions_mut(&mut self) -> &mut Vec<Condition> {
+        &mut self.conditions
+    }
+}
+
+impl WorkerGroupStatus {
+    /// Computes the status of a `WorkerGroup` from its worker pods
+    pub fn from_pods(pods: &[Pod]) -> Self {
+        let mut status = Self::default();
+        for pod in pods {
+            let name = match WorkerInstanceName::try_from(pod.name_any()) {
+                Ok(name) => name,
+                Err(e) => {
+                    warn!("ignoring worker pod \"{}\": {e}", pod.name_any());
+                    continue;
+                }
+            };
+            let state = WorkerGroupReportedInstanceState::from_pod(pod);
+            if state.status == WorkerGroupInstanceStatus::Ready {
+                status.ready_instances += 1;
+            }
+            status.instances += 1;
+            status.instance_names.push(name.clone());
+            status.instances_reported_state.insert(name, state);
+        }
+        status.instance_names.sort();
+        status
+    }
+}
+
+impl WorkerGroup {
+    /// Computes the status of the `WorkerGroup` from its worker pods.
+    ///
+    /// The `ReconcileError` condition is carried over from the current status, it is only
+    /// changed once the outcome of the reconcile is known.
+    pub(crate) fn observed_status(
+        &self,
+        pods: &[Pod],
+        config: &ControllerConfig,
+        icmp: bool,
+    ) -> WorkerGroupStatus {
+        let mut status = WorkerGroupStatus::from_pods(pods);
+        let generation = self.metadata.generation;
+        status.observed_generation = generation;
+        status.conditions = self
+            .status
+            .as_ref()
+            .map(|status| status.conditions.clone())
+            .unwrap_or_default();
+
+        let desired = self.spec.replicas;
+        let min_available = desired
+            .saturating_sub(
+                i32::try_from(self.spec.rolling_update.max_unavailable()).unwrap_or(i32::MAX),
+            )
+            .max(0);
+        let available = status.ready_instances >= min_available;
+        status.set_condition(
+            CONDITION_AVAILABLE,
+            available,
+            if available {
+                "MinimumWorkersAvailable"
+            } else {
+                "MinimumWorkersUnavailable"
+            },
+            format!(
+                "{} of {desired} workers are ready, {min_available} required",
+                status.ready_instances
+            ),
+            generation,
+        );
+
+        let pod_spec = Worker::pod_spec_annotation(self, config, icmp);
+        let outdated = pods
+            .iter()
+            .filter(|pod| is_outdated(pod, &pod_spec))
+            .count();
+        let (progressing, reason, message) = if outdated > 0 {
+            (
+                true,
+                "RollingUpdate",
+                format!("{outdated} workers run an outdated pod spec"),
+            )
+        } else if status.instances < desired {
+            (
+                true,
+                "ScalingUp",
+                format!("Scaling up from {} to {desired} workers", status.instances),
+            )
+        } else if status.instances > desired {
+            (
+                true,
+                "ScalingDown",
+                format!(
+                    "Scaling down from {} to {desired} workers",
+                    status.instances
+                ),
+            )
+        } else {
+            (
+                false,
+                "WorkersUpToDate",
+                format!("All {desired} workers run the desired pod spec"),
+            )
+        };
+        status.set_condition(
+            CONDITION_PROGRESSING,
+            progressing,
+            reason,
+            message,
+            generation,
+        );
+
+        let not_ready = status.instances - status.ready_instances;
+        status.set_condition(
+            CONDITION_DEGRADED,
+            not_ready > 0,
+            if not_ready > 0 {
+                "WorkersNotReady"
+            } else {
+                "WorkersReady"
+            },
+            format!("{not_ready} of {} workers are not ready", status.instances),
+            generation,
+        );
+
+        if !status
+            .conditions
+            .iter()
+            .any(|condition| condition.type_ == CONDITION_RECONCILE_ERROR)
+        {
+            status.set_reconcile_error(None, generation);
+        }
+
+        status
+    }
+}
+
+impl ReconciledResource for WorkerGroup {}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_json_snapshot;
+    use k8s_openapi::{
+        api::core::v1::{PodCondition, PodStatus},
+        apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time},
+    };
+    use kube::Resource;
+
+    use super::*;
+
+    fn pod(name: &str, ready: bool, transitioned: i64) -> Pod {
+        Pod {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                conditions: Some(vec![PodCondition {
+                    type_: "Ready".to_string(),
+                    status: if ready { "True" } else { "False" }.to_string(),
+                    last_transition_time: Some(Time(
+                        chrono::DateTime::from_timestamp(transitioned, 0).unwrap(),
+                    )),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_status_from_pods() {
+        let status = WorkerGroupStatus::from_pods(&[
+            pod("test-1", false, 1_700_000_100),
+            pod("test-0", true, 1_700_000_000),
+            pod(&"a".repeat(64), true, 1_700_000_000),
+        ]);
+
+        assert_json_snapshot!(status);
+    }
+
+    #[test]
+    fn test_observed_status_conditions() {
+        let mut worker_group = WorkerGroup::new(
+            "test",
+            crate::worker_group::crd::WorkerGroupSpec {
+                replicas: 2,
+                image: Some("test".to_string()),
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        );
+        worker_group.meta_mut().generation = Some(3);
+        let pod_spec =
+            Worker::pod_spec_annotation(&worker_group, &ControllerConfig::default(), false);
+        let with_spec = |mut pod: Pod| {
+            pod.annotations_mut()
+                .insert("probelet.dev/podSpec".to_string(), pod_spec.clone());
+            pod
+        };
+
+        let status = worker_group.observed_status(
+            &[
+                with_spec(pod("test-0", true, 1_700_000_000)),
+                with_spec(pod("test-1", false, 1_700_000_000)),
+            ],
+            &ControllerConfig::default(),
+            false,
+        );
+        let condition = |type_: &str| {
+            let condition = status
+                .conditions
+                .iter()
+                .find(|condition| condition.type_ == type_)
+                .unwrap();
+            (condition.status.as_str(), condition.reason.as_str())
+        };
+
+        assert_eq!(status.observed_generation, Some(3));
+        assert_eq!(
+            condition(CONDITION_AVAILABLE),
+            ("True", "MinimumWorkersAvailable")
+        );
+        assert_eq!(
+            condition(CONDITION_PROGRESSING),
+            ("False", "WorkersUpToDate")
+        );
+        assert_eq!(condition(CONDITION_DEGRADED), ("True", "WorkersNotReady"));
+        assert_eq!(
+            condition(CONDITION_RECONCILE_ERROR),
+            ("False", "ReconcileSucceeded")
+        );
+    }
+
+    #[test]
+    fn test_set_condition_keeps_transition_time() {
+        let mut status = WorkerGroupStatus::default();
+        status.set_condition(CONDITION_DEGRADED, true, "A", String::new(), None);
+        let first = status.conditions[0].last_transition_time.clone();
+
+        status.set_condition(CONDITION_DEGRADED, true, "B", String::new(), Some(1));
+        assert_eq!(status.conditions.len(), 1);
+        assert_eq!(status.conditions[0].last_transition_time, first);
+        assert_eq!(status.conditions[0].reason, "B");
+    }
+}
@@ -1,43 +1,105 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::BTreeSet, sync::Arc};
 
-use k8s_openapi::api::core::v1::{Container, Pod, PodSpec};
+use k8s_openapi::{
+    api::core::v1::{
+        Container, ContainerPort, DownwardAPIVolumeFile, DownwardAPIVolumeSource, EnvVar,
+        EnvVarSource, HTTPGetAction, ObjectFieldSelector, ObjectReference, Pod, PodSpec, Probe,
+        Sysctl, Volume, VolumeMount,
+    },
+    apimachinery::pkg::util::intstr::IntOrString,
+};
 use kube::{
     Api, Resource, ResourceExt,
-    api::{ObjectMeta, PostParams},
+    api::{DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PostParams},
     runtime::{
         controller::Action,
         events::{Event, EventType},
     },
 };
+use serde_json::json;
 use snafu::ResultExt;
 
 use crate::{
     Context,
-    worker_group::{WorkerGroup, error::KubeSnafu, reconcile::EventReason},
+    config::ControllerConfig,
+    error::KubeSnafu,
+    worker_group::{
+        WorkerGroup, crd::WorkerInstanceName, error::InvalidWorkerNameSnafu, reconcile::EventReason,
+    },
 };
 
-use super::Result;
+use super::{
+    DRAINING_ANNOTATION, Result, SHARD_ANNOTATION, WORKER_GROUP_NAME_LABEL, WORKER_NAME_LABEL,
+};
+
+/// Annotation holding the serialized `PodSpec` a worker was created from
+const POD_SPEC_ANNOTATION: &str = "probelet.dev/podSpec";
+
+/// Where the worker binary is installed in the worker image
+const WORKER_COMMAND: &str = "/app/worker";
+
+/// Port of the health and metrics server of the workers
+const WORKER_PORT: i32 = 8080;
 
-const WORKER_GROUP_DEFAULT_DELETION_GRACE_PERIOD_SECONDS: i64 = 30; // 30 seconds
+/// Directory where the downward API exposes the annotations of the worker pod
+const PODINFO_PATH: &str = "/etc/probelet/podinfo";
+
+/// Sysctl granting groups the right to open datagram ICMP sockets
+const PING_GROUP_RANGE_SYSCTL: &str = "net.ipv4.ping_group_range";
+
+/// Every group of the pod, whatever the user the worker runs as
+const PING_GROUP_RANGE_ALL: &str = "0 2147483647";
 
 #[derive(Debug, Clone)]
 pub struct Worker {
     pub name: String,
-    pub image: String,
+    /// The position of the worker in its group, `None` for pods created before ordinals
+    pub ordinal: Option<u32>,
     pub worker_group: Arc<WorkerGroup>,
 }
 
 impl Worker {
-    pub fn new(name: String, image: String, worker_group: Arc<WorkerGroup>) -> Self {
+    /// Creates the worker at `ordinal`, named `<group>-<ordinal>`
+    pub fn new(ordinal: u32, worker_group: Arc<WorkerGroup>) -> Result<Self> {
+        let name = WorkerInstanceName::try_from(format!("{}-{ordinal}", worker_group.name_any()))
+            .map_err(|message| InvalidWorkerNameSnafu { message }.build())?;
+
+        Ok(Self {
+            name: name.as_string(),
+            ordinal: Some(ordinal),
+            worker_group,
+        })
+    }
+
+    /// Builds a `Worker` from one of the pods owned by the `WorkerGroup`
+    pub fn from_pod(pod: &Pod, worker_group: Arc<WorkerGroup>) -> Self {
         Self {
-            name,
-            image,
+            name: pod.name_any(),
+            ordinal: ordinal(pod),
             worker_group,
         }
     }
 
-    pub async fn create(&self, context: Arc<Context>) -> Result<Action> {
-        let pod = self.pod();
+    /// Lists the worker pods of the `WorkerGroup`, including the ones already being deleted
+    pub async fn list(worker_group: &WorkerGroup, context: Arc<Context>) -> Result<Vec<Pod>> {
+        let client = context.client.clone();
+        let api = Api::<Pod>::namespaced(client, &worker_group.namespace().unwrap());
+        let selector = format!("{WORKER_GROUP_NAME_LABEL}={}", worker_group.name_any());
+        let pods = api
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .context(KubeSnafu {
+                message: format!(
+                    "Failed to list workers of worker group {}",
+                    worker_group.name_any()
+                ),
+            })?;
+
+        Ok(pods.items)
+    }
+
+    pub async fn create(&self, icmp: bool, context: Arc<Context>) -> Result<Action> {
+        let pod = self.pod(&context.config, icmp);
         let client = context.client.clone();
         let api = Api::<Pod>::namespaced(client, &self.worker_group.namespace().unwrap());
         api.create(&PostParams::default(), &pod)
@@ -62,38 +124,256 @@ impl Worker {
                 message: format!("Failed to publish event for worker {}", self.name),
             })?;
 
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+        Ok(Action::requeue(context.config.requeue_interval()))
+    }
+
+    pub async fn delete(&self, context: Arc<Context>) -> Result<Action> {
+        let client = context.client.clone();
+        let api = Api::<Pod>::namespaced(client, &self.worker_group.namespace().unwrap());
+        let params = DeleteParams {
+            grace_period_seconds: Some(context.config.deletion_grace_period_seconds),
+            ..Default::default()
+        };
+        api.delete(&self.name, &params).await.context(KubeSnafu {
+            message: format!("Failed to delete worker pod {}", self.name),
+        })?;
+
+        let event = Event {
+            type_: EventType::Normal,
+            reason: EventReason::WorkerDeleted.to_string(),
+            note: Some("Worker Deleted".to_string()),
+            secondary: Some(self.worker_group.object_ref(&())),
+            action: EventReason::WorkerDeleted.to_string(),
+        };
+
+        let recorder = context.recorder.clone();
+        recorder
+            .publish(&event, &self.object_ref())
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to publish event for worker {}", self.name),
+            })?;
+
+        Ok(Action::requeue(context.config.requeue_interval()))
+    }
+
+    /// Marks the worker as draining so it stops accepting probe assignments
+    pub async fn drain(&self, context: Arc<Context>) -> Result<()> {
+        let client = context.client.clone();
+        let api = Api::<Pod>::namespaced(client, &self.worker_group.namespace().unwrap());
+        let patch = json!({ "metadata": { "annotations": { DRAINING_ANNOTATION: "true" } } });
+        api.patch(&self.name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to drain worker pod {}", self.name),
+            })?;
+
+        let event = Event {
+            type_: EventType::Normal,
+            reason: EventReason::WorkerDraining.to_string(),
+            note: Some("Worker Draining".to_string()),
+            secondary: Some(self.worker_group.object_ref(&())),
+            action: EventReason::WorkerDraining.to_string(),
+        };
+
+        let recorder = context.recorder.clone();
+        recorder
+            .publish(&event, &self.object_ref())
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to publish event for worker {}", self.name),
+            })?;
+
+        Ok(())
+    }
+
+    /// Sets the `probelet.dev/shard` annotation of the worker pod, or removes it
+    pub async fn set_shard(&self, shard: Option<&str>, context: Arc<Context>) -> Result<()> {
+        let client = context.client.clone();
+        let api = Api::<Pod>::namespaced(client, &self.worker_group.namespace().unwrap());
+        let patch = json!({ "metadata": { "annotations": { SHARD_ANNOTATION: shard } } });
+        api.patch(&self.name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context(KubeSnafu {
+                message: format!("Failed to set the shard of worker pod {}", self.name),
+            })?;
+        Ok(())
+    }
+
+    /// Reference to the worker pod, usable once the pod is gone from the API
+    fn object_ref(&self) -> ObjectReference {
+        ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: Some(self.name.clone()),
+            namespace: self.worker_group.namespace(),
+            ..Default::default()
+        }
     }
 
-    pub fn pod(&self) -> Pod {
-        let spec = PodSpec {
+    /// The pod spec every worker of the `WorkerGroup` should currently run.
+    ///
+    /// Workers run `/app/worker` from the image of the `WorkerGroup`, or the worker image
+    /// of `config` when it does not set one, and learn their identity through the downward
+    /// API. They get the deletion grace period of `config` to stop.
+    /// `icmp` sets the `net.ipv4.ping_group_range` sysctl of the pod so that its nonroot
+    /// workers can open datagram ICMP sockets, unless the template already sets it.
+    pub fn pod_spec(worker_group: &WorkerGroup, config: &ControllerConfig, icmp: bool) -> PodSpec {
+        let template = worker_group.spec.template.clone().unwrap_or_default();
+
+        let mut pod_security_context = template.pod_security_context;
+        if icmp {
+            let sysctls = pod_security_context
+                .get_or_insert_with(Default::default)
+                .sysctls
+                .get_or_insert_with(Vec::new);
+            if !sysctls
+                .iter()
+                .any(|sysctl| sysctl.name == PING_GROUP_RANGE_SYSCTL)
+            {
+                sysctls.push(Sysctl {
+                    name: PING_GROUP_RANGE_SYSCTL.to_string(),
+                    value: PING_GROUP_RANGE_ALL.to_string(),
+                });
+            }
+        }
+
+        let field_env = |name: &str, field_path: &str| EnvVar {
+            name: name.to_string(),
+            value_from: Some(EnvVarSource {
+                field_ref: Some(ObjectFieldSelector {
+                    field_path: field_path.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut env = vec![
+            field_env(
+                "WORKER_NAME",
+                &format!("metadata.labels['{WORKER_NAME_LABEL}']"),
+            ),
+            field_env(
+                "WORKER_GROUP_NAME",
+                &format!("metadata.labels['{WORKER_GROUP_NAME_LABEL}']"),
+            ),
+            field_env("POD_NAME", "metadata.name"),
+            field_env("POD_NAMESPACE", "metadata.namespace"),
+            field_env("NODE_NAME", "spec.nodeName"),
+        ];
+        env.extend(template.env);
+
+        let http_probe = |path: &str| Probe {
+            http_get: Some(HTTPGetAction {
+                path: Some(path.to_string()),
+                port: IntOrString::String("http".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        PodSpec {
             containers: vec![Container {
                 name: "worker".to_string(),
-                image: Some(self.image.clone()),
-                command: Some(vec!["/bin/sh".to_string(), "-c".to_string()]),
-                args: Some(vec!["sleep infinity".to_string()]),
+                image: Some(
+                    worker_group
+                        .spec
+                        .image
+                        .clone()
+                        .unwrap_or_else(|| config.worker_image.clone()),
+                ),
+                command: Some(vec![WORKER_COMMAND.to_string()]),
+                env: Some(env),
+                ports: Some(vec![ContainerPort {
+                    name: Some("http".to_string()),
+                    container_port: WORKER_PORT,
+                    ..Default::default()
+                }]),
+                liveness_probe: Some(http_probe("/healthz")),
+                readiness_probe: Some(http_probe("/readyz")),
+                volume_mounts: Some(vec![VolumeMount {
+                    name: "podinfo".to_string(),
+                    mount_path: PODINFO_PATH.to_string(),
+                    read_only: Some(true),
+                    ..Default::default()
+                }]),
+                resources: template.resources,
+                security_context: template.security_context,
                 ..Default::default()
             }],
+            volumes: Some(vec![Volume {
+                name: "podinfo".to_string(),
+                downward_api: Some(DownwardAPIVolumeSource {
+                    items: Some(vec![DownwardAPIVolumeFile {
+                        path: "annotations".to_string(),
+                        field_ref: Some(ObjectFieldSelector {
+                            field_path: "metadata.annotations".to_string(),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
             restart_policy: Some("Always".to_string()),
+            security_context: pod_security_context,
+            node_selector: Some(template.node_selector)
+                .filter(|node_selector| !node_selector.is_empty()),
+            tolerations: Some(template.tolerations).filter(|tolerations| !tolerations.is_empty()),
+            affinity: template.affinity,
+            service_account_name: Some(
+                template
+                    .service_account_name
+                    .unwrap_or_else(|| worker_group.worker_service_account_name()),
+            ),
+            image_pull_secrets: Some(template.image_pull_secrets)
+                .filter(|image_pull_secrets| !image_pull_secrets.is_empty()),
+            termination_grace_period_seconds: Some(config.deletion_grace_period_seconds.into()),
             ..Default::default()
-        };
+        }
+    }
+
+    /// The serialized pod spec stored in the `probelet.dev/podSpec` annotation
+    pub fn pod_spec_annotation(
+        worker_group: &WorkerGroup,
+        config: &ControllerConfig,
+        icmp: bool,
+    ) -> String {
+        serde_json::to_string(&Self::pod_spec(worker_group, config, icmp)).unwrap()
+    }
+
+    pub fn pod(&self, config: &ControllerConfig, icmp: bool) -> Pod {
+        let spec = Self::pod_spec(&self.worker_group, config, icmp);
+        let template = self.worker_group.spec.template.as_ref();
 
-        let mut annotations = self.worker_group.default_annotations();
+        // operator labels and annotations take precedence over the template ones
+        let mut annotations = template
+            .map(|template| template.annotations.clone())
+            .unwrap_or_default();
+        annotations.extend(self.worker_group.default_annotations());
         annotations.insert(
-            "probelet.dev/podSpec".to_string(),
+            POD_SPEC_ANNOTATION.to_string(),
             serde_json::to_string(&spec).unwrap(),
         );
-        let mut labels = self.worker_group.default_labels();
-        labels.insert("probelet.dev/workerName".to_string(), self.name.clone());
+        let mut labels = template
+            .map(|template| template.labels.clone())
+            .unwrap_or_default();
+        labels.extend(self.worker_group.default_labels());
+        labels.insert(WORKER_NAME_LABEL.to_string(), self.name.clone());
+        if let Some(ordinal) = self.ordinal {
+            labels.insert(
+                "probelet.dev/workerOrdinal".to_string(),
+                ordinal.to_string(),
+            );
+        }
 
         Pod {
             metadata: ObjectMeta {
                 name: Some(self.name.clone()),
                 owner_references: Some(vec![self.worker_group.owner_ref(&()).unwrap()]),
                 annotations: Some(annotations),
-                deletion_grace_period_seconds: Some(
-                    WORKER_GROUP_DEFAULT_DELETION_GRACE_PERIOD_SECONDS,
-                ),
                 labels: Some(labels),
                 ..Default::default()
             },
@@ -103,6 +383,98 @@ impl Worker {
     }
 }
 
+/// Whether the pod reports the `Ready` condition
+pub fn is_ready(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.conditions.as_ref())
+        .into_iter()
+        .flatten()
+        .any(|condition| condition.type_ == "Ready" && condition.status == "True")
+}
+
+/// The ordinal of a worker pod, read from its `probelet.dev/workerOrdinal` label
+pub fn ordinal(pod: &Pod) -> Option<u32> {
+    pod.labels()
+        .get("probelet.dev/workerOrdinal")
+        .and_then(|ordinal| ordinal.parse().ok())
+}
+
+/// Finds the `count` lowest ordinals not used by any of the pods,
+/// so gaps left by deleted workers are filled first.
+///
+/// The ordinals of the pods being deleted are still used: their names are only free
+/// once they are gone.
+pub fn missing_ordinals(pods: &[Pod], count: usize) -> Vec<u32> {
+    let used = pods.iter().filter_map(ordinal).collect::<BTreeSet<_>>();
+    (0..)
+        .filter(|ordinal| !used.contains(ordinal))
+        .take(count)
+        .collect()
+}
+
+/// Whether the pod is being deleted, it no longer counts as a worker of its group
+pub fn is_terminating(pod: &Pod) -> bool {
+    pod.metadata.deletion_timestamp.is_some()
+}
+
+/// Whether the worker was asked to stop accepting probe assignments
+pub fn is_draining(pod: &Pod) -> bool {
+    pod.annotations()
+        .get(DRAINING_ANNOTATION)
+        .is_some_and(|draining| draining == "true")
+}
+
+/// The shard each pod should run, `<index>/<count>` over the ready workers that are not
+/// draining in ordinal order, none for the other pods.
+pub fn shards(pods: &[Pod]) -> Vec<(&Pod, Option<String>)> {
+    let (mut active, idle): (Vec<_>, Vec<_>) = pods
+        .iter()
+        .partition(|pod| is_ready(pod) && !is_draining(pod) && !is_terminating(pod));
+    active.sort_by_key(|pod| (ordinal(pod), pod.name_any()));
+    let count = active.len();
+    active
+        .into_iter()
+        .enumerate()
+        .map(|(index, pod)| (pod, Some(format!("{index}/{count}"))))
+        .chain(idle.into_iter().map(|pod| (pod, None)))
+        .collect()
+}
+
+/// Whether a container of the pod is waiting to restart after crashing repeatedly
+pub fn is_crash_looping(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|status| status.container_statuses.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|status| status.state.as_ref()?.waiting.as_ref()?.reason.as_deref())
+        .any(|reason| reason == "CrashLoopBackOff")
+}
+
+/// Whether the pod was created from a different spec than the `desired` annotation
+pub fn is_outdated(pod: &Pod, desired: &str) -> bool {
+    pod.annotations()
+        .get(POD_SPEC_ANNOTATION)
+        .map(String::as_str)
+        != Some(desired)
+}
+
+/// Picks `count` pods to delete when scaling down.
+///
+/// Pods that are not ready go first, then the most recently created ones.
+/// Ties are broken by name so the choice is stable between reconciles.
+pub fn select_victims(mut pods: Vec<Pod>, count: usize) -> Vec<Pod> {
+    pods.sort_by(|a, b| {
+        is_ready(a)
+            .cmp(&is_ready(b))
+            .then_with(|| b.creation_timestamp().cmp(&a.creation_timestamp()))
+            .then_with(|| b.name_any().cmp(&a.name_any()))
+    });
+    pods.truncate(count);
+    pods
+}
+
 #[cfg(test)]
 mod tests {
     use insta::assert_snapshot;
@@ -111,11 +483,17 @@ mod tests {
 
     use super::*;
 
+    fn config() -> ControllerConfig {
+        ControllerConfig {
+            worker_image: "probelet/operator:test".to_string(),
+            ..Default::default()
+        }
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_pod() {
         let worker = Worker::new(
-            "test".to_string(),
-            "test".to_string(),
+            0,
             Arc::new(WorkerGroup {
                 metadata: ObjectMeta {
                     name: Some("test".to_string()),
@@ -124,15 +502,274 @@ mod tests {
                 },
                 spec: WorkerGroupSpec {
                     replicas: 1,
-                    image: "test".to_string(),
+                    image: None,
+                    rolling_update: Default::default(),
+                    template: None,
+                    drain_timeout_seconds: 60,
                 },
                 status: None,
             }),
-        );
+        )
+        .unwrap();
 
-        let pod = worker.pod();
+        let pod = worker.pod(&config(), false);
         let pod_json = serde_json::to_string_pretty(&pod).unwrap();
 
         assert_snapshot!(pod_json);
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_pod_with_template() {
+        use k8s_openapi::{
+            api::core::v1::{
+                Capabilities, LocalObjectReference, ResourceRequirements, SecurityContext,
+            },
+            apimachinery::pkg::api::resource::Quantity,
+        };
+
+        use crate::worker_group::crd::WorkerPodTemplate;
+
+        let template = WorkerPodTemplate {
+            labels: [
+                ("team".to_string(), "sre".to_string()),
+                (
+                    "probelet.dev/workerGroupName".to_string(),
+                    "other".to_string(),
+                ),
+            ]
+            .into(),
+            resources: Some(ResourceRequirements {
+                limits: Some([("memory".to_string(), Quantity("64Mi".to_string()))].into()),
+                ..Default::default()
+            }),
+            security_context: Some(SecurityContext {
+                run_as_non_root: Some(true),
+                allow_privilege_escalation: Some(false),
+                capabilities: Some(Capabilities {
+                    drop: Some(vec!["ALL".to_string()]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            service_account_name: Some("probelet-worker".to_string()),
+            image_pull_secrets: vec![LocalObjectReference {
+                name: "registry".to_string(),
+            }],
+            ..Default::default()
+        };
+        let worker = Worker::new(
+            1,
+            Arc::new(WorkerGroup {
+                metadata: ObjectMeta {
+                    name: Some("test".to_string()),
+                    uid: Some("test".to_string()),
+                    ..Default::default()
+                },
+                spec: WorkerGroupSpec {
+                    replicas: 2,
+                    image: Some("registry.example.com/probelet:test".to_string()),
+                    rolling_update: Default::default(),
+                    template: Some(template),
+                    drain_timeout_seconds: 60,
+                },
+                status: None,
+            }),
+        )
+        .unwrap();
+
+        let pod = worker.pod(&config(), false);
+        let pod_json = serde_json::to_string_pretty(&pod).unwrap();
+
+        assert_snapshot!(pod_json);
+    }
+
+    #[test]
+    fn test_pod_spec_icmp() {
+        use k8s_openapi::api::core::v1::PodSecurityContext;
+
+        use crate::worker_group::crd::WorkerPodTemplate;
+
+        let mut worker_group = WorkerGroup::new(
+            "test",
+            WorkerGroupSpec {
+                replicas: 1,
+                image: None,
+                rolling_update: Default::default(),
+                template: Some(WorkerPodTemplate {
+                    pod_security_context: Some(PodSecurityContext {
+                        run_as_non_root: Some(true),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                drain_timeout_seconds: 60,
+            },
+        );
+        let sysctls = |worker_group: &WorkerGroup, icmp: bool| {
+            Worker::pod_spec(worker_group, &config(), icmp)
+                .security_context
+                .and_then(|security_context| security_context.sysctls)
+        };
+        let ping_group_range = |value: &str| Sysctl {
+            name: "net.ipv4.ping_group_range".to_string(),
+            value: value.to_string(),
+        };
+
+        assert_eq!(sysctls(&worker_group, false), None);
+        assert_eq!(
+            sysctls(&worker_group, true),
+            Some(vec![ping_group_range("0 2147483647")])
+        );
+        assert_eq!(
+            Worker::pod_spec(&worker_group, &config(), true)
+                .security_context
+                .and_then(|security_context| security_context.run_as_non_root),
+            Some(true)
+        );
+
+        // the range of the template is kept
+        worker_group.spec.template = Some(WorkerPodTemplate {
+            pod_security_context: Some(PodSecurityContext {
+                sysctls: Some(vec![ping_group_range("1000 1000")]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            sysctls(&worker_group, true),
+            Some(vec![ping_group_range("1000 1000")])
+        );
+
+        worker_group.spec.template = None;
+        assert_eq!(
+            sysctls(&worker_group, true),
+            Some(vec![ping_group_range("0 2147483647")])
+        );
+        assert_ne!(
+            Worker::pod_spec_annotation(&worker_group, &config(), false),
+            Worker::pod_spec_annotation(&worker_group, &config(), true)
+        );
+    }
+
+    fn pod(name: &str, created: i64, ready: bool) -> Pod {
+        use k8s_openapi::{
+            api::core::v1::{PodCondition, PodStatus},
+            apimachinery::pkg::apis::meta::v1::Time,
+            chrono::DateTime,
+        };
+
+        Pod {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                creation_timestamp: Some(Time(DateTime::from_timestamp(created, 0).unwrap())),
+                ..Default::default()
+            },
+            status: Some(PodStatus {
+                conditions: Some(vec![PodCondition {
+                    type_: "Ready".to_string(),
+                    status: if ready { "True" } else { "False" }.to_string(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_missing_ordinals() {
+        let with_ordinal = |ordinal: u32| {
+            let mut pod = pod(&format!("test-{ordinal}"), 0, true);
+            pod.labels_mut().insert(
+                "probelet.dev/workerOrdinal".to_string(),
+                ordinal.to_string(),
+            );
+            pod
+        };
+
+        let mut pods = vec![with_ordinal(0), with_ordinal(2), with_ordinal(4)];
+        assert_eq!(missing_ordinals(&pods, 3), vec![1, 3, 5]);
+        assert_eq!(missing_ordinals(&[], 2), vec![0, 1]);
+        assert_eq!(missing_ordinals(&pods, 0), Vec::<u32>::new());
+
+        // the name of a terminating pod is taken until it is gone
+        let mut terminating = with_ordinal(1);
+        terminating.metadata.deletion_timestamp = terminating.metadata.creation_timestamp.clone();
+        pods.push(terminating);
+        assert_eq!(missing_ordinals(&pods, 2), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_shards() {
+        let with_ordinal = |ordinal: u32, ready: bool| {
+            let mut pod = pod(&format!("test-{ordinal}"), 0, ready);
+            pod.labels_mut().insert(
+                "probelet.dev/workerOrdinal".to_string(),
+                ordinal.to_string(),
+            );
+            pod
+        };
+        let mut draining = with_ordinal(1, true);
+        draining
+            .annotations_mut()
+            .insert(DRAINING_ANNOTATION.to_string(), "true".to_string());
+        let pods = vec![
+            with_ordinal(10, true),
+            draining,
+            with_ordinal(2, true),
+            with_ordinal(3, false),
+            with_ordinal(0, true),
+        ];
+
+        let shards = shards(&pods)
+            .into_iter()
+            .map(|(pod, shard)| (pod.name_any(), shard))
+            .collect::<Vec<_>>();
+        let shard = |name: &str, shard: Option<&str>| (name.to_string(), shard.map(String::from));
+        assert_eq!(
+            shards,
+            vec![
+                shard("test-0", Some("0/3")),
+                shard("test-2", Some("1/3")),
+                shard("test-10", Some("2/3")),
+                shard("test-1", None),
+                shard("test-3", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_names() {
+        let worker_group = Arc::new(WorkerGroup::new(
+            &"a".repeat(62),
+            WorkerGroupSpec {
+                replicas: 1,
+                image: Some("test".to_string()),
+                rolling_update: Default::default(),
+                template: None,
+                drain_timeout_seconds: 60,
+            },
+        ));
+
+        assert!(Worker::new(1, worker_group.clone()).is_err());
+    }
+
+    #[test]
+    fn test_select_victims() {
+        let pods = vec![
+            pod("oldest-ready", 1, true),
+            pod("newest-ready", 3, true),
+            pod("old-not-ready", 2, false),
+            pod("new-not-ready", 4, false),
+        ];
+
+        let names = |pods: Vec<Pod>| pods.iter().map(|pod| pod.name_any()).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(select_victims(pods.clone(), 3)),
+            vec!["new-not-ready", "old-not-ready", "newest-ready"]
+        );
+        assert_eq!(names(select_victims(pods.clone(), 0)), Vec::<String>::new());
+        assert_eq!(names(select_victims(pods, 10)).len(), 4);
+    }
 }